@@ -0,0 +1,48 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use kuragemu_riscv::processor::Processor;
+
+// 1000 iterations of a three-instruction loop
+const TIGHT_LOOP: [u32; 6] = [
+    0x00000293, // addi t0, zero, 0
+    0x3e800313, // addi t1, zero, 1000
+    0x00128293, // loop: addi t0, t0, 1
+    0x0053c3b3, // xor t2, t2, t0
+    0xfe629ce3, // bne t0, t1, loop
+    0x0000006f, // jal zero, 0
+];
+const STEPS: u64 = 2 + 3 * 1000 + 1;
+
+fn run(decode_cache: bool) {
+    let mut cpu = Processor::new();
+    cpu.set_trace(false);
+    cpu.set_decode_cache(decode_cache);
+    cpu.load(cpu.pc(), &TIGHT_LOOP).unwrap();
+
+    while !cpu.is_halt() {
+        if cpu.step().is_err() {
+            panic!("tight loop faulted");
+        }
+    }
+}
+
+fn run_blocks() {
+    let mut cpu = Processor::new();
+    cpu.set_trace(false);
+    cpu.load(cpu.pc(), &TIGHT_LOOP).unwrap();
+
+    if cpu.run_blocks(u64::MAX).is_err() {
+        panic!("tight loop faulted");
+    }
+}
+
+fn bench_tight_loop(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tight_loop");
+    group.throughput(Throughput::Elements(STEPS));
+    group.bench_function("decode_every_step", |b| b.iter(|| run(false)));
+    group.bench_function("decode_cache", |b| b.iter(|| run(true)));
+    group.bench_function("block_engine", |b| b.iter(run_blocks));
+    group.finish();
+}
+
+criterion_group!(benches, bench_tight_loop);
+criterion_main!(benches);
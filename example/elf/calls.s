@@ -0,0 +1,54 @@
+# Sums the squares of 1..=10 with a call per term, storing 385 at address 0.
+# Built with:
+#   llvm-mc -triple=riscv32 -filetype=obj -g -fdebug-compilation-dir=. calls.s -o calls.o
+#   rust-lld -flavor gnu -m elf32lriscv -N --image-base=0 -Ttext=0x2000 -e _start calls.o -o calls.elf
+
+    .text
+    .globl _start
+_start:
+    li      sp, 0x40000
+    li      a0, 10
+    call    sum_squares
+    sw      a0, 0(zero)
+halt:
+    j       halt
+
+    .globl sum_squares
+    .type   sum_squares, @function
+sum_squares:
+    addi    sp, sp, -16
+    sw      ra, 12(sp)
+    sw      s0, 8(sp)
+    sw      s1, 4(sp)
+    mv      s0, a0
+    li      s1, 0
+1:
+    beqz    s0, 2f
+    mv      a0, s0
+    call    square
+    add     s1, s1, a0
+    addi    s0, s0, -1
+    j       1b
+2:
+    mv      a0, s1
+    lw      ra, 12(sp)
+    lw      s0, 8(sp)
+    lw      s1, 4(sp)
+    addi    sp, sp, 16
+    ret
+    .size   sum_squares, . - sum_squares
+
+    .globl square
+    .type   square, @function
+square:
+    li      t0, 0
+    mv      t1, a0
+3:
+    beqz    t1, 4f
+    add     t0, t0, a0
+    addi    t1, t1, -1
+    j       3b
+4:
+    mv      a0, t0
+    ret
+    .size   square, . - square
@@ -0,0 +1,16 @@
+#![no_main]
+
+use kuragemu_riscv::processor::{
+    decoder::{decode, instruction::RiscvInstruction},
+    xlen::Xlen,
+};
+use libfuzzer_sys::fuzz_target;
+
+// decoding any word, and printing what it decodes to, must not panic
+fuzz_target!(|raw: u32| {
+    for xlen in [Xlen::X32, Xlen::X64] {
+        if let Ok(inst) = decode(raw, &xlen) {
+            inst.assembly();
+        }
+    }
+});
@@ -0,0 +1,558 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Result};
+use serde::Deserialize;
+
+/// Which line of a full set makes room for a new one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Replacement {
+    #[default]
+    Lru,
+    Fifo,
+    Random,
+}
+
+impl Display for Replacement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Replacement::Lru => "lru",
+            Replacement::Fifo => "fifo",
+            Replacement::Random => "random",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// When a store reaches the next level.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WritePolicy {
+    /// Stores allocate the line and mark it dirty; it is written out when evicted.
+    #[default]
+    WriteBack,
+    /// Stores go through to the next level and do not allocate on a miss.
+    WriteThrough,
+}
+
+impl Display for WritePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WritePolicy::WriteBack => "write-back",
+            WritePolicy::WriteThrough => "write-through",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Geometry and policies of one cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    // bytes
+    pub size: u64,
+    pub associativity: u64,
+    pub line_size: u64,
+    #[serde(default)]
+    pub replacement: Replacement,
+    #[serde(default)]
+    pub write_policy: WritePolicy,
+    // cycles for a lookup, hit or miss
+    #[serde(default = "default_latency")]
+    pub latency: u64,
+}
+
+fn default_latency() -> u64 {
+    1
+}
+
+fn default_memory_latency() -> u64 {
+    100
+}
+
+impl CacheConfig {
+    pub fn new(size: u64, associativity: u64, line_size: u64) -> Self {
+        Self {
+            size,
+            associativity,
+            line_size,
+            replacement: Replacement::default(),
+            write_policy: WritePolicy::default(),
+            latency: default_latency(),
+        }
+    }
+
+    pub fn sets(&self) -> u64 {
+        self.size / (self.associativity * self.line_size)
+    }
+
+    pub fn validate(&self, name: &str) -> Result<()> {
+        ensure!(
+            self.line_size >= 4 && self.line_size.is_power_of_two(),
+            "{} line size must be a power of two of at least 4 bytes",
+            name
+        );
+        ensure!(
+            self.associativity > 0
+                && self
+                    .size
+                    .is_multiple_of(self.associativity * self.line_size),
+            "{} size must be a multiple of associativity times line size",
+            name
+        );
+        ensure!(
+            self.sets().is_power_of_two(),
+            "{} must have a power of two number of sets",
+            name
+        );
+        Ok(())
+    }
+}
+
+/// The caches in front of main memory, all optional.
+///
+/// In a machine file:
+///
+/// ```toml
+/// [cache]
+/// memory_latency = 100
+///
+/// [cache.l1d]
+/// size = 16384
+/// associativity = 4
+/// line_size = 64
+/// replacement = "lru"         # or "fifo", "random"
+/// write_policy = "write-back" # or "write-through"
+/// latency = 1
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HierarchyConfig {
+    pub l1i: Option<CacheConfig>,
+    pub l1d: Option<CacheConfig>,
+    pub l2: Option<CacheConfig>,
+    #[serde(default = "default_memory_latency")]
+    pub memory_latency: u64,
+}
+
+impl HierarchyConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (name, config) in [("l1i", &self.l1i), ("l1d", &self.l1d), ("l2", &self.l2)] {
+            if let Some(config) = config {
+                config.validate(name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for HierarchyConfig {
+    /// Split 16 KiB 4-way L1s with 64-byte lines over a 256 KiB 8-way L2.
+    fn default() -> Self {
+        Self {
+            l1i: Some(CacheConfig::new(0x4000, 4, 64)),
+            l1d: Some(CacheConfig::new(0x4000, 4, 64)),
+            l2: Some(CacheConfig {
+                latency: 10,
+                ..CacheConfig::new(0x40000, 8, 64)
+            }),
+            memory_latency: default_memory_latency(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub read_misses: u64,
+    pub write_misses: u64,
+    pub evictions: u64,
+    // dirty lines written to the next level
+    pub writebacks: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.accesses() - self.misses()
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        if self.accesses() == 0 {
+            0.0
+        } else {
+            self.hits() as f64 / self.accesses() as f64
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Line {
+    tag: u64,
+    dirty: bool,
+    // access stamps for LRU and FIFO
+    used: u64,
+    filled: u64,
+}
+
+/// What a lookup did to one cache.
+struct Lookup {
+    hit: bool,
+    // line address of an evicted dirty line
+    writeback: Option<u64>,
+}
+
+/// Tags of one set-associative cache. Data always comes from `MainMemory`,
+/// so the model only decides hits and misses and cannot change what the guest sees.
+pub struct Cache {
+    config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    clock: u64,
+    // xorshift state for random replacement, fixed so runs repeat
+    seed: u64,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            sets: vec![vec![]; config.sets() as usize],
+            clock: 0,
+            seed: 0x2545_F491_4F6C_DD1D,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    fn access(&mut self, address: u64, is_write: bool) -> Lookup {
+        self.clock += 1;
+        let line = address / self.config.line_size;
+        let sets = self.sets.len() as u64;
+        let (index, tag) = ((line % sets) as usize, line / sets);
+
+        if is_write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+
+        let write_back = self.config.write_policy == WritePolicy::WriteBack;
+        if let Some(way) = self.sets[index].iter_mut().find(|way| way.tag == tag) {
+            way.used = self.clock;
+            way.dirty |= is_write && write_back;
+            return Lookup {
+                hit: true,
+                writeback: None,
+            };
+        }
+
+        if is_write {
+            self.stats.write_misses += 1;
+        } else {
+            self.stats.read_misses += 1;
+        }
+        let miss = Lookup {
+            hit: false,
+            writeback: None,
+        };
+        if is_write && !write_back {
+            return miss;
+        }
+
+        let new = Line {
+            tag,
+            dirty: is_write,
+            used: self.clock,
+            filled: self.clock,
+        };
+        if (self.sets[index].len() as u64) < self.config.associativity {
+            self.sets[index].push(new);
+            return miss;
+        }
+
+        let victim = self.victim(index);
+        let old = std::mem::replace(&mut self.sets[index][victim], new);
+        self.stats.evictions += 1;
+        let writeback = old.dirty.then(|| {
+            self.stats.writebacks += 1;
+            (old.tag * sets + index as u64) * self.config.line_size
+        });
+        Lookup { writeback, ..miss }
+    }
+
+    fn victim(&mut self, index: usize) -> usize {
+        let set = &self.sets[index];
+        let oldest = |key: fn(&Line) -> u64| {
+            (0..set.len())
+                .min_by_key(|way| key(&set[*way]))
+                .unwrap_or(0)
+        };
+        match self.config.replacement {
+            Replacement::Lru => oldest(|line| line.used),
+            Replacement::Fifo => oldest(|line| line.filled),
+            Replacement::Random => {
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 7;
+                self.seed ^= self.seed << 17;
+                (self.seed % set.len() as u64) as usize
+            }
+        }
+    }
+}
+
+/// Split L1 caches over an optional shared L2 and main memory.
+///
+/// Each access returns the cycles it stalls the pipeline beyond the single
+/// cycle an IF or MEM stage takes anyway. Write-backs and write-through
+/// traffic are assumed to drain through a write buffer and cost nothing.
+pub struct CacheHierarchy {
+    l1i: Option<Cache>,
+    l1d: Option<Cache>,
+    l2: Option<Cache>,
+    memory_latency: u64,
+}
+
+impl CacheHierarchy {
+    pub fn new(config: &HierarchyConfig) -> Self {
+        Self {
+            l1i: config.l1i.map(Cache::new),
+            l1d: config.l1d.map(Cache::new),
+            l2: config.l2.map(Cache::new),
+            memory_latency: config.memory_latency,
+        }
+    }
+
+    pub fn l1i(&self) -> Option<&Cache> {
+        self.l1i.as_ref()
+    }
+
+    pub fn l1d(&self) -> Option<&Cache> {
+        self.l1d.as_ref()
+    }
+
+    pub fn l2(&self) -> Option<&Cache> {
+        self.l2.as_ref()
+    }
+
+    /// Fetches `size` bytes of instruction at `address`.
+    pub fn fetch(&mut self, address: u64, size: u64) -> u64 {
+        self.access(true, address, size, false)
+    }
+
+    /// Loads or stores `width` bytes at `address`.
+    pub fn data(&mut self, address: u64, width: u64, is_write: bool) -> u64 {
+        self.access(false, address, width, is_write)
+    }
+
+    fn access(&mut self, is_fetch: bool, address: u64, width: u64, is_write: bool) -> u64 {
+        let slot = if is_fetch {
+            &mut self.l1i
+        } else {
+            &mut self.l1d
+        };
+        // out of its slot while the levels below are consulted
+        let Some(mut l1) = slot.take() else {
+            return self.below(address, is_write).saturating_sub(1);
+        };
+
+        // one lookup for every line the access touches
+        let line_size = l1.config.line_size;
+        let first = address / line_size;
+        let last = address.wrapping_add(width.max(1) - 1) / line_size;
+        let write_through = l1.config.write_policy == WritePolicy::WriteThrough;
+
+        let mut cycles = 0;
+        for line in first..=last {
+            let line_address = line * line_size;
+            let lookup = l1.access(line_address, is_write);
+            cycles += l1.config.latency;
+
+            if let Some(victim) = lookup.writeback {
+                self.below(victim, true);
+            }
+            if is_write && write_through {
+                self.below(line_address, true);
+            } else if !lookup.hit {
+                cycles += self.below(line_address, false);
+            }
+        }
+
+        let slot = if is_fetch {
+            &mut self.l1i
+        } else {
+            &mut self.l1d
+        };
+        *slot = Some(l1);
+        cycles.saturating_sub(1)
+    }
+
+    /// Cycles for the L2, if any, and main memory to serve a line.
+    fn below(&mut self, address: u64, is_write: bool) -> u64 {
+        let Some(l2) = &mut self.l2 else {
+            return self.memory_latency;
+        };
+
+        let lookup = l2.access(address, is_write);
+        let mut cycles = l2.config.latency;
+        if !lookup.hit && !is_write {
+            cycles += self.memory_latency;
+        }
+        cycles
+    }
+}
+
+impl Display for CacheHierarchy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let levels = [("l1i", &self.l1i), ("l1d", &self.l1d), ("l2", &self.l2)];
+        let mut first = true;
+        for (name, cache) in levels {
+            let Some(cache) = cache else {
+                continue;
+            };
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+
+            let (config, stats) = (cache.config(), cache.stats());
+            writeln!(
+                f,
+                "{} ({} B, {}-way, {} B lines, {}, {})",
+                name,
+                config.size,
+                config.associativity,
+                config.line_size,
+                config.replacement,
+                config.write_policy
+            )?;
+            writeln!(
+                f,
+                "  accesses : {} ({} reads, {} writes)",
+                stats.accesses(),
+                stats.reads,
+                stats.writes
+            )?;
+            writeln!(
+                f,
+                "  hits     : {} ({:.2}%)",
+                stats.hits(),
+                stats.hit_rate() * 100.0
+            )?;
+            writeln!(
+                f,
+                "  misses   : {} ({} reads, {} writes)",
+                stats.misses(),
+                stats.read_misses,
+                stats.write_misses
+            )?;
+            write!(
+                f,
+                "  evictions: {} ({} written back)",
+                stats.evictions, stats.writebacks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CacheConfig, CacheHierarchy, HierarchyConfig, Replacement, WritePolicy};
+
+    // one set of two 16-byte lines
+    fn two_way(replacement: Replacement, write_policy: WritePolicy) -> Cache {
+        Cache::new(CacheConfig {
+            replacement,
+            write_policy,
+            ..CacheConfig::new(32, 2, 16)
+        })
+    }
+
+    #[test]
+    fn test_replacement() {
+        // a, b, a, c: LRU evicts b, FIFO evicts a
+        let hit = |replacement, probe| {
+            let mut cache = two_way(replacement, WritePolicy::WriteBack);
+            for address in [0x00, 0x10, 0x00, 0x20] {
+                cache.access(address, false);
+            }
+            cache.access(probe, false).hit
+        };
+        assert!(hit(Replacement::Lru, 0x00) && !hit(Replacement::Lru, 0x10));
+        assert!(!hit(Replacement::Fifo, 0x00) && hit(Replacement::Fifo, 0x10));
+
+        let mut cache = two_way(Replacement::Random, WritePolicy::WriteBack);
+        for address in (0..64).map(|line| line * 0x10) {
+            cache.access(address, false);
+        }
+        assert_eq!(cache.stats().evictions, 62);
+    }
+
+    #[test]
+    fn test_write_policy() {
+        let mut cache = two_way(Replacement::Lru, WritePolicy::WriteBack);
+        assert!(!cache.access(0x00, true).hit);
+        cache.access(0x10, false);
+        // the dirty line goes out when evicted
+        assert_eq!(cache.access(0x20, false).writeback, Some(0x00));
+        assert_eq!(cache.access(0x30, false).writeback, None);
+        assert_eq!(cache.stats().writebacks, 1);
+
+        // write-through does not allocate on a store miss
+        let mut cache = two_way(Replacement::Lru, WritePolicy::WriteThrough);
+        assert!(!cache.access(0x00, true).hit);
+        assert!(!cache.access(0x00, false).hit);
+        assert!(cache.access(0x00, true).hit);
+        assert_eq!(cache.stats().write_misses, 1);
+        assert_eq!(cache.stats().read_misses, 1);
+    }
+
+    #[test]
+    fn test_hierarchy_latency() {
+        let mut caches = CacheHierarchy::new(&HierarchyConfig::default());
+        // L1 and L2 miss, then an L1 hit
+        assert_eq!(caches.data(0x100, 4, false), 1 + 10 + 100 - 1);
+        assert_eq!(caches.data(0x104, 4, false), 0);
+        // a word across two lines, the second one missing
+        assert_eq!(caches.data(0x13E, 4, false), 1 + 1 + 10 + 100 - 1);
+        // instruction fetches do not share the L1 but do share the L2
+        assert_eq!(caches.fetch(0x100, 4), 1 + 10 - 1);
+
+        let l1d = caches.l1d().unwrap().stats();
+        assert_eq!((l1d.reads, l1d.read_misses), (4, 2));
+        assert_eq!(caches.l2().unwrap().stats().hits(), 1);
+
+        let no_l1 = HierarchyConfig {
+            l1i: None,
+            l1d: None,
+            l2: None,
+            memory_latency: 50,
+        };
+        assert_eq!(CacheHierarchy::new(&no_l1).fetch(0x0, 4), 49);
+    }
+
+    #[test]
+    fn test_validate_cache() {
+        assert!(HierarchyConfig::default().validate().is_ok());
+        assert!(CacheConfig::new(0x1000, 4, 64).validate("l1").is_ok());
+        assert!(CacheConfig::new(0x1000, 4, 48).validate("l1").is_err());
+        assert!(CacheConfig::new(0x1000, 3, 64).validate("l1").is_err());
+        assert!(CacheConfig::new(0x1000, 0, 64).validate("l1").is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    io::{BufRead, BufReader, Lines},
+    process::{Child, ChildStdout, Command, Stdio},
+    rc::Rc,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::processor::{
+    decoder::instruction::{Instruction, RiscvInstruction},
+    hook::{Hook, HookAction},
+    register::{FloatRegisterAlias, RegisterAlias},
+    Processor,
+};
+
+/// A store as seen at commit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct StoreRecord {
+    pub address: u64,
+    pub width: u64,
+    pub value: u64,
+}
+
+/// What one retired instruction changed, as compared between two models.
+///
+/// External models send one record per line as JSON, e.g.
+/// `{"pc":8192,"raw":262455,"rd":[2,262144]}`; `rd`, `frd` and `store` may be
+/// left out when the instruction writes none.
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct CommitRecord {
+    pub pc: u64,
+    // a compressed instruction in the lower 16 bits
+    pub raw: u32,
+    // (index, value) of the integer register written, x0 never
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rd: Option<(u8, u64)>,
+    // (index, NaN-boxed value) of the floating-point register written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frd: Option<(u8, u64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<StoreRecord>,
+}
+
+/// A model retiring instructions one at a time.
+pub trait CommitModel {
+    /// Retires the next instruction; `None` once the model has halted.
+    fn next(&mut self) -> Result<Option<CommitRecord>>;
+}
+
+// builds the record of the current step from its events
+#[derive(Default)]
+struct CommitRecorder {
+    pending: CommitRecord,
+    committed: Rc<RefCell<Option<CommitRecord>>>,
+}
+
+impl Hook for CommitRecorder {
+    fn retire(&mut self, inst: &Instruction, pc: u64, _next_pc: u64) -> HookAction {
+        let mut record = std::mem::take(&mut self.pending);
+        record.pc = pc;
+        record.raw = inst.raw();
+        *self.committed.borrow_mut() = Some(record);
+        HookAction::Continue
+    }
+
+    fn memory_write(&mut self, _pc: u64, address: u64, width: u64, value: u64) -> HookAction {
+        self.pending.store = Some(StoreRecord {
+            address,
+            width,
+            value,
+        });
+        HookAction::Continue
+    }
+
+    fn register_write(&mut self, _pc: u64, index: u8, value: u64) -> HookAction {
+        self.pending.rd = Some((index, value));
+        HookAction::Continue
+    }
+
+    fn float_register_write(&mut self, _pc: u64, index: u8, value: u64) -> HookAction {
+        self.pending.frd = Some((index, value));
+        HookAction::Continue
+    }
+}
+
+/// A `Processor` retiring through `step`, recorded by a hook added after any
+/// it already has. Its per-step log is off until it is taken back.
+pub struct ProcessorModel {
+    cpu: Processor,
+    committed: Rc<RefCell<Option<CommitRecord>>>,
+    // the log setting to restore
+    trace: bool,
+}
+
+impl ProcessorModel {
+    pub fn new(mut cpu: Processor) -> Self {
+        let recorder = CommitRecorder::default();
+        let committed = recorder.committed.clone();
+        let trace = cpu.trace();
+        cpu.set_trace(false);
+        cpu.add_hook(Box::new(recorder));
+        Self {
+            cpu,
+            committed,
+            trace,
+        }
+    }
+
+    pub fn processor(&self) -> &Processor {
+        &self.cpu
+    }
+
+    /// The processor back, without the recorder and with its log as it was.
+    pub fn into_processor(mut self) -> Processor {
+        let mut hooks = self.cpu.take_hooks();
+        hooks.pop();
+        for hook in hooks {
+            self.cpu.add_hook(hook);
+        }
+        self.cpu.set_trace(self.trace);
+        self.cpu
+    }
+}
+
+impl CommitModel for ProcessorModel {
+    fn next(&mut self) -> Result<Option<CommitRecord>> {
+        if self.cpu.is_halt() {
+            return Ok(None);
+        }
+        self.cpu.step()?;
+        // the halt instruction itself does not retire
+        Ok(self.committed.borrow_mut().take())
+    }
+}
+
+/// An external model run as a child process, printing one JSON commit record
+/// per line on stdout and exiting when it halts.
+pub struct PipeModel {
+    child: Child,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl PipeModel {
+    /// Starts `command` with `sh -c`, so it may carry arguments and pipes.
+    pub fn spawn(command: &str) -> Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to start reference model: {}", command))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        Ok(Self {
+            child,
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+}
+
+impl CommitModel for PipeModel {
+    fn next(&mut self) -> Result<Option<CommitRecord>> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .with_context(|| format!("invalid commit record from reference model: {}", line))?;
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for PipeModel {
+    fn drop(&mut self) {
+        // a model still running after a divergence is not waited for
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// The first instruction on which the two models disagree.
+#[derive(Debug)]
+pub struct Divergence {
+    // instructions retired by both before this one
+    pub index: u64,
+    pub expected: Option<CommitRecord>,
+    pub actual: Option<CommitRecord>,
+    // source of the pc under test, when known
+    pub location: Option<String>,
+}
+
+/// How a co-simulation ended.
+#[derive(Debug)]
+pub enum CosimOutcome {
+    // both halted, or the limit was reached, after the same instructions
+    Matched { instructions: u64 },
+    Diverged(Box<Divergence>),
+}
+
+/// Steps `dut` and `reference` together for up to `limit` instructions, stopping
+/// at the first commit record that differs.
+pub fn lockstep(
+    dut: &mut ProcessorModel,
+    reference: &mut dyn CommitModel,
+    limit: u64,
+) -> Result<CosimOutcome> {
+    for index in 0..limit {
+        let pc = dut.cpu.pc();
+        let actual = match dut.next() {
+            Ok(actual) => actual,
+            Err(error) => bail!("{} after {} instructions in lockstep", error, index),
+        };
+        let expected = reference.next()?;
+
+        if expected != actual {
+            let location = dut.cpu.debug_info().locate(pc);
+            return Ok(CosimOutcome::Diverged(Box::new(Divergence {
+                index,
+                expected,
+                actual,
+                location,
+            })));
+        }
+        if actual.is_none() {
+            return Ok(CosimOutcome::Matched {
+                instructions: index,
+            });
+        }
+    }
+    Ok(CosimOutcome::Matched {
+        instructions: limit,
+    })
+}
+
+fn register(rd: &Option<(u8, u64)>, float: bool) -> String {
+    let Some((index, value)) = rd else {
+        return "-".into();
+    };
+    let name = match float {
+        true => FloatRegisterAlias::try_from(*index).map(|alias| alias.assembly()),
+        false => RegisterAlias::try_from(*index).map(|alias| alias.assembly()),
+    };
+    let name = name.unwrap_or_else(|_| format!("r{}", index));
+    format!("{} = {:#x}", name, value)
+}
+
+fn store(store: &Option<StoreRecord>) -> String {
+    match store {
+        Some(store) => format!(
+            "mem[{:#010x}; {}] = {:#x}",
+            store.address, store.width, store.value
+        ),
+        None => "-".into(),
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "divergence at instruction {}", self.index)?;
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+        writeln!(f)?;
+
+        let fields = |record: &Option<CommitRecord>| match record {
+            Some(record) => [
+                format!("{:#010x}", record.pc),
+                format!("{:#010x}", record.raw),
+                register(&record.rd, false),
+                register(&record.frd, true),
+                store(&record.store),
+            ],
+            None => [
+                "halted".into(),
+                "-".into(),
+                "-".into(),
+                "-".into(),
+                "-".into(),
+            ],
+        };
+        let expected = fields(&self.expected);
+        let actual = fields(&self.actual);
+
+        write!(f, "  {:<7} {:<32} actual", "", "expected")?;
+        for (i, name) in ["pc", "raw", "rd", "frd", "store"].iter().enumerate() {
+            // differing fields are marked
+            let mark = if expected[i] != actual[i] { '!' } else { ' ' };
+            write!(
+                f,
+                "\n{} {:<7} {:<32} {}",
+                mark, name, expected[i], actual[i]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for CosimOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Matched { instructions } => {
+                write!(f, "lockstep matched over {} instructions", instructions)
+            }
+            Self::Diverged(divergence) => write!(f, "{}", divergence),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::Processor;
+
+    use super::{lockstep, CommitRecord, CosimOutcome, PipeModel, ProcessorModel, StoreRecord};
+
+    fn model() -> ProcessorModel {
+        ProcessorModel::new(Processor::init("./example/elf/calls.elf").unwrap())
+    }
+
+    #[test]
+    fn test_lockstep_processors() {
+        let mut dut = model();
+        let outcome = lockstep(&mut dut, &mut model(), u64::MAX).unwrap();
+        assert!(matches!(
+            outcome,
+            CosimOutcome::Matched { instructions: 359 }
+        ));
+
+        // the reference squares with sub t0, t0, a0 instead
+        let mut cpu = Processor::init("./example/elf/calls.elf").unwrap();
+        cpu.write_memory(0x2070, &0x40a282b3u32.to_le_bytes())
+            .unwrap();
+        let outcome = lockstep(&mut model(), &mut ProcessorModel::new(cpu), u64::MAX).unwrap();
+        let CosimOutcome::Diverged(divergence) = outcome else {
+            panic!("models must diverge");
+        };
+        assert_eq!(divergence.index, 17);
+        assert_eq!(
+            divergence.expected.as_ref().unwrap().rd,
+            Some((5, (-10i64) as u32 as u64))
+        );
+        assert_eq!(divergence.actual.as_ref().unwrap().rd, Some((5, 10)));
+        assert_eq!(divergence.location.as_deref(), Some("calls.s:48 in square"));
+        let diff = divergence.to_string();
+        assert!(diff.contains("\n  pc      0x00002070"));
+        assert!(diff.contains("\n! rd      t0 = 0xfffffff6"));
+    }
+
+    #[test]
+    fn test_pipe_model() {
+        let records = [
+            CommitRecord {
+                pc: 0x2000,
+                raw: 0x00040137,
+                rd: Some((2, 0x40000)),
+                ..Default::default()
+            },
+            CommitRecord {
+                pc: 0x2004,
+                raw: 0x00a00513,
+                rd: Some((10, 10)),
+                store: Some(StoreRecord {
+                    address: 0x0,
+                    width: 4,
+                    value: 10,
+                }),
+                ..Default::default()
+            },
+        ];
+        let lines: Vec<_> = records
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect();
+        let command = format!("printf '%s\\n' '{}' '{}'", lines[0], lines[1]);
+
+        let mut reference = PipeModel::spawn(&command).unwrap();
+        let outcome = lockstep(&mut model(), &mut reference, u64::MAX).unwrap();
+        let CosimOutcome::Diverged(divergence) = outcome else {
+            panic!("the store is not made by li a0, 10");
+        };
+        assert_eq!(divergence.index, 1);
+        assert!(divergence
+            .to_string()
+            .contains("\n! store   mem[0x00000000; 4] = 0xa"));
+
+        let mut reference = PipeModel::spawn("echo '{\"pc\": 8192, \"raw\": 0}'").unwrap();
+        let outcome = lockstep(&mut model(), &mut reference, 1).unwrap();
+        assert!(matches!(outcome, CosimOutcome::Diverged(_)));
+        let mut reference = PipeModel::spawn("echo not json").unwrap();
+        assert!(lockstep(&mut model(), &mut reference, 1).is_err());
+    }
+}
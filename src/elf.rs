@@ -0,0 +1,391 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use gimli::{EndianSlice, RunTimeEndian, SectionId};
+use object::{
+    Architecture, Object, ObjectSection, ObjectSegment, ObjectSymbol, SegmentFlags, SymbolKind,
+    SymbolSection,
+};
+
+use crate::processor::xlen::Xlen;
+
+/// Leading bytes of every ELF file.
+pub const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+
+/// A named code address, from the symbol table of an ELF file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    // 0 for a bare label, which then runs up to the next symbol
+    pub size: u64,
+}
+
+/// Code symbols sorted by address, for naming pcs in reports.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by(|a, b| a.address.cmp(&b.address).then(a.name.cmp(&b.name)));
+        Self { symbols }
+    }
+
+    /// Reads the code symbols of an ELF file; other formats have none.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = fs::read(path)?;
+        if !bytes.starts_with(&ELF_MAGIC) {
+            return Ok(Self::default());
+        }
+        Ok(ElfImage::parse(&bytes)?.symbols)
+    }
+
+    /// The function covering `address`, or else the label right before it.
+    pub fn lookup(&self, address: u64) -> Option<&Symbol> {
+        let end = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= address);
+        let candidates = &self.symbols[..end];
+
+        candidates
+            .iter()
+            .rev()
+            .find(|symbol| symbol.size > 0 && address - symbol.address < symbol.size)
+            .or_else(|| candidates.last().filter(|symbol| symbol.size == 0))
+    }
+
+    /// `name+offset` for `address`, or just its hex form without a symbol.
+    pub fn describe(&self, address: u64) -> String {
+        match self.lookup(address) {
+            Some(symbol) if symbol.address == address => symbol.name.clone(),
+            Some(symbol) => format!("{}+{:#x}", symbol.name, address - symbol.address),
+            None => format!("{:#010x}", address),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Addresses `start..end` generated from one source line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: u64,
+    pub end: u64,
+    // index into `LineTable::files`
+    pub file: usize,
+    pub line: u64,
+}
+
+/// Source lines of code addresses, from the `.debug_line` section.
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    // sorted by start, not overlapping
+    ranges: Vec<LineRange>,
+}
+
+impl LineTable {
+    fn parse(file: &object::File) -> Result<Self> {
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let load = |id: SectionId| -> Result<EndianSlice<RunTimeEndian>, gimli::Error> {
+            let data = file
+                .section_by_name(id.name())
+                .and_then(|section| section.data().ok())
+                .unwrap_or(&[]);
+            Ok(EndianSlice::new(data, endian))
+        };
+        let dwarf = gimli::Dwarf::load(load)?;
+
+        let mut table = Self::default();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+
+            let mut rows = program.rows();
+            // the row opening the range being built: (address, file, line)
+            let mut open: Option<(u64, usize, u64)> = None;
+            while let Some((header, row)) = rows.next_row()? {
+                if let Some((start, file, line)) = open.take() {
+                    if start < row.address() {
+                        table.ranges.push(LineRange {
+                            start,
+                            end: row.address(),
+                            file,
+                            line,
+                        });
+                    }
+                }
+                if row.end_sequence() {
+                    continue;
+                }
+
+                let Some(entry) = row.file(header) else {
+                    continue;
+                };
+                let name = dwarf.attr_string(&unit, entry.path_name())?;
+                let mut path = name.to_string_lossy().into_owned();
+                if let Some(dir) = entry.directory(header) {
+                    let dir = dwarf
+                        .attr_string(&unit, dir)?
+                        .to_string_lossy()
+                        .into_owned();
+                    if !dir.is_empty() && dir != "." && !path.starts_with('/') {
+                        path = format!("{}/{}", dir, path);
+                    }
+                }
+
+                let file = match table.files.iter().position(|known| *known == path) {
+                    Some(index) => index,
+                    None => {
+                        table.files.push(path);
+                        table.files.len() - 1
+                    }
+                };
+                let line = row.line().map_or(0, |line| line.get());
+                open = Some((row.address(), file, line));
+            }
+        }
+
+        table.ranges.sort_by_key(|range| range.start);
+        Ok(table)
+    }
+
+    /// File and line of the code at `address`.
+    pub fn lookup(&self, address: u64) -> Option<(&str, u64)> {
+        let index = self
+            .ranges
+            .partition_point(|range| range.start <= address)
+            .checked_sub(1)?;
+        let range = &self.ranges[index];
+        (address < range.end).then(|| (self.files[range.file].as_str(), range.line))
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn ranges(&self) -> &[LineRange] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/// What is known about the source of the guest program, for reports.
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    pub symbols: SymbolTable,
+    pub lines: LineTable,
+}
+
+impl DebugInfo {
+    /// Reads the symbols and line table of an ELF file; other formats have none.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let bytes = fs::read(path)?;
+        if !bytes.starts_with(&ELF_MAGIC) {
+            return Ok(Self::default());
+        }
+        Ok(ElfImage::parse(&bytes)?.debug_info())
+    }
+
+    /// `file:line in function` for the code at `address`, or as much of it as
+    /// is known.
+    pub fn locate(&self, address: u64) -> Option<String> {
+        let line = self.lines.lookup(address);
+        let symbol = self.symbols.lookup(address);
+        match (line, symbol) {
+            (Some((file, line)), Some(symbol)) => {
+                Some(format!("{}:{} in {}", file, line, symbol.name))
+            }
+            (Some((file, line)), None) => Some(format!("{}:{}", file, line)),
+            (None, Some(_)) => Some(format!("in {}", self.symbols.describe(address))),
+            (None, None) => None,
+        }
+    }
+}
+
+/// One loadable segment; memory past the file bytes is left zero.
+pub struct Segment {
+    pub address: u64,
+    pub data: Vec<u8>,
+    pub executable: bool,
+}
+
+/// What the emulator needs from a RISC-V executable: its loadable segments,
+/// where it starts, and the names and source lines of its code.
+pub struct ElfImage {
+    pub xlen: Xlen,
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    pub symbols: SymbolTable,
+    pub lines: LineTable,
+}
+
+impl ElfImage {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let file = object::File::parse(bytes).context("failed to parse ELF file")?;
+        let xlen = match file.architecture() {
+            Architecture::Riscv32 => Xlen::X32,
+            Architecture::Riscv64 => Xlen::X64,
+            arch => bail!("not a RISC-V executable: {:?}", arch),
+        };
+
+        let mut segments = vec![];
+        for segment in file.segments() {
+            let executable = match segment.flags() {
+                SegmentFlags::Elf { p_flags } => p_flags & object::elf::PF_X != 0,
+                _ => true,
+            };
+            segments.push(Segment {
+                address: segment.address(),
+                data: segment.data()?.to_vec(),
+                executable,
+            });
+        }
+
+        // functions, and the labels of hand-written assembly
+        let symbols = file
+            .symbols()
+            .filter(|symbol| match symbol.kind() {
+                SymbolKind::Text => true,
+                SymbolKind::Unknown => matches!(symbol.section(), SymbolSection::Section(_)),
+                _ => false,
+            })
+            .filter_map(|symbol| {
+                let name = symbol.name().ok()?;
+                // assembler locals and mapping symbols
+                if name.is_empty() || name.starts_with(".L") || name.starts_with('$') {
+                    return None;
+                }
+                Some(Symbol {
+                    name: name.into(),
+                    address: symbol.address(),
+                    size: symbol.size(),
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            xlen,
+            entry: file.entry(),
+            segments,
+            symbols: SymbolTable::new(symbols),
+            lines: LineTable::parse(&file).context("failed to read DWARF line table")?,
+        })
+    }
+
+    /// `start..end` of every executable segment.
+    pub fn code_ranges(&self) -> Vec<(u64, u64)> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.executable)
+            .map(|segment| (segment.address, segment.address + segment.data.len() as u64))
+            .collect()
+    }
+
+    pub fn debug_info(&self) -> DebugInfo {
+        DebugInfo {
+            symbols: self.symbols.clone(),
+            lines: self.lines.clone(),
+        }
+    }
+
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugInfo, ElfImage, Symbol, SymbolTable};
+    use crate::processor::xlen::Xlen;
+
+    #[test]
+    fn test_parse_elf() {
+        let image = ElfImage::from_file("./example/elf/calls.elf").unwrap();
+        assert_eq!(image.xlen, Xlen::X32);
+        assert_eq!(image.entry, 0x2000);
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].address, 0x2000);
+        // lui sp, 0x40
+        assert_eq!(image.segments[0].data[..4], [0x37, 0x01, 0x04, 0x00]);
+
+        let symbols = &image.symbols;
+        assert_eq!(symbols.lookup(0x2000).unwrap().name, "_start");
+        assert_eq!(symbols.lookup(0x2014).unwrap().name, "halt");
+        assert_eq!(symbols.describe(0x2020), "sum_squares+0x8");
+        assert_eq!(symbols.describe(0x2064), "square");
+
+        // li sp, 0x40000 is on line 9 of calls.s, the last instruction on line 53
+        let lines = &image.lines;
+        assert_eq!(lines.files(), ["calls.s"]);
+        assert_eq!(lines.lookup(0x2000), Some(("calls.s", 9)));
+        assert_eq!(lines.lookup(0x2082), Some(("calls.s", 53)));
+        assert_eq!(lines.lookup(0x2084), None);
+        assert_eq!(image.code_ranges(), [(0x2000, 0x2084)]);
+
+        let debug = image.debug_info();
+        assert_eq!(debug.locate(0x2070).unwrap(), "calls.s:48 in square");
+        assert_eq!(debug.locate(0x3000), None);
+        let symbols_only = DebugInfo {
+            symbols: image.symbols.clone(),
+            ..Default::default()
+        };
+        assert_eq!(symbols_only.locate(0x2070).unwrap(), "in square+0xc");
+
+        assert!(ElfImage::parse(b"\x7fELF\x01").is_err());
+    }
+
+    #[test]
+    fn test_symbol_lookup() {
+        let symbol = |name: &str, address, size| Symbol {
+            name: name.into(),
+            address,
+            size,
+        };
+        let symbols = SymbolTable::new(vec![
+            symbol("f", 0x100, 0x20),
+            symbol("loop", 0x110, 0),
+            symbol("g", 0x180, 0x10),
+            symbol("label", 0x200, 0),
+        ]);
+        // the function wins over a label inside it
+        assert_eq!(symbols.lookup(0x118).unwrap().name, "f");
+        assert!(symbols.lookup(0x80).is_none());
+        assert!(symbols.lookup(0x190).is_none());
+        assert_eq!(symbols.lookup(0x240).unwrap().name, "label");
+        assert_eq!(symbols.describe(0x80), "0x00000080");
+    }
+}
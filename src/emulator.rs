@@ -1,51 +1,553 @@
-use std::path::Path;
+use std::{fs, path::Path};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-use super::processor::Processor;
+use super::{
+    cosim::{lockstep, CommitModel, CosimOutcome, ProcessorModel},
+    elf::{DebugInfo, ElfImage, SymbolTable, ELF_MAGIC},
+    machine::MachineConfig,
+    processor::{
+        decoder::instruction::Instruction,
+        hook::Hook,
+        predictor::BranchPredictor,
+        profiler::ProfileWeight,
+        stats::{StatsFormat, StatsReport},
+        xlen::Xlen,
+        Processor, ProcessorError,
+    },
+    snapshot::{SnapshotReader, SnapshotWriter},
+};
 
+/// A machine and its program, run from the command line or driven as a library.
+///
+/// The `init*` constructors keep the per-step log of the command line, while
+/// `with_config` builds a quiet machine for test harnesses:
+///
+/// ```
+/// use kuragemu_riscv::{
+///     emulator::Emulator, machine::MachineConfig, processor::register::RegisterAlias,
+/// };
+///
+/// let mut emu = Emulator::with_config(&MachineConfig::default()).unwrap();
+/// // addi a0, a0, 1; jal zero, 0
+/// emu.load_bytes(0x2000, &[0x13, 0x05, 0x15, 0x00, 0x6f, 0x00, 0x00, 0x00])
+///     .unwrap();
+/// emu.set_register(RegisterAlias::A0, 41).unwrap();
+/// emu.step_n(1).unwrap();
+/// assert_eq!(emu.register(RegisterAlias::A0).unwrap(), 42);
+/// assert_eq!(emu.last_instruction().unwrap().mnemonic(), "addi");
+/// ```
 pub struct Emulator {
     cpu: Processor,
+    // executable segments of the ELF program, or the words of a hex one
+    code: Vec<(u64, u64)>,
+    // format and number of hot spots of the statistics report, when enabled
+    stats: Option<(StatsFormat, usize)>,
 }
 
 impl Emulator {
     pub fn new() -> Self {
         let cpu = Processor::new();
 
-        Self { cpu }
+        Self::with_processor(cpu)
     }
 
     pub fn init<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let cpu = Processor::init(path)?;
-        let emu = Self { cpu };
+        Self::init_with_xlen(path, Xlen::default())
+    }
+
+    pub fn init_with_xlen<P>(path: P, xlen: Xlen) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let config = MachineConfig {
+            xlen,
+            ..MachineConfig::default()
+        };
+        Self::init_with_config(path, &config)
+    }
+
+    pub fn init_with_config<P>(path: P, config: &MachineConfig) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let cpu = Processor::init_with_config(&path, config)?;
+        let mut emu = Self::with_processor(cpu);
+        let bytes = fs::read(path)?;
+        emu.code = if bytes.starts_with(&ELF_MAGIC) {
+            ElfImage::parse(&bytes)?.code_ranges()
+        } else {
+            // one word per line
+            let words = String::from_utf8_lossy(&bytes).lines().count() as u64;
+            vec![(config.reset_vector, config.reset_vector + words * 4)]
+        };
 
         Ok(emu)
     }
 
-    pub fn run(&mut self) {
+    /// A machine built from `config` with nothing loaded and no stdout output,
+    /// starting at its reset vector.
+    pub fn with_config(config: &MachineConfig) -> Result<Self> {
+        let mut cpu = Processor::with_config(config)?;
+        cpu.set_trace(false);
+        Ok(Self::with_processor(cpu))
+    }
+
+    fn with_processor(cpu: Processor) -> Self {
+        Self {
+            cpu,
+            code: vec![],
+            stats: None,
+        }
+    }
+
+    /// Serializes the full machine state in the versioned snapshot format.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        self.cpu.save(&mut writer);
+        writer.finish()
+    }
+
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Self> {
+        let mut reader = SnapshotReader::new(snapshot)?;
+        let cpu = Processor::restore(&mut reader)?;
+        reader.finish()?;
+
+        Ok(Self::with_processor(cpu))
+    }
+
+    pub fn save_snapshot<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        fs::write(path, self.snapshot())?;
+        Ok(())
+    }
+
+    pub fn load_snapshot<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let snapshot = fs::read(path)?;
+        Self::from_snapshot(&snapshot)
+    }
+
+    /// The processor, for the models and counters enabled on it.
+    pub fn processor(&self) -> &Processor {
+        &self.cpu
+    }
+
+    /// Turns the per-step log and the final report on stdout on or off.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.cpu.set_trace(trace);
+    }
+
+    /// Places an image at `address`, ignoring region permissions.
+    pub fn load_bytes(&mut self, address: u64, bytes: &[u8]) -> Result<()> {
+        self.cpu.load_bytes(address, bytes)
+    }
+
+    /// Loads an ELF executable and starts at its entry point, taking its symbols
+    /// and source lines for reports.
+    pub fn load_elf<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let image = ElfImage::from_file(path)?;
+        self.cpu.load_elf(&image)?;
+        self.code = image.code_ranges();
+        Ok(())
+    }
+
+    /// Reads an integer register by index or `RegisterAlias`.
+    pub fn register<R>(&self, register: R) -> Result<u64, ProcessorError>
+    where
+        R: Into<u8>,
+    {
+        self.cpu.register(register.into())
+    }
+
+    /// Writes an integer register by index or `RegisterAlias`; x0 stays zero.
+    pub fn set_register<R>(&mut self, register: R, value: u64) -> Result<(), ProcessorError>
+    where
+        R: Into<u8>,
+    {
+        self.cpu.set_register(register.into(), value)
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.cpu.pc()
+    }
+
+    pub fn set_pc(&mut self, pc: u64) {
+        self.cpu.set_pc(pc);
+    }
+
+    /// Fills `buf` from memory at `address`, ignoring region permissions.
+    pub fn read_memory(&self, address: u64, buf: &mut [u8]) -> Result<(), ProcessorError> {
+        self.cpu.read_memory(address, buf)
+    }
+
+    /// Writes `data` at `address`, ignoring region permissions.
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), ProcessorError> {
+        self.cpu.write_memory(address, data)
+    }
+
+    pub fn step(&mut self) -> Result<(), ProcessorError> {
+        self.cpu.step()
+    }
+
+    /// Steps up to `n` instructions, stopping early at the halt or when a hook
+    /// asks to. Returns how many were stepped.
+    pub fn step_n(&mut self, n: u64) -> Result<u64, ProcessorError> {
         let mut count = 0;
+        while count < n && !self.cpu.is_halt() {
+            self.cpu.step()?;
+            count += 1;
+            if self.cpu.stop_requested() {
+                break;
+            }
+        }
+        Ok(count)
+    }
 
-        loop {
-            if self.cpu.is_halt() {
+    /// Steps until `predicate` holds, checked before every step, the processor
+    /// halts or a hook asks to stop. Returns how many instructions were stepped.
+    pub fn run_until<F>(&mut self, mut predicate: F) -> Result<u64, ProcessorError>
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        let mut count = 0;
+        while !self.cpu.is_halt() && !predicate(self) {
+            self.cpu.step()?;
+            count += 1;
+            if self.cpu.stop_requested() {
                 break;
             }
+        }
+        Ok(count)
+    }
+
+    /// Calls `hook` on the events of every step; see `Hook`.
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.cpu.add_hook(hook);
+    }
+
+    pub fn is_halt(&self) -> bool {
+        self.cpu.is_halt()
+    }
+
+    /// The instruction retired last.
+    pub fn last_instruction(&self) -> Option<&Instruction> {
+        self.cpu.last_instruction()
+    }
+
+    /// Times the run on the 5-stage pipeline model, reported at the end of `run`.
+    pub fn enable_pipeline(&mut self, forwarding: bool) {
+        self.cpu.enable_pipeline(forwarding);
+    }
+
+    /// Evaluates a branch predictor over the run, reported at the end of `run`.
+    pub fn set_branch_predictor(&mut self, predictor: Box<dyn BranchPredictor>) {
+        self.cpu.set_branch_predictor(predictor);
+    }
 
-            println!("step: {}", count + 1);
-            let res = self.cpu.step();
-            match res {
-                Err(error) => {
-                    println!("{}", error.form());
-                    break;
-                }
-                _ => {}
+    /// Names pcs in reports, for programs loaded from hex next to their ELF.
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        let debug = DebugInfo {
+            symbols,
+            lines: self.cpu.debug_info().lines.clone(),
+        };
+        self.cpu.set_debug_info(debug);
+    }
+
+    /// Names pcs and maps them to source lines, for programs loaded from hex
+    /// next to their ELF.
+    pub fn set_debug_info(&mut self, debug: DebugInfo) {
+        self.cpu.set_debug_info(debug);
+    }
+
+    /// Disassembly of the program, labelled with its symbols and source lines.
+    pub fn disassembly(&self) -> String {
+        self.code
+            .iter()
+            .map(|(start, end)| self.cpu.disassemble(*start, *end))
+            .collect()
+    }
+
+    /// The current pc, or the faulting one after an error, with its source line,
+    /// followed by the call sites on the shadow stack when profiling.
+    pub fn backtrace(&self) -> String {
+        let pc = self.cpu.pc();
+        let call_sites = self
+            .cpu
+            .profiler()
+            .map_or(&[][..], |profiler| profiler.call_sites());
+        let frames = std::iter::once(&pc).chain(call_sites.iter().rev());
+
+        let mut backtrace = String::from("backtrace:");
+        for (depth, address) in frames.enumerate() {
+            backtrace += &format!("\n  #{} {}", depth, self.cpu.describe(*address));
+        }
+        backtrace
+    }
+
+    /// Counts the instruction mix and the `top` hottest pcs and functions,
+    /// reported at the end of `run` in `format`.
+    pub fn enable_stats(&mut self, format: StatsFormat, top: usize) {
+        self.cpu.enable_stats();
+        self.stats = Some((format, top));
+    }
+
+    /// Statistics so far, when enabled.
+    pub fn stats_report(&self) -> Option<StatsReport> {
+        let (_, top) = self.stats?;
+        let symbols = &self.cpu.debug_info().symbols;
+        let symbols = (!symbols.is_empty()).then_some(symbols);
+        Some(self.cpu.stats()?.report(symbols, top))
+    }
+
+    /// Keeps a shadow call stack and attributes each retired instruction, or its
+    /// cycles when the pipeline model is on, to the call path it ran on.
+    pub fn enable_profiler(&mut self, weight: ProfileWeight) {
+        self.cpu.enable_profiler(weight);
+    }
+
+    /// The profile so far as folded stacks, named from the program symbols.
+    pub fn folded_profile(&self) -> Option<String> {
+        Some(self.cpu.profiler()?.folded(&self.cpu.debug_info().symbols))
+    }
+
+    /// Writes the folded stacks for `flamegraph.pl` or `inferno-flamegraph`.
+    pub fn write_profile<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let Some(folded) = self.folded_profile() else {
+            bail!("profiling is not enabled");
+        };
+        fs::write(path, folded)?;
+        Ok(())
+    }
+
+    /// Counts executions of every address and branch direction.
+    pub fn enable_coverage(&mut self) {
+        self.cpu.enable_coverage();
+    }
+
+    /// The coverage so far as an lcov tracefile, when the program has line info.
+    pub fn lcov(&self) -> Option<String> {
+        let coverage = self.cpu.coverage()?;
+        let debug = self.cpu.debug_info();
+        if debug.lines.is_empty() {
+            return None;
+        }
+        Some(coverage.lcov(debug, |address| self.cpu.instruction_at(address)))
+    }
+
+    pub fn write_lcov<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        if self.cpu.coverage().is_none() {
+            bail!("coverage is not enabled");
+        }
+        let Some(lcov) = self.lcov() else {
+            bail!("the program has no DWARF line info to map coverage to");
+        };
+        fs::write(path, lcov)?;
+        Ok(())
+    }
+
+    /// Disassembly of the program annotated with the coverage so far. A restored
+    /// snapshot has no program file, so there it spans the code executed.
+    pub fn coverage_listing(&self) -> Option<String> {
+        let coverage = self.cpu.coverage()?;
+        let ranges = match self.code.is_empty() {
+            true => coverage.span().into_iter().collect(),
+            false => self.code.clone(),
+        };
+        Some(coverage.listing(&ranges, self.cpu.debug_info(), |address| {
+            self.cpu.instruction_at(address)
+        }))
+    }
+
+    pub fn write_coverage_listing<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let Some(listing) = self.coverage_listing() else {
+            bail!("coverage is not enabled");
+        };
+        fs::write(path, listing)?;
+        Ok(())
+    }
+
+    /// Records the last `limit` steps so that they can be stepped back.
+    pub fn enable_history(&mut self, limit: usize) {
+        self.cpu.enable_history(limit);
+    }
+
+    pub fn step_back(&mut self, n: usize) -> usize {
+        self.cpu.step_back(n)
+    }
+
+    pub fn reverse_continue(&mut self, breakpoints: &[u64]) -> usize {
+        self.cpu.reverse_continue(breakpoints)
+    }
+
+    /// Runs to the halt on the block engine, without the per-step log. The final
+    /// state is reported, when the log is on, even if an instruction faults.
+    pub fn run_fast(&mut self) -> Result<(), ProcessorError> {
+        let trace = self.cpu.trace();
+        self.cpu.set_trace(false);
+        let res = self.cpu.run_blocks(u64::MAX).map(|_| ());
+        self.cpu.set_trace(trace);
+
+        self.report();
+        res
+    }
+
+    /// Runs in lockstep with `reference` for up to `limit` instructions, without
+    /// the per-step log, and reports the final state when the log is on.
+    pub fn cosim(&mut self, reference: &mut dyn CommitModel, limit: u64) -> Result<CosimOutcome> {
+        let mut dut = ProcessorModel::new(std::mem::take(&mut self.cpu));
+        let outcome = lockstep(&mut dut, reference, limit);
+        self.cpu = dut.into_processor();
+
+        self.report();
+        outcome
+    }
+
+    /// Steps to the halt, stopping at the first fault or when a hook asks to.
+    /// With the log on, every step is logged and the final state is reported
+    /// either way.
+    pub fn run(&mut self) -> Result<(), ProcessorError> {
+        let mut count = 0;
+        let mut res = Ok(());
+
+        while !self.cpu.is_halt() {
+            if self.cpu.trace() {
+                println!("step: {}", count + 1);
+            }
+            res = self.cpu.step();
+            if res.is_err() || self.cpu.stop_requested() {
+                break;
             }
 
             count += 1;
         }
 
+        self.report();
+        res
+    }
+
+    // the final state, then whatever models and counters were enabled
+    fn report(&self) {
+        if !self.cpu.trace() {
+            return;
+        }
         self.cpu.logging();
+
+        if let Some(pipeline) = self.cpu.pipeline() {
+            println!();
+            println!("{}", pipeline.diagram());
+            println!("{}", pipeline);
+        }
+        if let Some(caches) = self.cpu.caches() {
+            println!();
+            println!("{}", caches);
+        }
+        if let Some(profile) = self.cpu.branch_profile() {
+            println!();
+            println!("{}", profile);
+        }
+        if let (Some((format, _)), Some(report)) = (self.stats, self.stats_report()) {
+            println!();
+            println!("{}", report.format(format));
+        }
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cosim::{CosimOutcome, ProcessorModel},
+        machine::MachineConfig,
+        processor::{register::RegisterAlias, Processor},
+    };
+
+    use super::Emulator;
+
+    #[test]
+    fn test_embedding() {
+        let mut emu = Emulator::with_config(&MachineConfig::default()).unwrap();
+        emu.load_elf("./example/elf/calls.elf").unwrap();
+        assert_eq!(emu.pc(), 0x2000);
+
+        // the first call of square, with a0 = 10
+        let steps = emu.run_until(|emu| emu.pc() == 0x2064).unwrap();
+        assert_eq!(steps, 14);
+        assert_eq!(emu.register(RegisterAlias::A0).unwrap(), 10);
+        assert_eq!(emu.register(10).unwrap(), 10);
+        assert_eq!(emu.last_instruction().unwrap().mnemonic(), "jalr");
+
+        // square(3) instead, then back into sum_squares
+        emu.set_register(RegisterAlias::A0, 3).unwrap();
+        emu.run_until(|emu| emu.pc() == 0x2040).unwrap();
+        assert_eq!(emu.register(RegisterAlias::A0).unwrap(), 9);
+        emu.set_register(RegisterAlias::Zero, 1).unwrap();
+        assert_eq!(emu.register(0).unwrap(), 0);
+        assert!(emu.register(32).is_err());
+
+        assert_eq!(emu.step_n(3).unwrap(), 3);
+        emu.run_until(|_| false).unwrap();
+        assert!(emu.is_halt());
+        // 1 + 4 + ... + 81 + 9 for the replaced 100
+        let mut result = [0; 4];
+        emu.read_memory(0x0, &mut result).unwrap();
+        assert_eq!(u32::from_le_bytes(result), 294);
+
+        emu.write_memory(0x10, &[1, 2, 3]).unwrap();
+        let mut bytes = [0; 3];
+        emu.read_memory(0x10, &mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3]);
+        let error = emu.read_memory(0x3_fffe, &mut [0; 4]).unwrap_err();
+        assert_eq!(error.address(), Some(0x3_fffe));
+        assert_eq!(error.width(), Some(4));
+    }
+
+    #[test]
+    fn test_cosim() {
+        let mut emu = Emulator::init("./example/elf/calls.elf").unwrap();
+        let mut reference =
+            ProcessorModel::new(Processor::init("./example/elf/calls.elf").unwrap());
+        let outcome = emu.cosim(&mut reference, 100).unwrap();
+        assert!(matches!(
+            outcome,
+            CosimOutcome::Matched { instructions: 100 }
+        ));
+        // the log of an `init` machine is back on
+        assert!(emu.processor().trace());
+        emu.set_trace(false);
+
+        // the emulator keeps its processor, and the run goes on from there
+        assert_eq!(emu.pc(), reference.processor().pc());
+        // quietly, with the log off
+        emu.run_fast().unwrap();
+        assert!(emu.is_halt());
+        assert!(!emu.processor().trace());
+        let mut result = [0; 4];
+        emu.read_memory(0x0, &mut result).unwrap();
+        assert_eq!(u32::from_le_bytes(result), 385);
     }
 }
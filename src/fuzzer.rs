@@ -0,0 +1,549 @@
+use std::fmt::Display;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    cosim::{lockstep, CommitModel, CommitRecord, CosimOutcome, ProcessorModel, StoreRecord},
+    machine::MachineConfig,
+    processor::{
+        decoder::{decode, instruction::RiscvInstruction},
+        xlen::Xlen,
+        Processor,
+    },
+};
+
+// loads and stores only go through t6, which points at this window
+const DATA_BASE: u32 = 0x10000;
+const DATA_SIZE: u32 = 0x100;
+const BASE_REGISTER: u32 = 31;
+// jal zero, 0
+const HALT: u32 = 0x0000006F;
+// the farthest a generated branch or jal skips ahead, in instructions
+const MAX_SKIP: u64 = 8;
+
+// xorshift, so that a case is repeated from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self((seed ^ 0x2545_F491_4F6C_DD1D).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opecode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opecode
+}
+
+fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, opecode: u32) -> u32 {
+    (imm & 0xFFF) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opecode
+}
+
+fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    (imm >> 5 & 0x7F) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1F) << 7 | 0x23
+}
+
+fn b_type(offset: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    b_offset_bits(offset) | rs2 << 20 | rs1 << 15 | funct3 << 12 | 0x63
+}
+
+fn b_offset_bits(offset: u32) -> u32 {
+    (offset >> 12 & 1) << 31
+        | (offset >> 5 & 0x3F) << 25
+        | (offset >> 1 & 0xF) << 8
+        | (offset >> 11 & 1) << 7
+}
+
+fn j_offset_bits(offset: u32) -> u32 {
+    (offset >> 20 & 1) << 31
+        | (offset >> 1 & 0x3FF) << 21
+        | (offset >> 11 & 1) << 20
+        | (offset >> 12 & 0xFF) << 12
+}
+
+fn sign_extend(value: u32, bits: u32) -> u32 {
+    ((value << (32 - bits)) as i32 >> (32 - bits)) as u32
+}
+
+fn i_imm(raw: u32) -> u32 {
+    (raw as i32 >> 20) as u32
+}
+
+fn s_imm(raw: u32) -> u32 {
+    ((raw as i32 >> 25) << 5) as u32 | (raw >> 7 & 0x1F)
+}
+
+fn b_offset(raw: u32) -> u32 {
+    let imm = (raw >> 31 & 1) << 12
+        | (raw >> 7 & 1) << 11
+        | (raw >> 25 & 0x3F) << 5
+        | (raw >> 8 & 0xF) << 1;
+    sign_extend(imm, 13)
+}
+
+fn j_offset(raw: u32) -> u32 {
+    let imm = (raw >> 31 & 1) << 20
+        | (raw >> 12 & 0xFF) << 12
+        | (raw >> 20 & 1) << 11
+        | (raw >> 21 & 0x3FF) << 1;
+    sign_extend(imm, 21)
+}
+
+fn random_instruction(rng: &mut Rng) -> u32 {
+    // t6 keeps the data window
+    let rd = rng.below(31) as u32;
+    let rs1 = rng.below(32) as u32;
+    let rs2 = rng.below(32) as u32;
+    let skip = 4 * (1 + rng.below(MAX_SKIP)) as u32;
+
+    match rng.below(100) {
+        0..=34 => {
+            // OP, half of them from M
+            let funct3 = rng.below(8) as u32;
+            let funct7 = match rng.below(4) {
+                0 | 1 => 0b0000001,
+                2 if funct3 == 0b000 || funct3 == 0b101 => 0b0100000,
+                _ => 0b0000000,
+            };
+            r_type(funct7, rs2, rs1, funct3, rd, 0x33)
+        }
+        35..=59 => {
+            let funct3 = rng.below(8) as u32;
+            let imm = match funct3 {
+                0b001 => rng.below(32),
+                // srli or srai
+                0b101 => rng.below(32) | rng.below(2) << 10,
+                _ => rng.below(1 << 12),
+            };
+            i_type(imm as u32, rs1, funct3, rd, 0x13)
+        }
+        60..=69 => {
+            let opecode = if rng.below(2) == 0 { 0x37 } else { 0x17 };
+            (rng.next() as u32 & 0xFFFFF000) | rd << 7 | opecode
+        }
+        70..=79 => {
+            // lb, lh, lw, lbu or lhu
+            let funct3 = [0b000, 0b001, 0b010, 0b100, 0b101][rng.below(5) as usize];
+            let width = 1 << (funct3 % 4);
+            let offset = rng.below((DATA_SIZE / width) as u64) as u32 * width;
+            i_type(offset, BASE_REGISTER, funct3, rd, 0x03)
+        }
+        80..=89 => {
+            let funct3 = rng.below(3) as u32;
+            let width = 1 << funct3;
+            let offset = rng.below((DATA_SIZE / width) as u64) as u32 * width;
+            s_type(offset, rs2, BASE_REGISTER, funct3)
+        }
+        90..=97 => {
+            let funct3 = [0b000, 0b001, 0b100, 0b101, 0b110, 0b111][rng.below(6) as usize];
+            b_type(skip, rs2, rs1, funct3)
+        }
+        _ => j_offset_bits(skip) | rd << 7 | 0x6F,
+    }
+}
+
+/// `length` random RV32I/M instructions from `seed`. Loads and stores stay in a
+/// small data window and branches and jumps only go forward, so any sequence
+/// assembled with `assemble` ends.
+pub fn generate(seed: u64, length: usize) -> Vec<u32> {
+    let mut rng = Rng::new(seed);
+    (0..length).map(|_| random_instruction(&mut rng)).collect()
+}
+
+/// The program run for `body`: the data window set up, then `body` and a halt.
+/// Jumps past the halt, left by a minimization, are shortened to reach it.
+pub fn assemble(body: &[u32]) -> Vec<u32> {
+    let mut program = Vec::with_capacity(body.len() + 2);
+    program.push(DATA_BASE | BASE_REGISTER << 7 | 0x37);
+    program.extend_from_slice(body);
+    program.push(HALT);
+
+    let halt = program.len() - 1;
+    for (index, raw) in program.iter_mut().enumerate() {
+        let limit = ((halt - index) * 4) as u32;
+        match *raw & 0x7F {
+            0x63 if b_offset(*raw) > limit => *raw = *raw & 0x01FFF07F | b_offset_bits(limit),
+            0x6F if j_offset(*raw) > limit => *raw = *raw & 0xFFF | j_offset_bits(limit),
+            _ => {}
+        }
+    }
+    program
+}
+
+/// A plain RV32IM interpreter of the generated programs, written apart from the
+/// processor so that the two do not share a bug.
+pub struct Oracle {
+    program: Vec<u32>,
+    base: u32,
+    pc: u32,
+    x: [u32; 32],
+    data: Vec<u8>,
+    halted: bool,
+}
+
+impl Oracle {
+    /// `program` placed at `base`, started with `registers` and a zeroed data window.
+    pub fn new(program: &[u32], base: u64, registers: [u32; 32]) -> Self {
+        Self {
+            program: program.to_vec(),
+            base: base as u32,
+            pc: base as u32,
+            x: registers,
+            data: vec![0; DATA_SIZE as usize],
+            halted: false,
+        }
+    }
+
+    fn window(&self, address: u32, width: u32) -> Result<usize> {
+        let offset = address.wrapping_sub(DATA_BASE);
+        if offset >= DATA_SIZE || !offset.is_multiple_of(width) {
+            bail!("access out of the data window: {:#010x}", address);
+        }
+        Ok(offset as usize)
+    }
+}
+
+impl CommitModel for Oracle {
+    fn next(&mut self) -> Result<Option<CommitRecord>> {
+        if self.halted {
+            return Ok(None);
+        }
+        let pc = self.pc;
+        let index = (pc.wrapping_sub(self.base) / 4) as usize;
+        let Some(&raw) = self.program.get(index) else {
+            bail!("pc out of the program: {:#010x}", pc);
+        };
+
+        let rd = (raw >> 7 & 0x1F) as usize;
+        let funct3 = raw >> 12 & 0x7;
+        let (lhs, rhs) = (
+            self.x[(raw >> 15 & 0x1F) as usize],
+            self.x[(raw >> 20 & 0x1F) as usize],
+        );
+        let funct7 = raw >> 25;
+        let mut next_pc = pc.wrapping_add(4);
+        let mut store = None;
+
+        let value = match raw & 0x7F {
+            0x37 => Some(raw & 0xFFFFF000),
+            0x17 => Some(pc.wrapping_add(raw & 0xFFFFF000)),
+            0x6F => {
+                next_pc = pc.wrapping_add(j_offset(raw));
+                self.halted = raw == HALT;
+                Some(pc.wrapping_add(4))
+            }
+            0x63 => {
+                let taken = match funct3 {
+                    0b000 => lhs == rhs,
+                    0b001 => lhs != rhs,
+                    0b100 => (lhs as i32) < (rhs as i32),
+                    0b101 => (lhs as i32) >= (rhs as i32),
+                    0b110 => lhs < rhs,
+                    0b111 => lhs >= rhs,
+                    _ => bail!("unknown branch: {:#010x}", raw),
+                };
+                if taken {
+                    next_pc = pc.wrapping_add(b_offset(raw));
+                }
+                None
+            }
+            0x03 => {
+                let width = 1 << (funct3 % 4);
+                let offset = self.window(lhs.wrapping_add(i_imm(raw)), width)?;
+                let mut bytes = [0; 4];
+                bytes[..width as usize]
+                    .copy_from_slice(&self.data[offset..offset + width as usize]);
+                let word = u32::from_le_bytes(bytes);
+                Some(match funct3 {
+                    0b000 => word as u8 as i8 as u32,
+                    0b001 => word as u16 as i16 as u32,
+                    0b010 => word,
+                    0b100 => word as u8 as u32,
+                    0b101 => word as u16 as u32,
+                    _ => bail!("unknown load: {:#010x}", raw),
+                })
+            }
+            0x23 => {
+                if funct3 > 0b010 {
+                    bail!("unknown store: {:#010x}", raw);
+                }
+                let width = 1 << funct3;
+                let address = lhs.wrapping_add(s_imm(raw));
+                let offset = self.window(address, width)?;
+                let bytes = rhs.to_le_bytes();
+                self.data[offset..offset + width as usize]
+                    .copy_from_slice(&bytes[..width as usize]);
+                store = Some(StoreRecord {
+                    address: address as u64,
+                    width: width as u64,
+                    value: (rhs as u64) & ((1 << (width * 8)) - 1),
+                });
+                None
+            }
+            0x13 => {
+                let imm = i_imm(raw);
+                let shamt = imm & 0x1F;
+                Some(match funct3 {
+                    0b000 => lhs.wrapping_add(imm),
+                    0b001 => lhs << shamt,
+                    0b010 => ((lhs as i32) < (imm as i32)) as u32,
+                    0b011 => (lhs < imm) as u32,
+                    0b100 => lhs ^ imm,
+                    0b101 if funct7 == 0b0100000 => ((lhs as i32) >> shamt) as u32,
+                    0b101 => lhs >> shamt,
+                    0b110 => lhs | imm,
+                    _ => lhs & imm,
+                })
+            }
+            0x33 => {
+                let shamt = rhs & 0x1F;
+                let (signed_lhs, signed_rhs) = (lhs as i32 as i64, rhs as i32 as i64);
+                Some(match (funct7, funct3) {
+                    (0b0000000, 0b000) => lhs.wrapping_add(rhs),
+                    (0b0100000, 0b000) => lhs.wrapping_sub(rhs),
+                    (0b0000000, 0b001) => lhs << shamt,
+                    (0b0000000, 0b010) => ((lhs as i32) < (rhs as i32)) as u32,
+                    (0b0000000, 0b011) => (lhs < rhs) as u32,
+                    (0b0000000, 0b100) => lhs ^ rhs,
+                    (0b0000000, 0b101) => lhs >> shamt,
+                    (0b0100000, 0b101) => ((lhs as i32) >> shamt) as u32,
+                    (0b0000000, 0b110) => lhs | rhs,
+                    (0b0000000, 0b111) => lhs & rhs,
+                    (0b0000001, 0b000) => (signed_lhs * signed_rhs) as u32,
+                    (0b0000001, 0b001) => ((signed_lhs * signed_rhs) >> 32) as u32,
+                    (0b0000001, 0b010) => ((signed_lhs * rhs as i64) >> 32) as u32,
+                    (0b0000001, 0b011) => ((lhs as u64 * rhs as u64) >> 32) as u32,
+                    // the quotient by zero is -1 and the remainder the dividend
+                    (0b0000001, 0b100) if rhs == 0 => u32::MAX,
+                    (0b0000001, 0b100) => (signed_lhs / signed_rhs) as u32,
+                    (0b0000001, 0b101) if rhs == 0 => u32::MAX,
+                    (0b0000001, 0b101) => lhs / rhs,
+                    (0b0000001, 0b110) if rhs == 0 => lhs,
+                    (0b0000001, 0b110) => (signed_lhs % signed_rhs) as u32,
+                    (0b0000001, 0b111) if rhs == 0 => lhs,
+                    (0b0000001, 0b111) => lhs % rhs,
+                    _ => bail!("unknown operation: {:#010x}", raw),
+                })
+            }
+            _ => bail!("not an instruction of the oracle: {:#010x}", raw),
+        };
+
+        self.pc = next_pc;
+        let rd = match value {
+            Some(value) if rd != 0 => {
+                self.x[rd] = value;
+                Some((rd as u8, value as u64))
+            }
+            _ => None,
+        };
+        Ok(Some(CommitRecord {
+            pc: pc as u64,
+            raw,
+            rd,
+            frd: None,
+            store,
+        }))
+    }
+}
+
+/// Runs `program` on a `Processor` of the default machine and on the oracle in
+/// lockstep.
+pub fn check(program: &[u32]) -> Result<CosimOutcome> {
+    let config = MachineConfig::default();
+    let mut cpu = Processor::with_config(&config)?;
+    cpu.load(config.reset_vector, program)?;
+    let mut registers = [0; 32];
+    for (index, value) in registers.iter_mut().enumerate() {
+        *value = cpu.register(index as u8)? as u32;
+    }
+
+    let mut oracle = Oracle::new(program, config.reset_vector, registers);
+    // with forward jumps only, every instruction retires at most once
+    lockstep(
+        &mut ProcessorModel::new(cpu),
+        &mut oracle,
+        program.len() as u64 + 1,
+    )
+}
+
+fn fails(body: &[u32]) -> bool {
+    !matches!(check(&assemble(body)), Ok(CosimOutcome::Matched { .. }))
+}
+
+/// Removes instructions from `body` while `fails` still holds, first in large
+/// chunks, then one by one.
+pub fn minimize<F>(body: &[u32], mut fails: F) -> Vec<u32>
+where
+    F: FnMut(&[u32]) -> bool,
+{
+    let mut body = body.to_vec();
+    let mut chunk = body.len().div_ceil(2);
+    while chunk > 0 {
+        let mut removed = false;
+        let mut start = 0;
+        while start < body.len() {
+            let end = (start + chunk).min(body.len());
+            let candidate = [&body[..start], &body[end..]].concat();
+            if fails(&candidate) {
+                body = candidate;
+                removed = true;
+            } else {
+                start = end;
+            }
+        }
+        if !removed {
+            chunk /= 2;
+        }
+    }
+    body
+}
+
+/// A generated program on which the processor and the oracle disagree.
+#[derive(Debug)]
+pub struct FuzzFailure {
+    // seed of the failing case
+    pub case: u64,
+    // instructions generated before the minimization
+    pub length: usize,
+    // the minimized program, as assembled
+    pub program: Vec<u32>,
+    // the divergence, or the error, it ends with
+    pub outcome: String,
+}
+
+/// Checks `cases` random programs of `length` instructions, their seeds drawn
+/// from `seed`, and minimizes the first failing one.
+pub fn fuzz(seed: u64, cases: u64, length: usize) -> Option<FuzzFailure> {
+    let mut seeds = Rng::new(seed);
+    for _ in 0..cases {
+        let case = seeds.next();
+        let body = generate(case, length);
+        if !fails(&body) {
+            continue;
+        }
+
+        let program = assemble(&minimize(&body, fails));
+        let outcome = match check(&program) {
+            Ok(outcome) => outcome.to_string(),
+            Err(error) => error.to_string(),
+        };
+        return Some(FuzzFailure {
+            case,
+            length,
+            program,
+            outcome,
+        });
+    }
+    None
+}
+
+impl Display for FuzzFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "case {:#018x} fails, minimized from {} to {} instructions",
+            self.case,
+            self.length,
+            // without the setup and the halt
+            self.program.len() - 2
+        )?;
+        writeln!(f, "{}", self.outcome)?;
+        writeln!(f)?;
+
+        // the words alone make a hex program for the default machine
+        write!(f, "reproducer:")?;
+        let base = MachineConfig::default().reset_vector;
+        for (index, raw) in self.program.iter().enumerate() {
+            let assembly = match decode(*raw, &Xlen::X32) {
+                Ok(inst) => inst.assembly(),
+                Err(_) => "<not an instruction>".into(),
+            };
+            write!(
+                f,
+                "\n  {:08x}:  {:08x}  {}",
+                base + index as u64 * 4,
+                raw,
+                assembly
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cosim::{lockstep, CosimOutcome, ProcessorModel},
+        machine::MachineConfig,
+        processor::Processor,
+    };
+
+    use super::{assemble, check, fuzz, generate, minimize, Oracle, HALT};
+
+    #[test]
+    fn test_fuzz() {
+        assert_eq!(generate(7, 16), generate(7, 16));
+        assert_ne!(generate(7, 16), generate(8, 16));
+
+        let failure = fuzz(0x5eed, 200, 64);
+        assert!(failure.is_none(), "{}", failure.unwrap());
+    }
+
+    #[test]
+    fn test_assemble() {
+        // beq zero, zero, 64 and jal ra, 32 would skip past the halt
+        let program = assemble(&[0x04000063, 0x020000EF]);
+        assert_eq!(program.len(), 4);
+        assert_eq!(program[3], HALT);
+        // both now land on the halt, the beq skipping the jal
+        assert_eq!(program[1], 0x00000463);
+        assert_eq!(program[2], 0x004000EF);
+        assert!(matches!(
+            check(&program).unwrap(),
+            CosimOutcome::Matched { instructions: 3 }
+        ));
+
+        // the oracle running another program than the processor
+        let config = MachineConfig::default();
+        let mut cpu = Processor::with_config(&config).unwrap();
+        // addi a0, zero, 1 against addi a0, zero, 2
+        cpu.load(config.reset_vector, &assemble(&[0x00100513]))
+            .unwrap();
+        let mut oracle = Oracle::new(&assemble(&[0x00200513]), config.reset_vector, [0; 32]);
+        let outcome = lockstep(&mut ProcessorModel::new(cpu), &mut oracle, 10).unwrap();
+        let CosimOutcome::Diverged(divergence) = outcome else {
+            panic!("the programs differ");
+        };
+        assert_eq!(divergence.index, 1);
+        assert_eq!(divergence.expected.unwrap().rd, Some((10, 2)));
+    }
+
+    #[test]
+    fn test_minimize() {
+        // fails while a mul is followed by a sub, somewhere in 64 instructions
+        let (mul, sub) = (0x02C58533, 0x40C58533);
+        let mut body = generate(1, 64);
+        body.retain(|raw| *raw != mul && *raw != sub);
+        body.insert(10, mul);
+        body.insert(50, sub);
+
+        let mut tries = 0;
+        let minimized = minimize(&body, |candidate| {
+            tries += 1;
+            let position = |word| candidate.iter().position(|raw| *raw == word);
+            matches!((position(mul), position(sub)), (Some(m), Some(s)) if m < s)
+        });
+        assert_eq!(minimized, [mul, sub]);
+        assert!(tries < 200);
+    }
+}
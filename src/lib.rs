@@ -1,3 +1,9 @@
+pub mod cache;
+pub mod cosim;
+pub mod elf;
 pub mod emulator;
+pub mod fuzzer;
+pub mod machine;
 pub mod memory;
 pub mod processor;
+pub mod snapshot;
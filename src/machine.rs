@@ -0,0 +1,332 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    cache::HierarchyConfig,
+    memory::{MisalignedPolicy, Permissions},
+    processor::xlen::Xlen,
+};
+
+/// One RAM or ROM region of the physical memory map.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub base: u64,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub size: u64,
+    #[serde(deserialize_with = "deserialize_permissions")]
+    pub permissions: Permissions,
+}
+
+impl RegionConfig {
+    pub fn new(name: &str, base: u64, size: u64, permissions: Permissions) -> Self {
+        Self {
+            name: name.into(),
+            base,
+            size,
+            permissions,
+        }
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        self.base <= address && address - self.base < self.size
+    }
+}
+
+/// Description of the machine an emulator instance models.
+///
+/// In a TOML file:
+///
+/// ```toml
+/// xlen = 64
+/// reset_vector = 0x8000_0000
+/// stack_pointer = 0x8010_0000
+/// misaligned = "trap" # or "emulate", the default
+///
+/// [[region]]
+/// name = "dram"
+/// base = 0x8000_0000
+/// size = 0x10_0000
+/// permissions = "rwx"
+/// ```
+///
+/// An optional `[cache]` table adds a cache model, see [`HierarchyConfig`].
+///
+/// JSON has the same fields; addresses may also be given as strings like `"0x80000000"`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineConfig {
+    #[serde(default, deserialize_with = "deserialize_xlen")]
+    pub xlen: Xlen,
+    #[serde(deserialize_with = "deserialize_address")]
+    pub reset_vector: u64,
+    // x2 is left zero when not given
+    #[serde(default, deserialize_with = "deserialize_optional_address")]
+    pub stack_pointer: Option<u64>,
+    #[serde(default)]
+    pub misaligned: MisalignedPolicy,
+    #[serde(rename = "region")]
+    pub regions: Vec<RegionConfig>,
+    // timing only: no cache model when not given
+    #[serde(default)]
+    pub cache: Option<HierarchyConfig>,
+}
+
+impl MachineConfig {
+    pub fn new(reset_vector: u64, regions: Vec<RegionConfig>) -> Self {
+        Self {
+            xlen: Xlen::default(),
+            reset_vector,
+            stack_pointer: None,
+            misaligned: MisalignedPolicy::default(),
+            regions,
+            cache: None,
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        let config: Self = serde_json::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads a machine description, choosing the format by the file extension.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read machine file {}", path.display()))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => bail!("machine file must be .toml or .json: {}", path.display()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        ensure!(!self.regions.is_empty(), "no memory region is given");
+
+        for region in self.regions.iter() {
+            ensure!(
+                region.size > 0 && region.size % 4 == 0 && region.base % 4 == 0,
+                "region {} must be word aligned and not empty",
+                region.name
+            );
+            ensure!(
+                region.base.checked_add(region.size).is_some(),
+                "region {} exceeds the address space",
+                region.name
+            );
+        }
+
+        for (i, region) in self.regions.iter().enumerate() {
+            for other in self.regions.iter().skip(i + 1) {
+                let disjoint = region.base + region.size <= other.base
+                    || other.base + other.size <= region.base;
+                ensure!(
+                    disjoint,
+                    "regions {} and {} overlap",
+                    region.name,
+                    other.name
+                );
+            }
+        }
+
+        let executable = self
+            .regions
+            .iter()
+            .any(|region| region.contains(self.reset_vector) && region.permissions.execute);
+        ensure!(
+            executable,
+            "reset vector {:#010x} is not in an executable region",
+            self.reset_vector
+        );
+
+        if let Some(sp) = self.stack_pointer {
+            // the stack grows down from its initial value, which may be the end of a region
+            let writable = self.regions.iter().any(|region| {
+                region.permissions.write && region.base < sp && sp - region.base <= region.size
+            });
+            ensure!(
+                writable,
+                "stack pointer {:#010x} is not in a writable region",
+                sp
+            );
+        }
+
+        if let Some(cache) = &self.cache {
+            cache.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for MachineConfig {
+    /// A single 256 KiB RAM at address 0, entered at 0x2000.
+    fn default() -> Self {
+        let ram = RegionConfig::new("ram", 0, 0x40000, Permissions::all());
+        Self::new(0x2000, vec![ram])
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AddressValue {
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<AddressValue> for u64 {
+    type Error = String;
+
+    fn try_from(value: AddressValue) -> Result<Self, Self::Error> {
+        match value {
+            AddressValue::Number(number) => Ok(number),
+            AddressValue::Text(text) => {
+                let digits = text.replace('_', "");
+                let parsed = match digits.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => digits.parse(),
+                };
+                parsed.map_err(|_| format!("invalid address: {}", text))
+            }
+        }
+    }
+}
+
+fn deserialize_address<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = AddressValue::deserialize(deserializer)?;
+    u64::try_from(value).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_address<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_address(deserializer).map(Some)
+}
+
+fn deserialize_xlen<'de, D>(deserializer: D) -> Result<Xlen, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u32::deserialize(deserializer)? {
+        32 => Ok(Xlen::X32),
+        64 => Ok(Xlen::X64),
+        xlen => Err(serde::de::Error::custom(format!(
+            "xlen must be 32 or 64, but get: {}",
+            xlen
+        ))),
+    }
+}
+
+fn deserialize_permissions<'de, D>(deserializer: D) -> Result<Permissions, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        memory::{MisalignedPolicy, Permissions},
+        processor::xlen::Xlen,
+    };
+
+    use super::{MachineConfig, RegionConfig};
+
+    #[test]
+    fn test_parse_machine() {
+        let toml = r#"
+            xlen = 64
+            reset_vector = 0x8000_0000
+            stack_pointer = 0x8010_0000
+            misaligned = "trap"
+
+            [[region]]
+            name = "rom"
+            base = 0x1000
+            size = 0x1000
+            permissions = "r-x"
+
+            [[region]]
+            name = "dram"
+            base = 0x8000_0000
+            size = 0x10_0000
+            permissions = "rwx"
+        "#;
+        let config = MachineConfig::from_toml(toml).unwrap();
+        assert_eq!(config.xlen, Xlen::X64);
+        assert_eq!(config.reset_vector, 0x8000_0000);
+        assert_eq!(config.stack_pointer, Some(0x8010_0000));
+        assert_eq!(config.misaligned, MisalignedPolicy::Trap);
+        assert_eq!(
+            config.regions[0],
+            RegionConfig::new("rom", 0x1000, 0x1000, "rx".parse().unwrap())
+        );
+
+        let json = r#"{
+            "reset_vector": "0x8000_0000",
+            "region": [
+                { "name": "rom", "base": 4096, "size": "0x1000", "permissions": "r-x" },
+                { "name": "dram", "base": "0x80000000", "size": 1048576, "permissions": "rwx" }
+            ]
+        }"#;
+        let config = MachineConfig::from_json(json).unwrap();
+        assert_eq!(config.xlen, Xlen::X32);
+        assert_eq!(config.stack_pointer, None);
+        assert_eq!(config.misaligned, MisalignedPolicy::Emulate);
+        assert_eq!(config.regions[1].base, 0x8000_0000);
+        assert_eq!(config.regions[1].permissions, Permissions::all());
+    }
+
+    #[test]
+    fn test_validate_machine() {
+        let rwx = Permissions::all();
+        let rx: Permissions = "r-x".parse().unwrap();
+        assert!(MachineConfig::default().validate().is_ok());
+
+        let overlap = vec![
+            RegionConfig::new("a", 0x0, 0x2000, rwx),
+            RegionConfig::new("b", 0x1000, 0x2000, rwx),
+        ];
+        assert!(MachineConfig::new(0x0, overlap).validate().is_err());
+
+        let unaligned = vec![RegionConfig::new("a", 0x2, 0x1000, rwx)];
+        assert!(MachineConfig::new(0x4, unaligned).validate().is_err());
+
+        // the reset vector must be executable, the stack writable
+        let regions = vec![
+            RegionConfig::new("rom", 0x0, 0x1000, rx),
+            RegionConfig::new("data", 0x1000, 0x1000, "rw".parse().unwrap()),
+        ];
+        let mut config = MachineConfig::new(0x1000, regions);
+        assert!(config.validate().is_err());
+        config.reset_vector = 0x0;
+        config.stack_pointer = Some(0x2000);
+        assert!(config.validate().is_ok());
+        config.stack_pointer = Some(0x800);
+        assert!(config.validate().is_err());
+
+        assert!(MachineConfig::from_toml("reset_vector = 0\nxlen = 16\nregion = []").is_err());
+    }
+}
@@ -1,9 +1,188 @@
-use anyhow::Result;
-use kuragemu_riscv::emulator::Emulator;
+use std::env;
+
+use anyhow::{anyhow, bail, Context, Result};
+use kuragemu_riscv::{
+    cache::HierarchyConfig,
+    cosim::{CosimOutcome, PipeModel},
+    elf::DebugInfo,
+    emulator::Emulator,
+    fuzzer,
+    machine::MachineConfig,
+    processor::{predictor, profiler::ProfileWeight, xlen::Xlen},
+};
 
 fn main() -> Result<()> {
-    let path = "./example/instructions/ope.hex";
-    let mut emu = Emulator::init(path)?;
-    emu.run();
-    Ok(())
+    // usage: kuragemu-riscv [--rv64] [--fast] [--pipeline] [--no-forwarding] [--predictor <name>]
+    //                       [--cache] [--stats <table|json>] [--top <n>] [--symbols <elf>]
+    //                       [--profile <file>] [--profile-weight <instructions|cycles>]
+    //                       [--coverage <lcov file>] [--listing <file>] [--disassemble]
+    //                       [--cosim <reference command>] [--fuzz <cases>] [--seed <n>]
+    //                       [--machine <file>] [--restore <snapshot>] [--snapshot <file>] [path]
+    // path is an ELF executable or a hex program; the reference command of --cosim
+    // prints one JSON commit record per retired instruction; --fuzz checks random
+    // programs against a reference interpreter instead of running one
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut config = MachineConfig::default();
+    let mut machine = false;
+    let mut path = None;
+    let mut restore = None;
+    let mut snapshot = None;
+    let mut fast = false;
+    let mut pipeline = false;
+    let mut forwarding = true;
+    let mut predictor = None;
+    let mut cache = false;
+    let mut stats = None;
+    let mut top = 10;
+    let mut symbols = None;
+    let mut profile = None;
+    let mut weight = ProfileWeight::default();
+    let mut lcov = None;
+    let mut listing = None;
+    let mut disassemble = false;
+    let mut cosim = None;
+    let mut fuzz = None;
+    let mut seed = 0;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--rv64" => config.xlen = Xlen::X64,
+            "--fast" => fast = true,
+            "--pipeline" => pipeline = true,
+            "--no-forwarding" => forwarding = false,
+            "--cache" => cache = true,
+            "--disassemble" => disassemble = true,
+            "--machine" => {
+                let file = iter.next().context("--machine needs a file")?;
+                let xlen = config.xlen;
+                config = MachineConfig::from_file(file)?;
+                machine = true;
+                // --rv64 wins over the machine file
+                if xlen == Xlen::X64 {
+                    config.xlen = xlen;
+                }
+            }
+            "--predictor" => {
+                let name = iter.next().context("--predictor needs a name")?;
+                predictor = Some(predictor::from_name(name)?);
+            }
+            "--stats" => {
+                let format = iter.next().context("--stats needs table or json")?;
+                stats = Some(format.parse()?);
+            }
+            "--top" => {
+                let n = iter.next().context("--top needs a number")?;
+                top = n.parse().context("--top needs a number")?;
+            }
+            "--symbols" => symbols = Some(iter.next().context("--symbols needs a file")?),
+            "--profile" => profile = Some(iter.next().context("--profile needs a file")?),
+            "--profile-weight" => {
+                let name = iter.next().context("--profile-weight needs a weight")?;
+                weight = name.parse()?;
+            }
+            "--coverage" => lcov = Some(iter.next().context("--coverage needs a file")?),
+            "--listing" => listing = Some(iter.next().context("--listing needs a file")?),
+            "--cosim" => cosim = Some(iter.next().context("--cosim needs a command")?),
+            "--fuzz" => {
+                let n = iter.next().context("--fuzz needs a number of cases")?;
+                fuzz = Some(n.parse().context("--fuzz needs a number of cases")?);
+            }
+            "--seed" => {
+                let n = iter.next().context("--seed needs a number")?;
+                seed = n.parse().context("--seed needs a number")?;
+            }
+            "--restore" => restore = Some(iter.next().context("--restore needs a file")?),
+            "--snapshot" => snapshot = Some(iter.next().context("--snapshot needs a file")?),
+            _ if path.is_none() => path = Some(arg.as_str()),
+            _ => {}
+        }
+    }
+    if let Some(cases) = fuzz {
+        let length = 64;
+        if let Some(failure) = fuzzer::fuzz(seed, cases, length) {
+            println!("{}", failure);
+            bail!("the processor diverged from the reference interpreter");
+        }
+        println!(
+            "{} random programs of {} instructions passed (seed {})",
+            cases, length, seed
+        );
+        return Ok(());
+    }
+    // a restored machine is the one saved in the snapshot
+    if restore.is_some() && (machine || cache || config.xlen == Xlen::X64) {
+        bail!("--restore cannot be combined with --machine, --rv64 or --cache");
+    }
+    let path = path.unwrap_or("./example/instructions/ope.hex");
+    // the default hierarchy, unless the machine file describes one
+    if cache && config.cache.is_none() {
+        config.cache = Some(HierarchyConfig::default());
+    }
+
+    let mut emu = match restore {
+        Some(file) => Emulator::load_snapshot(file)?,
+        None => Emulator::init_with_config(path, &config)?,
+    };
+    if pipeline {
+        emu.enable_pipeline(forwarding);
+    }
+    if let Some(file) = symbols {
+        emu.set_debug_info(DebugInfo::from_file(file)?);
+    }
+    if disassemble {
+        print!("{}", emu.disassembly());
+        return Ok(());
+    }
+    if let Some(format) = stats {
+        emu.enable_stats(format, top);
+    }
+    if profile.is_some() {
+        // cycles come from the pipeline model
+        if weight == ProfileWeight::Cycles && !pipeline {
+            emu.enable_pipeline(forwarding);
+        }
+        emu.enable_profiler(weight);
+    }
+    if lcov.is_some() || listing.is_some() {
+        emu.enable_coverage();
+    }
+    if let Some(predictor) = predictor {
+        emu.set_branch_predictor(predictor);
+    }
+    let result = match cosim {
+        Some(command) => {
+            let mut reference = PipeModel::spawn(command)?;
+            emu.cosim(&mut reference, u64::MAX).and_then(|outcome| {
+                println!();
+                println!("{}", outcome);
+                match outcome {
+                    CosimOutcome::Diverged(_) => {
+                        Err(anyhow!("the machine diverged from the reference model"))
+                    }
+                    CosimOutcome::Matched { .. } => Ok(()),
+                }
+            })
+        }
+        None if fast => emu.run_fast().map_err(Into::into),
+        None => emu.run().map_err(Into::into),
+    };
+    if result.is_err() {
+        eprintln!("{}", emu.backtrace());
+    }
+
+    if let Some(file) = profile {
+        emu.write_profile(file)?;
+    }
+    if let Some(file) = lcov {
+        emu.write_lcov(file)?;
+    }
+    if let Some(file) = listing {
+        emu.write_coverage_listing(file)?;
+    }
+    if let Some(file) = snapshot {
+        emu.save_snapshot(file)?;
+    }
+    // the profile, coverage and snapshot of a faulting or diverging run are kept
+    result
 }
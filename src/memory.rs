@@ -1,97 +1,879 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
+use serde::Deserialize;
 
-use crate::processor::{decoder::instruction::ByteWideOption, ProcessorError, ProcessorErrorTrait};
+use crate::{
+    machine::{MachineConfig, RegionConfig},
+    processor::{decoder::instruction::ByteWideOption, ProcessorError},
+    snapshot::{SnapshotReader, SnapshotWriter},
+};
 
-const DATA_MEMORY_SIZE: usize = 0x10000;
+/// Access rights of a memory region.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    pub fn all() -> Self {
+        Self {
+            read: true,
+            write: true,
+            execute: true,
+        }
+    }
+
+    fn allows(&self, access: &Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+impl FromStr for Permissions {
+    type Err = String;
+
+    /// Parses the `ls -l` style, like "rwx" or "r-x".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut permissions = Self {
+            read: false,
+            write: false,
+            execute: false,
+        };
+
+        for c in s.chars() {
+            match c {
+                'r' => permissions.read = true,
+                'w' => permissions.write = true,
+                'x' => permissions.execute = true,
+                '-' => {}
+                _ => return Err(format!("invalid permissions: {}", s)),
+            }
+        }
+
+        Ok(permissions)
+    }
+}
+
+impl Display for Permissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = |set, c| if set { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.execute, 'x')
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+            Self::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// What a load or store does when its address is not a multiple of its width.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MisalignedPolicy {
+    /// Raise the load/store address-misaligned exception.
+    Trap,
+    /// Perform the access byte by byte, crossing words, pages and regions.
+    #[default]
+    Emulate,
+}
+
+const PAGE_SIZE: u64 = 0x1000;
+
+type Page = Box<[u8; PAGE_SIZE as usize]>;
+
+/// A region backed by 4 KiB pages, allocated on the first write.
+/// Untouched pages read as zero.
+#[derive(Clone)]
+struct MemoryRegion {
+    base: u64,
+    size: u64,
+    permissions: Permissions,
+    pages: HashMap<u64, Page>,
+}
+
+impl MemoryRegion {
+    fn new(config: &RegionConfig) -> Self {
+        Self {
+            base: config.base,
+            size: config.size,
+            permissions: config.permissions,
+            pages: HashMap::new(),
+        }
+    }
+
+    fn contains(&self, address: u64) -> bool {
+        self.base <= address && address - self.base < self.size
+    }
+
+    fn read_byte(&self, offset: u64) -> u8 {
+        match self.pages.get(&(offset / PAGE_SIZE)) {
+            Some(page) => page[(offset % PAGE_SIZE) as usize],
+            None => 0,
+        }
+    }
+
+    fn byte_mut(&mut self, offset: u64) -> &mut u8 {
+        let page = self
+            .pages
+            .entry(offset / PAGE_SIZE)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE as usize]));
+        &mut page[(offset % PAGE_SIZE) as usize]
+    }
+}
 
 #[derive(Clone)]
 pub struct MainMemory {
-    mem: [u32; DATA_MEMORY_SIZE],
+    regions: Vec<MemoryRegion>,
+    misaligned: MisalignedPolicy,
+    // word address reserved by the last LR.W, if any
+    reservation: Option<u64>,
 }
 
 impl MainMemory {
     pub fn new() -> Self {
+        Self::with_config(&MachineConfig::default())
+    }
+
+    pub fn with_regions(regions: &[RegionConfig]) -> Self {
+        Self {
+            regions: regions.iter().map(MemoryRegion::new).collect(),
+            misaligned: MisalignedPolicy::default(),
+            reservation: None,
+        }
+    }
+
+    pub fn with_config(config: &MachineConfig) -> Self {
         Self {
-            mem: [0; DATA_MEMORY_SIZE],
+            misaligned: config.misaligned,
+            ..Self::with_regions(&config.regions)
         }
     }
 
-    pub fn read(&self, address: u32, option: &ByteWideOption) -> Result<u32, ProcessorError> {
-        let addrdiff = address % 4;
-        let address = address >> 2;
+    /// Finds the region holding `address`, checking the region allows the access.
+    /// Returns the region index and the offset into it.
+    fn locate(&self, address: u64, access: Access) -> Result<(usize, u64), MainMemoryErrorType> {
+        let Some(index) = self
+            .regions
+            .iter()
+            .position(|region| region.contains(address))
+        else {
+            return Err(MainMemoryErrorType::AddressOutOfBounds);
+        };
 
-        if address < DATA_MEMORY_SIZE as u32 {
-            let raw = self.mem[address as usize];
-            let rd = option.trim(raw, addrdiff);
-            Ok(rd)
+        let region = &self.regions[index];
+        if region.permissions.allows(&access) {
+            Ok((index, address - region.base))
         } else {
-            let error_type = MainMemoryErrorType::AddressOutOfBounds;
-            Err(MainMemoryError::new(error_type))
+            Err(MainMemoryErrorType::PermissionDenied(access))
         }
     }
 
+    fn check_alignment(
+        &self,
+        address: u64,
+        width: u64,
+        access: Access,
+    ) -> Result<(), ProcessorError> {
+        if self.misaligned == MisalignedPolicy::Trap && !address.is_multiple_of(width) {
+            let error_type = MainMemoryErrorType::MisalignedAccess(access);
+            Err(ProcessorError::memory(error_type, address, width))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads `width` bytes from `address` as a little-endian value.
+    fn read_bytes(&self, address: u64, width: u64, access: Access) -> Result<u64, ProcessorError> {
+        let mut value = 0;
+        for i in 0..width {
+            let (region, offset) = self
+                .locate(address.wrapping_add(i), access)
+                .map_err(|kind| ProcessorError::memory(kind, address, width))?;
+            value |= (self.regions[region].read_byte(offset) as u64) << (i * 8);
+        }
+        Ok(value)
+    }
+
+    /// Loads a value, sign-extended to 64 bits unless the option is unsigned.
+    pub fn read(&self, address: u64, option: &ByteWideOption) -> Result<u64, ProcessorError> {
+        self.check_alignment(address, option.width(), Access::Read)?;
+
+        let raw = self.read_bytes(address, option.width(), Access::Read)?;
+        Ok(option.extend(raw))
+    }
+
     pub fn write(
         &mut self,
-        address: u32,
-        raw: u32,
+        address: u64,
+        raw: u64,
         option: &ByteWideOption,
     ) -> Result<(), ProcessorError> {
-        let addrdiff = address % 4;
-        let address = address >> 2;
-
-        if address < DATA_MEMORY_SIZE as u32 {
-            let mask = option.overwrite_mask(addrdiff);
-            let value = (raw << (addrdiff * 8)) & mask;
-            self.mem[address as usize] &= !mask;
-            self.mem[address as usize] |= value;
+        let width = option.width();
+        self.check_alignment(address, width, Access::Write)?;
+
+        // every byte is checked before any is written, so a faulting store
+        // leaves memory untouched
+        let mut targets = [(0, 0); 8];
+        for i in 0..width {
+            targets[i as usize] = self
+                .locate(address.wrapping_add(i), Access::Write)
+                .map_err(|kind| ProcessorError::memory(kind, address, width))?;
+        }
+
+        for (i, (region, offset)) in targets.iter().take(width as usize).enumerate() {
+            *self.regions[*region].byte_mut(*offset) = (raw >> (i * 8)) as u8;
+        }
+
+        // any store into the reserved word breaks the LR/SC sequence,
+        // whichever hart it comes from; a misaligned double word touches three words
+        let first = address >> 2;
+        let last = address.wrapping_add(width - 1) >> 2;
+        let covers = |reserved: u64| match first <= last {
+            true => (first..=last).contains(&reserved),
+            // the store wraps around the top of the address space
+            false => reserved >= first || reserved <= last,
+        };
+        if self.reservation.is_some_and(covers) {
+            self.reservation = None;
+        }
+        Ok(())
+    }
+
+    /// Reads the 16-bit parcel at a 2-byte aligned `address` for instruction fetch.
+    pub fn fetch_parcel(&self, address: u64) -> Result<u32, ProcessorError> {
+        Ok(self.read_bytes(address, 2, Access::Execute)? as u32)
+    }
+
+    /// Places words at `address` regardless of the region permissions,
+    /// so that a program can be put into ROM.
+    pub fn load(&mut self, address: u64, words: &[u32]) -> Result<()> {
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        self.load_bytes(address, &bytes)
+    }
+
+    /// Copies `bytes` to `address`, ignoring permissions like `load`.
+    pub fn load_bytes(&mut self, address: u64, bytes: &[u8]) -> Result<()> {
+        for (i, byte) in bytes.iter().copied().enumerate() {
+            let target = address + i as u64;
+            let Some(region) = self
+                .regions
+                .iter_mut()
+                .find(|region| region.contains(target))
+            else {
+                bail!("no memory region at {:#010x} to load into", target);
+            };
+            *region.byte_mut(target - region.base) = byte;
+        }
+
+        Ok(())
+    }
+
+    /// Reads `width` bytes regardless of permissions, as debuggers do.
+    /// Returns `None` when any byte is outside every region.
+    pub fn peek(&self, address: u64, width: u64) -> Option<u64> {
+        let mut value = 0;
+        for i in 0..width {
+            let target = address.wrapping_add(i);
+            let region = self.regions.iter().find(|region| region.contains(target))?;
+            value |= (region.read_byte(target - region.base) as u64) << (i * 8);
+        }
+        Some(value)
+    }
+
+    /// Writes `width` bytes regardless of permissions; bytes outside every region are dropped.
+    pub fn poke(&mut self, address: u64, width: u64, value: u64) {
+        for i in 0..width {
+            let target = address.wrapping_add(i);
+            if let Some(region) = self
+                .regions
+                .iter_mut()
+                .find(|region| region.contains(target))
+            {
+                *region.byte_mut(target - region.base) = (value >> (i * 8)) as u8;
+            }
+        }
+    }
+
+    /// Loads a file of one hexadecimal word per line at `address`.
+    pub fn load_hex<P>(&mut self, path: P, address: u64) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        let mut words = vec![];
+        for line in reader.lines() {
+            words.push(u32::from_str_radix(line?.as_str(), 16)?);
+        }
+
+        self.load(address, &words)
+    }
+
+    /// 64-bit access for LD/SD and FLD/FSD.
+    pub fn read_double_word(&self, address: u64) -> Result<u64, ProcessorError> {
+        self.read(address, &ByteWideOption::DoubleWord)
+    }
+
+    pub fn write_double_word(&mut self, address: u64, raw: u64) -> Result<(), ProcessorError> {
+        self.write(address, raw, &ByteWideOption::DoubleWord)
+    }
+
+    pub fn load_reserved(&mut self, address: u64) -> Result<u32, ProcessorError> {
+        Self::check_atomic_alignment(address)?;
+
+        let value = self.read(address, &ByteWideOption::Word)? as u32;
+        self.reservation = Some(address >> 2);
+        Ok(value)
+    }
+
+    /// Returns `true` when the store is performed.
+    /// The reservation is released whether the store succeeds or not.
+    pub fn store_conditional(&mut self, address: u64, raw: u32) -> Result<bool, ProcessorError> {
+        Self::check_atomic_alignment(address)?;
+
+        if self.reservation.take() == Some(address >> 2) {
+            self.write(address, raw as u64, &ByteWideOption::Word)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Reads a word, writes back `operation(old)` and returns the old value.
+    pub fn atomic_operation<F>(&mut self, address: u64, operation: F) -> Result<u32, ProcessorError>
+    where
+        F: FnOnce(u32) -> u32,
+    {
+        Self::check_atomic_alignment(address)?;
+
+        let old = self.read(address, &ByteWideOption::Word)? as u32;
+        self.write(address, operation(old) as u64, &ByteWideOption::Word)?;
+        Ok(old)
+    }
+
+    pub fn clear_reservation(&mut self) {
+        self.reservation = None;
+    }
+
+    pub fn reservation(&self) -> Option<u64> {
+        self.reservation
+    }
+
+    pub fn set_reservation(&mut self, reservation: Option<u64>) {
+        self.reservation = reservation;
+    }
+
+    fn check_atomic_alignment(address: u64) -> Result<(), ProcessorError> {
+        if address.is_multiple_of(4) {
             Ok(())
         } else {
-            let error_type = MainMemoryErrorType::AddressOutOfBounds;
-            Err(MainMemoryError::new(error_type))
+            let error_type = MainMemoryErrorType::MisalignedAtomicAccess;
+            Err(ProcessorError::memory(error_type, address, 4))
         }
     }
 
+    /// The first `n` words of the first region.
     pub fn head(&self, n: usize) -> Vec<u32> {
-        let view = &self.mem[0..n];
-        view.to_vec()
+        let region = &self.regions[0];
+        let n = n.min((region.size >> 2) as usize) as u64;
+        (0..n * 4)
+            .step_by(4)
+            .map(|offset| {
+                let bytes = [0, 1, 2, 3].map(|i| region.read_byte(offset + i));
+                u32::from_le_bytes(bytes)
+            })
+            .collect()
+    }
+
+    /// Writes the regions with their non-zero pages, in address order.
+    pub fn save(&self, writer: &mut SnapshotWriter) {
+        writer.put_u8(self.misaligned as u8);
+        writer.put_bool(self.reservation.is_some());
+        writer.put_u64(self.reservation.unwrap_or(0));
+
+        writer.put_u64(self.regions.len() as u64);
+        for region in self.regions.iter() {
+            writer.put_u64(region.base);
+            writer.put_u64(region.size);
+            writer.put_bool(region.permissions.read);
+            writer.put_bool(region.permissions.write);
+            writer.put_bool(region.permissions.execute);
+
+            // all-zero pages read the same as absent ones and are left out
+            let mut indices: Vec<_> = region
+                .pages
+                .iter()
+                .filter(|(_, page)| page.iter().any(|byte| *byte != 0))
+                .map(|(index, _)| *index)
+                .collect();
+            indices.sort_unstable();
+            writer.put_u64(indices.len() as u64);
+            for index in indices {
+                writer.put_u64(index);
+                writer.put_bytes(&region.pages[&index][..]);
+            }
+        }
+    }
+
+    pub fn restore(reader: &mut SnapshotReader) -> Result<Self> {
+        let misaligned = match reader.get_u8()? {
+            0 => MisalignedPolicy::Trap,
+            1 => MisalignedPolicy::Emulate,
+            policy => bail!("invalid misaligned policy in snapshot: {}", policy),
+        };
+        let is_reserved = reader.get_bool()?;
+        let reserved = reader.get_u64()?;
+
+        let mut regions: Vec<MemoryRegion> = vec![];
+        for _ in 0..reader.get_u64()? {
+            let base = reader.get_u64()?;
+            let size = reader.get_u64()?;
+            // the same layout rules as a machine file
+            ensure!(
+                size > 0 && size % 4 == 0 && base % 4 == 0,
+                "region {:#x} must be word aligned and not empty in snapshot",
+                base
+            );
+            ensure!(
+                base.checked_add(size).is_some(),
+                "region {:#x} exceeds the address space in snapshot",
+                base
+            );
+            ensure!(
+                regions
+                    .iter()
+                    .all(|other| base + size <= other.base || other.base + other.size <= base),
+                "region {:#x} overlaps another in snapshot",
+                base
+            );
+            let permissions = Permissions {
+                read: reader.get_bool()?,
+                write: reader.get_bool()?,
+                execute: reader.get_bool()?,
+            };
+
+            let mut pages = HashMap::new();
+            for _ in 0..reader.get_u64()? {
+                let index = reader.get_u64()?;
+                let bytes = reader.get_bytes(PAGE_SIZE as usize)?;
+                if index >= size.div_ceil(PAGE_SIZE) {
+                    bail!("page {:#x} is out of its region in snapshot", index);
+                }
+                pages.insert(index, Box::new(bytes.try_into()?));
+            }
+
+            regions.push(MemoryRegion {
+                base,
+                size,
+                permissions,
+                pages,
+            });
+        }
+
+        ensure!(!regions.is_empty(), "no memory region in snapshot");
+
+        Ok(Self {
+            regions,
+            misaligned,
+            reservation: is_reserved.then_some(reserved),
+        })
+    }
+
+    /// Number of pages allocated so far, over all regions.
+    pub fn resident_pages(&self) -> usize {
+        self.regions.iter().map(|region| region.pages.len()).sum()
     }
 }
 
+impl Default for MainMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum MainMemoryErrorType {
     AddressOutOfBounds,
+    PermissionDenied(Access),
+    MisalignedAccess(Access),
+    MisalignedAtomicAccess,
 }
 
 impl Display for MainMemoryErrorType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::AddressOutOfBounds => {
-                write!(f, "violate address bounds by memory access")
-            }
+            Self::AddressOutOfBounds => write!(f, "no memory region"),
+            Self::PermissionDenied(access) => write!(f, "{} is not permitted", access),
+            Self::MisalignedAccess(access) => write!(f, "misaligned {}", access),
+            Self::MisalignedAtomicAccess => write!(f, "misaligned atomic access"),
         }
     }
 }
 
-pub struct MainMemoryError {
-    error_type: MainMemoryErrorType,
-}
+#[cfg(test)]
+mod tests {
+    use crate::processor::decoder::instruction::ByteWideOption;
+
+    use crate::{
+        machine::{MachineConfig, RegionConfig},
+        snapshot::{SnapshotReader, SnapshotWriter},
+    };
+
+    use super::{MainMemory, MisalignedPolicy, Permissions};
+
+    #[test]
+    fn test_load_reserved_store_conditional() {
+        let mut memory = MainMemory::new();
+        memory.write(0x10, 5, &ByteWideOption::Word).ok().unwrap();
+
+        assert_eq!(memory.load_reserved(0x10).ok().unwrap(), 5);
+        assert!(memory.store_conditional(0x10, 6).ok().unwrap());
+        assert_eq!(memory.read(0x10, &ByteWideOption::Word).ok().unwrap(), 6);
+
+        // the reservation is consumed by the first SC
+        assert!(!memory.store_conditional(0x10, 7).ok().unwrap());
+        assert_eq!(memory.read(0x10, &ByteWideOption::Word).ok().unwrap(), 6);
 
-impl MainMemoryError {
-    fn new(error_type: MainMemoryErrorType) -> Box<Self> {
-        let error = Self { error_type };
-        Box::new(error)
+        // another address does not match the reservation
+        memory.load_reserved(0x10).ok().unwrap();
+        assert!(!memory.store_conditional(0x14, 7).ok().unwrap());
+
+        // an intervening store into the reserved word breaks it
+        memory.load_reserved(0x10).ok().unwrap();
+        memory
+            .write(0x12, 0xAB, &ByteWideOption::Byte)
+            .ok()
+            .unwrap();
+        assert!(!memory.store_conditional(0x10, 7).ok().unwrap());
+
+        // so does a misaligned double word with the reserved word in the middle
+        // of the three it touches
+        memory.load_reserved(0x10).ok().unwrap();
+        memory
+            .write(0xE, 0, &ByteWideOption::DoubleWord)
+            .ok()
+            .unwrap();
+        assert!(!memory.store_conditional(0x10, 7).ok().unwrap());
+
+        memory.load_reserved(0x10).ok().unwrap();
+        memory.clear_reservation();
+        assert!(!memory.store_conditional(0x10, 7).ok().unwrap());
     }
-}
 
-impl Display for MainMemoryError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.form())
+    #[test]
+    fn test_atomic_operation() {
+        let mut memory = MainMemory::new();
+        memory.write(0x20, 40, &ByteWideOption::Word).ok().unwrap();
+
+        let old = memory.atomic_operation(0x20, |v| v + 2).ok().unwrap();
+        assert_eq!(old, 40);
+        assert_eq!(memory.read(0x20, &ByteWideOption::Word).ok().unwrap(), 42);
+
+        assert!(memory.atomic_operation(0x22, |v| v).is_err());
+        assert!(memory.load_reserved(0x21).is_err());
+        assert!(memory.store_conditional(0x23, 0).is_err());
     }
-}
 
-impl ProcessorErrorTrait for MainMemoryError {
-    fn form(&self) -> String {
-        format!("memory access is failed - {}", self.error_type)
+    #[test]
+    fn test_extension_and_double_word() {
+        let mut memory = MainMemory::new();
+        memory
+            .write(0x30, 0x8000_0000_FFFF_FF80, &ByteWideOption::DoubleWord)
+            .ok()
+            .unwrap();
+
+        let read = |option| memory.read(0x30, &option).ok().unwrap();
+        assert_eq!(read(ByteWideOption::Byte), 0xFFFF_FFFF_FFFF_FF80);
+        assert_eq!(read(ByteWideOption::ByteUnsigned), 0x80);
+        assert_eq!(read(ByteWideOption::Word), 0xFFFF_FFFF_FFFF_FF80);
+        assert_eq!(read(ByteWideOption::WordUnsigned), 0xFFFF_FF80);
+        assert_eq!(read(ByteWideOption::DoubleWord), 0x8000_0000_FFFF_FF80);
+        assert_eq!(
+            memory.read(0x34, &ByteWideOption::Word).ok().unwrap(),
+            0xFFFF_FFFF_8000_0000
+        );
+    }
+
+    #[test]
+    fn test_regions() {
+        let rom: Permissions = "r-x".parse().unwrap();
+        let regions = [
+            RegionConfig::new("rom", 0x1000, 0x100, rom),
+            RegionConfig::new("dram", 0x8000_0000, 0x1000, "rw-".parse().unwrap()),
+        ];
+        let mut memory = MainMemory::with_regions(&regions);
+
+        // loading ignores permissions
+        memory.load(0x1000, &[0x0000_0013, 0xABCD_1234]).unwrap();
+        assert_eq!(memory.fetch_parcel(0x1004).ok().unwrap(), 0x1234);
+        assert_eq!(memory.fetch_parcel(0x1006).ok().unwrap(), 0xABCD);
+        assert_eq!(
+            memory
+                .read(0x1004, &ByteWideOption::WordUnsigned)
+                .ok()
+                .unwrap(),
+            0xABCD_1234
+        );
+        assert!(memory.write(0x1004, 0, &ByteWideOption::Word).is_err());
+
+        memory
+            .write(0x8000_0FFC, 7, &ByteWideOption::Word)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            memory
+                .read(0x8000_0FFC, &ByteWideOption::Word)
+                .ok()
+                .unwrap(),
+            7
+        );
+        assert!(memory.fetch_parcel(0x8000_0000).is_err());
+
+        // outside of every region
+        assert!(memory.read(0x1100, &ByteWideOption::Word).is_err());
+        assert!(memory.read(0x8000_1000, &ByteWideOption::Word).is_err());
+        assert!(memory.load(0x10FC, &[0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_sparse_pages() {
+        let regions = [RegionConfig::new(
+            "dram",
+            0x0,
+            0x1_0000_0000,
+            Permissions::all(),
+        )];
+        let mut memory = MainMemory::with_regions(&regions);
+        assert_eq!(memory.resident_pages(), 0);
+
+        // reads of untouched memory do not allocate
+        assert_eq!(
+            memory
+                .read(0xFFFF_FFFC, &ByteWideOption::Word)
+                .ok()
+                .unwrap(),
+            0
+        );
+        assert_eq!(memory.resident_pages(), 0);
+
+        memory
+            .write(0xFFFF_FFFC, 0x1234, &ByteWideOption::Word)
+            .ok()
+            .unwrap();
+        memory
+            .write(0x8000_0FFE, 0xAB, &ByteWideOption::Byte)
+            .ok()
+            .unwrap();
+        memory
+            .write(0x8000_0FFC, 0xCD, &ByteWideOption::Byte)
+            .ok()
+            .unwrap();
+        assert_eq!(memory.resident_pages(), 2);
+        assert_eq!(
+            memory
+                .read(0xFFFF_FFFC, &ByteWideOption::Word)
+                .ok()
+                .unwrap(),
+            0x1234
+        );
+        assert_eq!(
+            memory
+                .read(0x8000_0FFC, &ByteWideOption::WordUnsigned)
+                .ok()
+                .unwrap(),
+            0x00AB_00CD
+        );
+
+        // a double word across a page boundary touches both pages
+        memory
+            .write(0x1FFC, u64::MAX, &ByteWideOption::DoubleWord)
+            .ok()
+            .unwrap();
+        assert_eq!(memory.resident_pages(), 4);
+        assert_eq!(memory.read_double_word(0x1FFC).ok().unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn test_misaligned_emulate() {
+        let options = [
+            ByteWideOption::ByteUnsigned,
+            ByteWideOption::HalfWordUnsigned,
+            ByteWideOption::WordUnsigned,
+            ByteWideOption::DoubleWord,
+        ];
+        let pattern = 0x8877_6655_4433_2211u64;
+
+        for option in options.iter() {
+            let width = option.width();
+            let mask = if width == 8 {
+                u64::MAX
+            } else {
+                (1 << (width * 8)) - 1
+            };
+
+            for offset in 0..8 {
+                // the bytes around a word boundary and a page boundary
+                for base in [0x100, 0xFF8] {
+                    let mut memory = MainMemory::new();
+                    let address = base + offset;
+                    memory.write(address, pattern, option).ok().unwrap();
+                    assert_eq!(
+                        memory.read(address, option).ok().unwrap(),
+                        pattern & mask,
+                        "{} at {:#x}",
+                        option,
+                        address
+                    );
+
+                    // byte by byte, little-endian, nothing else touched
+                    for i in 0..16 {
+                        let byte = memory
+                            .read(base - 4 + i, &ByteWideOption::ByteUnsigned)
+                            .ok()
+                            .unwrap();
+                        let index = (base - 4 + i).wrapping_sub(address);
+                        let expected = if index < width {
+                            (pattern >> (index * 8)) & 0xFF
+                        } else {
+                            0
+                        };
+                        assert_eq!(byte, expected, "{} at {:#x}", option, address);
+                    }
+                }
+            }
+        }
+
+        let mut memory = MainMemory::new();
+        memory
+            .write(0x201, 0x8000, &ByteWideOption::HalfWord)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            memory.read(0x201, &ByteWideOption::HalfWord).ok().unwrap(),
+            0xFFFF_FFFF_FFFF_8000
+        );
+        memory
+            .write(0x203, 0x8000_0000, &ByteWideOption::Word)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            memory.read(0x203, &ByteWideOption::Word).ok().unwrap(),
+            0xFFFF_FFFF_8000_0000
+        );
+    }
+
+    #[test]
+    fn test_misaligned_trap() {
+        let config = MachineConfig {
+            misaligned: MisalignedPolicy::Trap,
+            ..MachineConfig::default()
+        };
+        let mut memory = MainMemory::with_config(&config);
+
+        let options = [
+            ByteWideOption::Byte,
+            ByteWideOption::HalfWord,
+            ByteWideOption::Word,
+            ByteWideOption::DoubleWord,
+        ];
+        for option in options.iter() {
+            for offset in 0..8 {
+                let address = 0x100 + offset;
+                let aligned = offset % option.width() == 0;
+                assert_eq!(memory.write(address, 1, option).is_ok(), aligned);
+                assert_eq!(memory.read(address, option).is_ok(), aligned);
+            }
+        }
+    }
+
+    #[test]
+    fn test_access_across_regions() {
+        let regions = [
+            RegionConfig::new("a", 0x0, 0x1000, Permissions::all()),
+            RegionConfig::new("b", 0x1000, 0x1000, "r--".parse().unwrap()),
+        ];
+        let mut memory = MainMemory::with_regions(&regions);
+        memory.load(0xFFC, &[0x4433_2211, 0x8877_6655]).unwrap();
+        assert_eq!(
+            memory
+                .read(0xFFE, &ByteWideOption::WordUnsigned)
+                .ok()
+                .unwrap(),
+            0x6655_4433
+        );
+
+        // the store faults on its last byte and writes nothing
+        assert!(memory.write(0xFFE, 0, &ByteWideOption::Word).is_err());
+        assert_eq!(
+            memory
+                .read(0xFFC, &ByteWideOption::WordUnsigned)
+                .ok()
+                .unwrap(),
+            0x4433_2211
+        );
+        assert!(memory.read(0x1FFE, &ByteWideOption::Word).is_err());
+    }
+
+    #[test]
+    fn test_restore_regions() {
+        // (base, size) of every region, with no pages
+        let restore = |regions: &[(u64, u64)]| {
+            let mut writer = SnapshotWriter::new();
+            writer.put_u8(MisalignedPolicy::Emulate as u8);
+            writer.put_bool(false);
+            writer.put_u64(0);
+            writer.put_u64(regions.len() as u64);
+            for (base, size) in regions {
+                writer.put_u64(*base);
+                writer.put_u64(*size);
+                for _ in 0..3 {
+                    writer.put_bool(true);
+                }
+                writer.put_u64(0);
+            }
+            let snapshot = writer.finish();
+            let mut reader = SnapshotReader::new(&snapshot).unwrap();
+            MainMemory::restore(&mut reader)
+        };
+
+        let memory = restore(&[(0x0, 0x1000), (0x1000, 0x1000)]).unwrap();
+        assert_eq!(memory.head(2), [0, 0]);
+        assert!(restore(&[]).is_err());
+        assert!(restore(&[(0x0, 0)]).is_err());
+        assert!(restore(&[(0x2, 0x1000)]).is_err());
+        assert!(restore(&[(0xFFFF_FFFF_FFFF_F000, 0x2000)]).is_err());
+        assert!(restore(&[(0x0, 0x2000), (0x1000, 0x1000)]).is_err());
     }
 }
@@ -1,35 +1,151 @@
+pub mod block;
+pub mod coverage;
+pub mod csr;
 pub mod decoder;
+pub mod error;
 pub mod executer;
 pub mod fetcher;
+pub mod history;
+pub mod hook;
+pub mod pipeline;
+pub mod predictor;
+pub mod profiler;
 pub mod register;
+pub mod stats;
+pub mod xlen;
 
-use std::path::Path;
+pub use self::error::{ErrorContext, ProcessorError};
 
-use anyhow::Result;
+use std::{fs, path::Path, rc::Rc};
 
-use crate::memory::MainMemory;
+use anyhow::{bail, Result};
+
+use crate::{
+    cache::{CacheHierarchy, HierarchyConfig},
+    elf::{DebugInfo, ElfImage, ELF_MAGIC},
+    machine::MachineConfig,
+    memory::{MainMemory, MainMemoryErrorType},
+    snapshot::{SnapshotReader, SnapshotWriter},
+};
 
 use self::{
-    decoder::{decode, instruction::InstructionCode},
-    executer::execute,
-    fetcher::{Fetcher, INSTRUCTION_MEMORY_INIT},
-    register::Register,
+    block::{Block, BlockCache},
+    coverage::Coverage,
+    csr::{ControlStatusRegister, RoundingMode},
+    decoder::{
+        cache::DecodeCache,
+        decode,
+        instruction::{
+            AtomicCode, ByteWideOption, CsrCode, FloatFormat, Instruction, InstructionCode,
+            RiscvInstruction,
+        },
+    },
+    executer::{
+        execute,
+        float::{float_operation, fused_operation},
+        unit::{atomic_operation, csr_operation},
+    },
+    fetcher::Fetcher,
+    history::{History, UndoRecord},
+    hook::{Hook, HookAction},
+    pipeline::Pipeline,
+    predictor::{BranchPredictor, BranchProfile},
+    profiler::{CallProfiler, ProfileWeight},
+    register::{nan_box, FloatRegister, Register},
+    stats::ExecutionStats,
+    xlen::Xlen,
 };
 
+// the per-stage log of `step`, printed unless tracing is turned off
+macro_rules! trace {
+    ($cpu:expr, $($arg:tt)*) => {
+        if $cpu.trace {
+            println!($($arg)*);
+        }
+    };
+}
+
 pub struct Processor {
     fetcher: Fetcher,
     register: Register,
+    float_register: FloatRegister,
+    csr: ControlStatusRegister,
     memory: MainMemory,
+    xlen: Xlen,
     is_halt: bool,
+    // last instruction retired, for embedders inspecting the run
+    retired: Option<Rc<Instruction>>,
+    // undo log for step_back, when enabled
+    history: Option<History>,
+    decode_cache: Option<DecodeCache>,
+    block_cache: BlockCache,
+    // timing model fed by retired instructions, when enabled
+    pipeline: Option<Pipeline>,
+    // branch predictor under evaluation, when set
+    predictor: Option<BranchProfile>,
+    // cache model in front of fetch and data accesses, when configured
+    caches: Option<CacheHierarchy>,
+    // instruction mix and hot spots, when enabled
+    stats: Option<ExecutionStats>,
+    // shadow call stack, when profiling
+    profiler: Option<CallProfiler>,
+    // execution counts per address and branch, when measuring coverage
+    coverage: Option<Coverage>,
+    // symbols and source lines of the loaded program, for traces and reports
+    debug: DebugInfo,
+    // user instrumentation, called on the events of every step
+    hooks: Vec<Box<dyn Hook>>,
+    // a hook asked to stop after the current instruction
+    stop_requested: bool,
+    trace: bool,
 }
 
 impl Processor {
     pub fn new() -> Self {
+        Self::build(&MachineConfig::default())
+    }
+
+    pub fn with_xlen(xlen: Xlen) -> Self {
+        Self::build(&MachineConfig {
+            xlen,
+            ..MachineConfig::default()
+        })
+    }
+
+    /// Builds a processor on the memory map of `config`, after validating it.
+    pub fn with_config(config: &MachineConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self::build(config))
+    }
+
+    fn build(config: &MachineConfig) -> Self {
+        let mut register = Register::new();
+        if let Some(sp) = config.stack_pointer {
+            register.write(2, config.xlen.truncate(sp)).ok().unwrap();
+        }
+
         Self {
-            fetcher: Fetcher::new(),
-            register: Register::new(),
-            memory: MainMemory::new(),
+            fetcher: Fetcher::new(config.reset_vector),
+            register,
+            float_register: FloatRegister::new(),
+            csr: ControlStatusRegister::new(),
+            memory: MainMemory::with_config(config),
+            xlen: config.xlen,
             is_halt: false,
+            retired: None,
+            history: None,
+            decode_cache: Some(DecodeCache::new()),
+            block_cache: BlockCache::new(),
+            pipeline: None,
+            predictor: None,
+            caches: config.cache.as_ref().map(CacheHierarchy::new),
+            stats: None,
+            profiler: None,
+            coverage: None,
+            debug: DebugInfo::default(),
+            hooks: vec![],
+            stop_requested: false,
+            trace: true,
         }
     }
 
@@ -37,34 +153,572 @@ impl Processor {
     where
         P: AsRef<Path>,
     {
-        let mut fetcher = Fetcher::new();
-        fetcher.load_hex(path, INSTRUCTION_MEMORY_INIT)?;
+        Self::init_with_xlen(path, Xlen::default())
+    }
 
-        let cpu = Self {
-            fetcher,
-            register: Register::new(),
-            memory: MainMemory::new(),
-            is_halt: false,
+    pub fn init_with_xlen<P>(path: P, xlen: Xlen) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let config = MachineConfig {
+            xlen,
+            ..MachineConfig::default()
         };
+        Self::init_with_config(path, &config)
+    }
+
+    /// Loads an ELF executable at its own addresses and entry point, or otherwise
+    /// a hex program at the reset vector of `config`.
+    pub fn init_with_config<P>(path: P, config: &MachineConfig) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut cpu = Self::with_config(config)?;
+        let bytes = fs::read(&path)?;
+        if bytes.starts_with(&ELF_MAGIC) {
+            cpu.load_elf(&ElfImage::parse(&bytes)?)?;
+        } else {
+            cpu.memory.load_hex(path, config.reset_vector)?;
+        }
         Ok(cpu)
     }
 
+    /// Serializes the whole processor state, memory included.
+    pub fn save(&self, writer: &mut SnapshotWriter) {
+        writer.put_u32(self.xlen.bits());
+        writer.put_u64(self.fetcher.pc);
+        self.register.save(writer);
+        self.float_register.save(writer);
+        self.csr.save(writer);
+        self.memory.save(writer);
+        writer.put_bool(self.is_halt);
+    }
+
+    pub fn restore(reader: &mut SnapshotReader) -> Result<Self> {
+        let xlen = match reader.get_u32()? {
+            32 => Xlen::X32,
+            64 => Xlen::X64,
+            bits => bail!("invalid xlen in snapshot: {}", bits),
+        };
+        let pc = reader.get_u64()?;
+
+        Ok(Self {
+            fetcher: Fetcher::new(pc),
+            register: Register::restore(reader)?,
+            float_register: FloatRegister::restore(reader)?,
+            csr: ControlStatusRegister::restore(reader)?,
+            memory: MainMemory::restore(reader)?,
+            xlen,
+            is_halt: reader.get_bool()?,
+            retired: None,
+            history: None,
+            decode_cache: Some(DecodeCache::new()),
+            block_cache: BlockCache::new(),
+            pipeline: None,
+            predictor: None,
+            caches: None,
+            stats: None,
+            profiler: None,
+            coverage: None,
+            debug: DebugInfo::default(),
+            hooks: vec![],
+            stop_requested: false,
+            trace: true,
+        })
+    }
+
+    pub fn xlen(&self) -> Xlen {
+        self.xlen
+    }
+
+    /// Places a program at `address`, ignoring region permissions.
+    pub fn load(&mut self, address: u64, words: &[u32]) -> Result<()> {
+        self.memory.load(address, words)?;
+        self.clear_code_caches();
+        Ok(())
+    }
+
+    /// Places raw bytes at `address`, ignoring region permissions.
+    pub fn load_bytes(&mut self, address: u64, bytes: &[u8]) -> Result<()> {
+        self.memory.load_bytes(address, bytes)?;
+        self.clear_code_caches();
+        Ok(())
+    }
+
+    /// Places the segments of `image` and starts at its entry point.
+    pub fn load_elf(&mut self, image: &ElfImage) -> Result<()> {
+        if image.xlen != self.xlen {
+            bail!(
+                "{}-bit executable on a {}-bit processor",
+                image.xlen.bits(),
+                self.xlen.bits()
+            );
+        }
+
+        for segment in image.segments.iter() {
+            self.memory.load_bytes(segment.address, &segment.data)?;
+        }
+        self.clear_code_caches();
+        self.fetcher = Fetcher::new(image.entry);
+        self.debug = image.debug_info();
+        Ok(())
+    }
+
+    /// Names and source lines of the guest code, for programs loaded from hex
+    /// next to their ELF.
+    pub fn set_debug_info(&mut self, debug: DebugInfo) {
+        self.debug = debug;
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug
+    }
+
+    /// `address` in hex, followed by its source line and function when known.
+    pub fn describe(&self, address: u64) -> String {
+        match self.debug.locate(address) {
+            Some(location) => format!("{:#010x} ({})", address, location),
+            None => format!("{:#010x}", address),
+        }
+    }
+
+    /// Disassembly of `start..end`, labelled with symbols and source lines.
+    pub fn disassemble(&self, start: u64, end: u64) -> String {
+        let mut listing = String::new();
+        let mut source = None;
+        let mut address = start;
+        while address < end {
+            if let Some(symbol) = self.debug.symbols.lookup(address) {
+                if symbol.address == address {
+                    listing += &format!("\n{}:\n", symbol.name);
+                }
+            }
+            let line = self.debug.lines.lookup(address);
+            if let Some((file, number)) = line.filter(|_| line != source) {
+                listing += &format!("  ; {}:{}\n", file, number);
+            }
+            source = line;
+
+            let Some(inst) = self.instruction_at(address) else {
+                listing += &format!("{:08x}:  <not an instruction>\n", address);
+                break;
+            };
+            let raw = match inst.size() {
+                2 => format!("{:04x}    ", inst.raw()),
+                _ => format!("{:08x}", inst.raw()),
+            };
+            listing += &format!("{:08x}:  {}  {}\n", address, raw, inst.assembly());
+            address += inst.size();
+        }
+        listing
+    }
+
+    /// Starts timing retired instructions on the 5-stage pipeline model.
+    pub fn enable_pipeline(&mut self, forwarding: bool) {
+        self.pipeline = Some(Pipeline::new(forwarding));
+    }
+
+    pub fn pipeline(&self) -> Option<&Pipeline> {
+        self.pipeline.as_ref()
+    }
+
+    /// Evaluates `predictor` on every branch and jump from now on.
+    pub fn set_branch_predictor(&mut self, predictor: Box<dyn BranchPredictor>) {
+        self.predictor = Some(BranchProfile::new(predictor));
+    }
+
+    pub fn branch_profile(&self) -> Option<&BranchProfile> {
+        self.predictor.as_ref()
+    }
+
+    /// Puts the caches of `config` in front of fetch and data accesses, replacing
+    /// any already there. Misses stall the pipeline model when it is on.
+    pub fn enable_caches(&mut self, config: &HierarchyConfig) -> Result<()> {
+        config.validate()?;
+        self.caches = Some(CacheHierarchy::new(config));
+        Ok(())
+    }
+
+    pub fn caches(&self) -> Option<&CacheHierarchy> {
+        self.caches.as_ref()
+    }
+
+    /// Starts counting the instruction mix and hot spots from now on.
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(ExecutionStats::new());
+    }
+
+    pub fn stats(&self) -> Option<&ExecutionStats> {
+        self.stats.as_ref()
+    }
+
+    /// Starts attributing retired instructions, or pipeline cycles, to call paths.
+    pub fn enable_profiler(&mut self, weight: ProfileWeight) {
+        self.profiler = Some(CallProfiler::new(weight));
+    }
+
+    pub fn profiler(&self) -> Option<&CallProfiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Starts counting executions of every address and branch direction.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Calls `hook` on the events of every step from now on, after the hooks
+    /// added before it.
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Removes and returns the hooks, to read what they gathered.
+    pub fn take_hooks(&mut self) -> Vec<Box<dyn Hook>> {
+        std::mem::take(&mut self.hooks)
+    }
+
+    /// Whether a hook asked to stop during the last step. Runs stop on it.
+    pub fn stop_requested(&self) -> bool {
+        self.stop_requested
+    }
+
+    // calls every hook with the pc of the current instruction
+    fn fire<F>(&mut self, event: F)
+    where
+        F: Fn(&mut dyn Hook, u64) -> HookAction,
+    {
+        let pc = self.fetcher.pc;
+        for hook in self.hooks.iter_mut() {
+            if event(hook.as_mut(), pc) == HookAction::Stop {
+                self.stop_requested = true;
+            }
+        }
+    }
+
+    /// Turns the per-stage log of every step on or off.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    pub fn trace(&self) -> bool {
+        self.trace
+    }
+
+    /// Turns the decoded-instruction cache on or off.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(DecodeCache::new);
+    }
+
+    /// Drops decoded instructions and blocks on the pages written by `width` bytes
+    /// at `address`. Returns whether any translated block was dropped.
+    fn invalidate_code(&mut self, address: u64, width: u64) -> bool {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(address, width);
+        }
+        self.block_cache.invalidate(address, width)
+    }
+
+    fn clear_code_caches(&mut self) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+        }
+        self.block_cache.clear();
+    }
+
+    /// Whether tracing, the undo log or a timing model needs every step to be seen.
+    fn is_observed(&self) -> bool {
+        self.trace
+            || self.history.is_some()
+            || self.pipeline.is_some()
+            || self.predictor.is_some()
+            || self.caches.is_some()
+            || self.stats.is_some()
+            || self.profiler.is_some()
+            || self.coverage.is_some()
+            || !self.hooks.is_empty()
+    }
+
+    /// Runs translated basic blocks until the processor halts or at least `limit`
+    /// instructions have run, checked between blocks. Returns how many ran.
+    ///
+    /// Instructions the block engine does not translate, and every instruction
+    /// while anything watches single steps, go through `step`, the reference path.
+    pub fn run_blocks(&mut self, limit: u64) -> Result<u64, ProcessorError> {
+        let mut count = 0;
+        // pc of the last op retired, decoded into `retired` only on the way out
+        let mut retired = None;
+        // a stop asked for in an earlier run does not end this one
+        self.stop_requested = false;
+
+        while !self.is_halt && !self.stop_requested && count < limit {
+            let pc = self.fetcher.pc;
+            let block = if self.is_observed() {
+                None
+            } else if let Some(block) = self.block_cache.get(pc) {
+                Some(block)
+            } else {
+                Block::translate(self, pc).map(|block| self.block_cache.insert(block))
+            };
+
+            let Some(block) = block else {
+                self.step()?;
+                count += 1;
+                retired = None;
+                continue;
+            };
+
+            let mut next = block.end;
+            for (pc, op) in block.pcs.iter().zip(block.ops.iter()) {
+                match op(self) {
+                    Ok(None) => {
+                        count += 1;
+                        retired = Some(*pc);
+                    }
+                    Ok(Some(target)) => {
+                        count += 1;
+                        retired = Some(*pc);
+                        next = target;
+                        break;
+                    }
+                    Err(error) => {
+                        // precise: the faulting instruction has not retired
+                        self.fetcher.update_program_counter(*pc);
+                        self.memory.clear_reservation();
+                        self.set_retired(retired);
+                        return Err(self.fault(error, *pc));
+                    }
+                }
+            }
+            self.fetcher.update_program_counter(next);
+        }
+
+        self.set_retired(retired);
+        Ok(count)
+    }
+
+    fn set_retired(&mut self, pc: Option<u64>) {
+        if let Some(pc) = pc {
+            self.retired = self.instruction_at(pc).map(Rc::new);
+        }
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.fetcher.pc
+    }
+
+    /// Moves execution to `pc`, as a debugger does.
+    pub fn set_pc(&mut self, pc: u64) {
+        self.fetcher.update_program_counter(self.xlen.truncate(pc));
+    }
+
+    /// Reads integer register `index`, from 0 to 31.
+    pub fn register(&self, index: u8) -> Result<u64, ProcessorError> {
+        self.register.read(index)
+    }
+
+    /// Writes integer register `index`, cut down to the register width.
+    /// Writes to x0 are ignored as in hardware.
+    pub fn set_register(&mut self, index: u8, value: u64) -> Result<(), ProcessorError> {
+        self.register.write(index, self.xlen.truncate(value))
+    }
+
+    /// Copies memory at `address` into `buf`, ignoring region permissions.
+    pub fn read_memory(&self, address: u64, buf: &mut [u8]) -> Result<(), ProcessorError> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let Some(value) = self.memory.peek(address.wrapping_add(i as u64), 1) else {
+                let kind = MainMemoryErrorType::AddressOutOfBounds;
+                return Err(ProcessorError::memory(kind, address, buf.len() as u64));
+            };
+            *byte = value as u8;
+        }
+        Ok(())
+    }
+
+    /// Copies `data` to `address`, ignoring region permissions. Nothing is written
+    /// unless every byte is inside a region.
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), ProcessorError> {
+        let width = data.len() as u64;
+        if (0..width).any(|i| self.memory.peek(address.wrapping_add(i), 1).is_none()) {
+            let kind = MainMemoryErrorType::AddressOutOfBounds;
+            return Err(ProcessorError::memory(kind, address, width));
+        }
+
+        for (i, byte) in data.iter().enumerate() {
+            self.memory
+                .poke(address.wrapping_add(i as u64), 1, *byte as u64);
+        }
+        self.clear_code_caches();
+        Ok(())
+    }
+
+    /// The instruction retired last, by `step` or by the block engine.
+    pub fn last_instruction(&self) -> Option<&Instruction> {
+        self.retired.as_deref()
+    }
+
+    /// Decodes the instruction in memory at `address`, if any.
+    pub fn instruction_at(&self, address: u64) -> Option<Instruction> {
+        let raw = Fetcher::fetch_at(&self.memory, address).ok()?;
+        decode(raw, &self.xlen).ok()
+    }
+
+    /// Starts recording an undo log of the last `limit` steps.
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history = Some(History::new(limit));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Undoes up to `n` recorded steps and returns how many were undone.
+    pub fn step_back(&mut self, n: usize) -> usize {
+        for count in 0..n {
+            if !self.undo() {
+                return count;
+            }
+        }
+        n
+    }
+
+    /// Undoes steps until the pc reaches one of `breakpoints` or the history runs out.
+    /// Returns how many steps were undone.
+    pub fn reverse_continue(&mut self, breakpoints: &[u64]) -> usize {
+        let mut count = 0;
+        while self.undo() {
+            count += 1;
+            if breakpoints.contains(&self.fetcher.pc) {
+                break;
+            }
+        }
+        count
+    }
+
+    fn undo(&mut self) -> bool {
+        let Some(record) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+
+        for (address, width, old) in record.memory.iter().rev() {
+            self.memory.poke(*address, *width, *old);
+            self.invalidate_code(*address, *width);
+        }
+        for (index, old) in record.registers.iter().rev() {
+            self.register.write(*index, *old).ok().unwrap();
+        }
+        for (index, old) in record.float_registers.iter().rev() {
+            self.float_register.write(*index, *old).ok().unwrap();
+        }
+        self.csr = record.csr;
+        self.memory.set_reservation(record.reservation);
+        self.is_halt = record.is_halt;
+        self.fetcher.update_program_counter(record.pc);
+        true
+    }
+
+    fn log_register(&mut self, index: u8) {
+        if let Some(record) = self.history.as_mut().and_then(History::current) {
+            if let Ok(old) = self.register.read(index) {
+                record.registers.push((index, old));
+            }
+        }
+    }
+
+    fn log_float_register(&mut self, index: u8) {
+        if let Some(record) = self.history.as_mut().and_then(History::current) {
+            if let Ok(old) = self.float_register.read(index) {
+                record.float_registers.push((index, old));
+            }
+        }
+    }
+
+    fn log_memory(&mut self, address: u64, width: u64) {
+        if let Some(record) = self.history.as_mut().and_then(History::current) {
+            if let Some(old) = self.memory.peek(address, width) {
+                record.memory.push((address, width, old));
+            }
+        }
+    }
+
     pub fn step(&mut self) -> Result<(), ProcessorError> {
-        // fetch
-        let inst = self.fetcher.fetch();
+        if let Some(history) = &mut self.history {
+            let record = UndoRecord::new(
+                self.fetcher.pc,
+                self.is_halt,
+                self.csr.clone(),
+                self.memory.reservation(),
+            );
+            history.begin(record);
+        }
+
+        self.stop_requested = false;
         let pc = self.fetcher.pc;
+        let res = self.step_instruction();
+        if res.is_err() {
+            // the instruction did not retire, so there is no step to undo
+            if let Some(history) = &mut self.history {
+                history.pop();
+            }
+            // a faulting instruction abandons any LR/SC sequence in flight
+            self.memory.clear_reservation();
+        }
+        res.map_err(|error| {
+            let error = self.fault(error, pc);
+            self.fire(|hook, pc| hook.trap(pc, &error));
+            error
+        })
+    }
+
+    // attaches the instruction at `pc`, which has not retired, to `error`
+    fn fault(&self, error: ProcessorError, pc: u64) -> ProcessorError {
+        let raw = Fetcher::fetch_at(&self.memory, pc).ok();
+        error.at(pc, raw)
+    }
 
-        println!("[fetch] instruction: {:#06x} | {:#010x}", pc, inst);
+    fn step_instruction(&mut self) -> Result<(), ProcessorError> {
+        // fetch and decode, both skipped for an instruction in the decode cache
+        let pc = self.fetcher.pc;
+        let cached = self.decode_cache.as_ref().and_then(|cache| cache.get(pc));
+        let raw = match &cached {
+            Some(inst) => inst.raw(),
+            None => self.fetcher.fetch(&self.memory)?,
+        };
 
-        if inst == 0x0000006f {
-            // halt:
+        trace!(
+            self,
+            "[fetch] instruction: {:#06x} | {:#010x}{}",
+            pc,
+            raw,
+            self.debug
+                .locate(pc)
+                .map_or(String::new(), |location| format!("  ; {}", location))
+        );
+
+        if raw == 0x0000006f || raw == 0xa001 {
+            // halt: jal x0, 0 / c.j 0
             self.is_halt = true;
         }
 
-        // decode
-        let inst = decode(inst)?;
+        let inst = match cached {
+            Some(inst) => inst,
+            None => {
+                let inst = decode(raw, &self.xlen)?;
+                match &mut self.decode_cache {
+                    Some(cache) => cache.insert(pc, inst),
+                    None => Rc::new(inst),
+                }
+            }
+        };
 
-        println!("[decode] {}", inst);
+        trace!(self, "[decode] {}", inst);
 
         if inst.is_halt {
             self.is_halt = true;
@@ -75,80 +729,750 @@ impl Processor {
         let rs1 = self.register.read(inst.rs1)?;
         let rs2 = self.register.read(inst.rs2)?;
 
-        println!("[reg read] rs1 = {}(@{:#04x})", rs1, inst.rs1);
-        println!("[reg read] rs2 = {}(@{:#04x})", rs2, inst.rs2);
+        trace!(self, "[reg read] rs1 = {}(@{:#04x})", rs1, inst.rs1);
+        trace!(self, "[reg read] rs2 = {}(@{:#04x})", rs2, inst.rs2);
+
+        let frs1 = self.float_register.read(inst.rs1)?;
+        let frs2 = self.float_register.read(inst.rs2)?;
+        let frs3 = self.float_register.read(inst.rs3)?;
+
+        if let InstructionCode::FloatOpe(_, _)
+        | InstructionCode::Fused(_, _)
+        | InstructionCode::StoreFloat(_) = &inst.code
+        {
+            trace!(
+                self,
+                "[freg read] frs1 = {:#018x}(@{:#04x})",
+                frs1,
+                inst.rs1
+            );
+            trace!(
+                self,
+                "[freg read] frs2 = {:#018x}(@{:#04x})",
+                frs2,
+                inst.rs2
+            );
+            trace!(
+                self,
+                "[freg read] frs3 = {:#018x}(@{:#04x})",
+                frs3,
+                inst.rs3
+            );
+        }
 
         // execution
-        let (mut rd, pc) = execute(&inst, rs1, rs2, pc);
+        let (mut rd, pc) = execute(&inst, rs1, rs2, pc, &self.xlen);
+        let mut frd = 0;
+
+        trace!(self, "[exec] (rd, pc) = ({}, {:#04x})", rd, pc);
+
+        // floating-point execution
+        match &inst.code {
+            InstructionCode::FloatOpe(code, fmt) => {
+                let rm = if code.uses_rounding_mode() {
+                    self.csr.rounding_mode(inst.rm)?
+                } else {
+                    RoundingMode::NearestEven
+                };
+                let (value, flags) = float_operation(code, fmt, rs1, frs1, frs2, rm);
+                self.csr.accrue_exceptions(flags);
+
+                if code.writes_integer() {
+                    rd = value;
+                } else {
+                    frd = value;
+                }
+
+                trace!(
+                    self,
+                    "[fexec] result = {:#018x}, fflags |= {:#07b}",
+                    value,
+                    flags
+                );
+            }
+            InstructionCode::Fused(code, fmt) => {
+                let rm = self.csr.rounding_mode(inst.rm)?;
+                let (value, flags) = fused_operation(code, fmt, [frs1, frs2, frs3], rm);
+                self.csr.accrue_exceptions(flags);
+                frd = value;
+
+                trace!(
+                    self,
+                    "[fexec] result = {:#018x}, fflags |= {:#07b}",
+                    value,
+                    flags
+                );
+            }
+            _ => {}
+        }
+
+        // csr read/write
+        if let InstructionCode::Csr(code) = &inst.code {
+            let address = inst.csr();
+            let operand = if code.is_immediate() {
+                inst.rs1 as u64
+            } else {
+                rs1
+            };
+
+            rd = self.csr.read(address)?;
 
-        println!("[exec] (rd, pc) = ({}, {:#04x})", rd, pc);
+            // csrrs/csrrc with x0 (or zimm = 0) do not write
+            let is_write = matches!(code, CsrCode::ReadWrite | CsrCode::ReadWriteImmediate);
+            let mut new = None;
+            if is_write || inst.rs1 != 0 {
+                let value = csr_operation(code, rd, operand);
+                self.csr.write(address, value)?;
+                new = Some(value);
+            }
+            let old = rd;
+            self.fire(|hook, pc| hook.csr_access(pc, address, old, new));
+
+            trace!(
+                self,
+                "[csr] rd(@{:#04x}) = csr[{:#05x}] = {}, operand = {}",
+                inst.rd,
+                address,
+                rd,
+                operand
+            );
+        }
 
         // memory read/write
+        let address = self.xlen.truncate(rs1.wrapping_add(inst.imm));
+        // (address, width, is_write) of the bytes the instruction accesses
+        let access = match &inst.code {
+            InstructionCode::Load(opt) => Some((address, opt.width(), false)),
+            InstructionCode::Store(opt) => Some((address, opt.width(), true)),
+            InstructionCode::LoadFloat(fmt) => Some((address, fmt.bytes(), false)),
+            InstructionCode::StoreFloat(fmt) => Some((address, fmt.bytes(), true)),
+            InstructionCode::Atomic(AtomicCode::LoadReserved, _) => Some((rs1, 4, false)),
+            InstructionCode::Atomic(_, _) => Some((rs1, 4, true)),
+            _ => None,
+        };
+        let store = access
+            .filter(|(_, _, is_write)| *is_write)
+            .map(|(address, width, _)| (address, width));
+        if let Some((address, width)) = store {
+            self.log_memory(address, width);
+        }
+
         match &inst.code {
             InstructionCode::Load(opt) => {
-                rd = self.memory.read(rs1.wrapping_add(inst.imm), &opt)?;
+                rd = self.memory.read(address, opt)?;
+                let (width, value) = (opt.width(), rd & width_mask(opt.width()));
+                self.fire(|hook, pc| hook.memory_read(pc, address, width, value));
 
-                println!(
+                trace!(
+                    self,
                     "[mem read] rd(@{:#04x}) = mem[{} + {}] = mem[{}({:#010x})] = {}",
                     inst.rd,
                     rs1,
-                    inst.imm,
-                    rs1.wrapping_add(inst.imm),
-                    rs1.wrapping_add(inst.imm),
+                    inst.imm as i64,
+                    address,
+                    address,
                     rd
                 );
             }
             InstructionCode::Store(opt) => {
-                self.memory.write(rs1.wrapping_add(inst.imm), rs2, &opt)?;
+                self.memory.write(address, rs2, opt)?;
+                let (width, value) = (opt.width(), rs2 & width_mask(opt.width()));
+                self.fire(|hook, pc| hook.memory_write(pc, address, width, value));
 
-                println!(
+                trace!(
+                    self,
                     "[mem write] mem[{} + {}] = mem[{}] <= rs2(@{:#04x}) = {}",
                     rs1,
-                    inst.imm,
-                    rs1.wrapping_add(inst.imm),
+                    inst.imm as i64,
+                    address,
                     inst.rs2,
                     rs2
                 );
             }
+            InstructionCode::Atomic(code, _) => {
+                // word-sized values are sign-extended into the register
+                rd = match code {
+                    AtomicCode::LoadReserved => {
+                        let value = self.memory.load_reserved(rs1)?;
+                        self.fire(|hook, pc| hook.memory_read(pc, rs1, 4, value as u64));
+                        value as i32 as u64
+                    }
+                    AtomicCode::StoreConditional => {
+                        // rd = 0 on success, 1 on failure
+                        let stored = self.memory.store_conditional(rs1, rs2 as u32)?;
+                        if stored {
+                            let value = rs2 as u32 as u64;
+                            self.fire(|hook, pc| hook.memory_write(pc, rs1, 4, value));
+                        }
+                        (!stored) as u64
+                    }
+                    _ => {
+                        let old = self
+                            .memory
+                            .atomic_operation(rs1, |mem| atomic_operation(code, mem, rs2 as u32))?;
+                        let new = atomic_operation(code, old, rs2 as u32);
+                        self.fire(|hook, pc| hook.memory_read(pc, rs1, 4, old as u64));
+                        self.fire(|hook, pc| hook.memory_write(pc, rs1, 4, new as u64));
+                        old as i32 as u64
+                    }
+                };
+
+                trace!(
+                    self,
+                    "[mem atomic] {} rd(@{:#04x}) = {}, mem[{:#010x}], rs2(@{:#04x}) = {}",
+                    code,
+                    inst.rd,
+                    rd,
+                    rs1,
+                    inst.rs2,
+                    rs2
+                );
+            }
+            InstructionCode::LoadFloat(fmt) => {
+                let value = match fmt {
+                    FloatFormat::Single => self.memory.read(address, &ByteWideOption::Word)?,
+                    FloatFormat::Double => self.memory.read_double_word(address)?,
+                } & width_mask(fmt.bytes());
+                let width = fmt.bytes();
+                self.fire(|hook, pc| hook.memory_read(pc, address, width, value));
+                frd = match fmt {
+                    FloatFormat::Single => nan_box(value as u32),
+                    FloatFormat::Double => value,
+                };
+
+                trace!(
+                    self,
+                    "[mem read] frd(@{:#04x}) = mem[{:#010x}] = {:#018x}",
+                    inst.rd,
+                    address,
+                    frd
+                );
+            }
+            InstructionCode::StoreFloat(fmt) => {
+                match fmt {
+                    FloatFormat::Single => {
+                        self.memory.write(address, frs2, &ByteWideOption::Word)?
+                    }
+                    FloatFormat::Double => self.memory.write_double_word(address, frs2)?,
+                }
+                let (width, value) = (fmt.bytes(), frs2 & width_mask(fmt.bytes()));
+                self.fire(|hook, pc| hook.memory_write(pc, address, width, value));
+
+                trace!(
+                    self,
+                    "[mem write] mem[{:#010x}] <= frs2(@{:#04x}) = {:#018x}",
+                    address,
+                    inst.rs2,
+                    frs2
+                );
+            }
             _ => (),
         }
 
         // register write
+        let rd = self.xlen.truncate(rd);
         match &inst.code {
             InstructionCode::Ope(_)
             | InstructionCode::OpeI(_)
+            | InstructionCode::OpeW(_)
+            | InstructionCode::OpeIW(_)
             | InstructionCode::Lui
             | InstructionCode::Auipc
             | InstructionCode::Jal
             | InstructionCode::Jalr
-            | InstructionCode::Load(_) => {
+            | InstructionCode::Load(_)
+            | InstructionCode::Atomic(_, _)
+            | InstructionCode::Csr(_) => {
+                self.log_register(inst.rd);
                 self.register.write(inst.rd, rd)?;
+                self.fire_register_write(inst.rd, rd);
 
-                println!("[reg write] rd(@{:#04x}) = {}", inst.rd, rd);
+                trace!(self, "[reg write] rd(@{:#04x}) = {}", inst.rd, rd);
+            }
+            InstructionCode::FloatOpe(code, _) if code.writes_integer() => {
+                self.log_register(inst.rd);
+                self.register.write(inst.rd, rd)?;
+                self.fire_register_write(inst.rd, rd);
+
+                trace!(self, "[reg write] rd(@{:#04x}) = {}", inst.rd, rd);
+            }
+            InstructionCode::FloatOpe(_, _)
+            | InstructionCode::Fused(_, _)
+            | InstructionCode::LoadFloat(_) => {
+                self.log_float_register(inst.rd);
+                self.float_register.write(inst.rd, frd)?;
+                let index = inst.rd;
+                self.fire(|hook, pc| hook.float_register_write(pc, index, frd));
+
+                trace!(self, "[freg write] frd(@{:#04x}) = {:#018x}", inst.rd, frd);
             }
             _ => {}
         }
 
+        // self-modifying code: drop stale decoded instructions
+        if let Some((address, width)) = store {
+            self.invalidate_code(address, width);
+        }
+        if let InstructionCode::FenceI = &inst.code {
+            self.clear_code_caches();
+        }
+
+        if let Some(caches) = &mut self.caches {
+            let fetch = caches.fetch(self.fetcher.pc, inst.size());
+            let memory = access.map_or(0, |(address, width, is_write)| {
+                caches.data(address, width, is_write)
+            });
+            if let Some(pipeline) = &mut self.pipeline {
+                pipeline.delay(fetch, memory);
+            }
+        }
+        let mut cycles = 1;
+        if let Some(pipeline) = &mut self.pipeline {
+            let before = pipeline.cycles();
+            pipeline.retire(&inst, self.fetcher.pc, pc);
+            cycles = pipeline.cycles() - before;
+        }
+        if let Some(predictor) = &mut self.predictor {
+            predictor.retire(&inst, self.fetcher.pc, pc);
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.retire(&inst, self.fetcher.pc, pc);
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.retire(&inst, self.fetcher.pc, pc, cycles);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.retire(&inst, self.fetcher.pc, pc);
+        }
+        self.fire(|hook, at| hook.retire(&inst, at, pc));
+        self.retired = Some(inst);
+
         // update pc
         self.fetcher.update_program_counter(pc);
 
-        println!("");
+        trace!(self, "");
 
         Ok(())
     }
 
+    fn fire_register_write(&mut self, index: u8, value: u64) {
+        if index != 0 {
+            self.fire(|hook, pc| hook.register_write(pc, index, value));
+        }
+    }
+
     pub fn is_halt(&self) -> bool {
         self.is_halt
     }
 
     pub fn logging(&self) {
         self.register.logging().ok().unwrap();
+        self.float_register.logging().ok().unwrap();
+        self.csr.logging();
         let mem = self.memory.head(10);
         println!("main: {:?}", mem);
     }
 }
 
-pub trait ProcessorErrorTrait {
-    fn form(&self) -> String;
+// the low `width` bytes of a value
+fn width_mask(width: u64) -> u64 {
+    match width {
+        8 => u64::MAX,
+        _ => (1 << (width * 8)) - 1,
+    }
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cache::{CacheConfig, HierarchyConfig},
+        elf::{DebugInfo, SymbolTable},
+        machine::{MachineConfig, RegionConfig},
+        memory::{MainMemoryErrorType, Permissions},
+        snapshot::{SnapshotReader, SnapshotWriter},
+    };
+
+    use super::{profiler::ProfileWeight, xlen::Xlen, Processor, ProcessorError};
+
+    // L: addi a0, zero, 1 is overwritten with addi a0, zero, 2 and run again
+    const SELF_MODIFYING: [u32; 11] = [
+        0x00000297, // auipc t0, 0
+        0x0282a303, // lw t1, 40(t0)
+        0x00000593, // addi a1, zero, 0
+        0x00100393, // addi t2, zero, 1
+        0x00100513, // L: addi a0, zero, 1
+        0x00158593, // addi a1, a1, 1
+        0x0062a823, // sw t1, 16(t0)
+        0x0000100f, // fence.i
+        0xfe7588e3, // beq a1, t2, L
+        0x0000006f, // jal zero, 0
+        0x00200513, // addi a0, zero, 2
+    ];
+
+    fn snapshot(cpu: &Processor) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new();
+        cpu.save(&mut writer);
+        writer.finish()
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut cpu = Processor::init("./example/instructions/ope.hex").unwrap();
+        for _ in 0..5 {
+            cpu.step().ok().unwrap();
+        }
+
+        let checkpoint = snapshot(&cpu);
+        let mut reader = SnapshotReader::new(&checkpoint).unwrap();
+        let mut restored = Processor::restore(&mut reader).unwrap();
+        reader.finish().unwrap();
+        assert_eq!(snapshot(&restored), checkpoint);
+
+        // both continue to the same final state
+        while !cpu.is_halt() {
+            cpu.step().ok().unwrap();
+        }
+        while !restored.is_halt() {
+            restored.step().ok().unwrap();
+        }
+        assert_eq!(snapshot(&restored), snapshot(&cpu));
+        assert_eq!(restored.memory.head(10), [1, 2, 3, 4, 5, 6, 7, 8, 0, 0]);
+
+        assert!(SnapshotReader::new(&checkpoint[..checkpoint.len() - 1])
+            .and_then(|mut reader| Processor::restore(&mut reader))
+            .is_err());
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut cpu = Processor::init("./example/instructions/ope.hex").unwrap();
+        cpu.enable_history(usize::MAX);
+
+        let mut states = vec![];
+        let mut pcs = vec![];
+        while !cpu.is_halt() {
+            states.push(snapshot(&cpu));
+            pcs.push(cpu.pc());
+            cpu.step().ok().unwrap();
+        }
+        let last = snapshot(&cpu);
+        assert_eq!(cpu.history_len(), states.len());
+
+        // every step is undone exactly, memory and halt flag included
+        for state in states.iter().rev() {
+            assert_eq!(cpu.step_back(1), 1);
+            assert_eq!(&snapshot(&cpu), state);
+        }
+        assert_eq!(cpu.step_back(1), 0);
+
+        // replaying gives the same final state
+        while !cpu.is_halt() {
+            cpu.step().ok().unwrap();
+        }
+        assert_eq!(snapshot(&cpu), last);
+
+        let target = states.len() / 2;
+        let undone = cpu.reverse_continue(&[pcs[target]]);
+        assert_eq!(cpu.pc(), pcs[target]);
+        assert_eq!(
+            undone,
+            states.len() - pcs.iter().rposition(|pc| *pc == pcs[target]).unwrap()
+        );
+        assert_eq!(cpu.step_back(usize::MAX), states.len() - undone);
+        assert_eq!(snapshot(&cpu), states[0]);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut cpu = Processor::init("./example/instructions/ope.hex").unwrap();
+        cpu.enable_history(3);
+        for _ in 0..5 {
+            cpu.step().ok().unwrap();
+        }
+        assert_eq!(cpu.history_len(), 3);
+        assert_eq!(cpu.step_back(10), 3);
+
+        cpu.disable_history();
+        cpu.step().ok().unwrap();
+        assert_eq!(cpu.step_back(1), 0);
+    }
+
+    #[test]
+    fn test_step_back_fault() {
+        let mut cpu = Processor::new();
+        cpu.set_trace(false);
+        cpu.enable_history(usize::MAX);
+        // addi a0, zero, 0x100; lr.w a1, (a0); lw a2, -4(zero)
+        cpu.load(cpu.pc(), &[0x10000513, 0x100525af, 0xffc02603])
+            .unwrap();
+        cpu.step().unwrap();
+        let before = snapshot(&cpu);
+        cpu.step().unwrap();
+        assert!(cpu.step().is_err());
+
+        // the fault is not a step, so stepping back undoes the lr.w
+        assert_eq!(cpu.history_len(), 2);
+        assert_eq!(cpu.step_back(1), 1);
+        assert_eq!(snapshot(&cpu), before);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        for enabled in [true, false] {
+            let mut cpu = Processor::new();
+            cpu.set_trace(false);
+            cpu.set_decode_cache(enabled);
+            cpu.load(cpu.pc(), &SELF_MODIFYING).unwrap();
+            while !cpu.is_halt() {
+                cpu.step().ok().unwrap();
+            }
+            assert_eq!(cpu.register.read(10).ok().unwrap(), 2);
+            assert_eq!(cpu.register.read(11).ok().unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn test_run_blocks() {
+        let tight_loop = [
+            0x00000293, // addi t0, zero, 0
+            0x3e800313, // addi t1, zero, 1000
+            0x00128293, // loop: addi t0, t0, 1
+            0x0053c3b3, // xor t2, t2, t0
+            0xfe629ce3, // bne t0, t1, loop
+            0x0000006f, // jal zero, 0
+        ];
+        let hex = Processor::init("./example/instructions/ope.hex").unwrap();
+        // the program words at the 0x2000 reset vector
+        let example = &hex.memory.head(0xC00)[0x800..];
+
+        for program in [&SELF_MODIFYING[..], &tight_loop, example] {
+            let mut reference = Processor::new();
+            reference.set_trace(false);
+            reference.load(reference.pc(), program).unwrap();
+            let mut steps = 0;
+            while !reference.is_halt() {
+                reference.step().ok().unwrap();
+                steps += 1;
+            }
+
+            let mut cpu = Processor::new();
+            cpu.set_trace(false);
+            cpu.load(cpu.pc(), program).unwrap();
+            assert_eq!(cpu.run_blocks(u64::MAX).ok().unwrap(), steps);
+            assert!(cpu.is_halt());
+            assert_eq!(snapshot(&cpu), snapshot(&reference));
+        }
+
+        // the limit is checked between blocks
+        let mut cpu = Processor::new();
+        cpu.set_trace(false);
+        cpu.load(cpu.pc(), &tight_loop).unwrap();
+        assert_eq!(cpu.run_blocks(10).ok().unwrap(), 11);
+        assert_eq!(cpu.register.read(5).ok().unwrap(), 3);
+        assert_eq!(cpu.pc(), 0x2008);
+    }
+
+    #[test]
+    fn test_run_blocks_fault() {
+        let program = [
+            0x00500513, // addi a0, zero, 5
+            0x800002b7, // lui t0, 0x80000
+            0x00a2a023, // sw a0, 0(t0)
+            0x00600513, // addi a0, zero, 6
+            0x0000006f, // jal zero, 0
+        ];
+        let mut cpu = Processor::new();
+        cpu.set_trace(false);
+        cpu.load(cpu.pc(), &program).unwrap();
+
+        // the store faults with the instructions before it retired
+        assert!(cpu.run_blocks(u64::MAX).is_err());
+        assert_eq!(cpu.pc(), 0x2008);
+        assert_eq!(cpu.register.read(10).ok().unwrap(), 5);
+        assert_eq!(cpu.register.read(5).ok().unwrap(), 0x8000_0000);
+    }
+
+    #[test]
+    fn test_pipeline_matches_functional_model() {
+        let mut reference = Processor::init("./example/instructions/ope.hex").unwrap();
+        reference.set_trace(false);
+        let mut cpu = Processor::init("./example/instructions/ope.hex").unwrap();
+        cpu.set_trace(false);
+        cpu.enable_pipeline(true);
+
+        let mut steps = 0;
+        while !cpu.is_halt() {
+            reference.step().ok().unwrap();
+            cpu.step().ok().unwrap();
+            steps += 1;
+        }
+        assert_eq!(snapshot(&cpu), snapshot(&reference));
+
+        let pipeline = cpu.pipeline().unwrap();
+        assert_eq!(pipeline.instructions(), steps);
+        // filling the pipeline takes four cycles, everything else is a stall or a flush
+        assert_eq!(
+            pipeline.cycles(),
+            steps + 4 + pipeline.data_stalls() + pipeline.flush_cycles()
+        );
+    }
+
+    #[test]
+    fn test_caches_keep_results() {
+        let mut reference = Processor::init("./example/instructions/ope.hex").unwrap();
+        reference.set_trace(false);
+        let config = MachineConfig::from_file("./example/machine/cached.toml").unwrap();
+        let mut cpu =
+            Processor::init_with_config("./example/instructions/ope.hex", &config).unwrap();
+        cpu.set_trace(false);
+        cpu.enable_pipeline(true);
+
+        while !cpu.is_halt() {
+            reference.step().ok().unwrap();
+            cpu.step().ok().unwrap();
+        }
+        assert_eq!(snapshot(&cpu), snapshot(&reference));
+
+        let caches = cpu.caches().unwrap();
+        let l1i = caches.l1i().unwrap().stats();
+        let pipeline = cpu.pipeline().unwrap();
+        assert_eq!(l1i.accesses(), pipeline.instructions());
+        assert!(l1i.misses() > 0 && pipeline.memory_stalls() > 0);
+        assert!(
+            pipeline.cycles()
+                > pipeline.instructions() + 4 + pipeline.data_stalls() + pipeline.flush_cycles()
+        );
+
+        // a cache with no whole set is refused rather than divided by
+        let config = HierarchyConfig {
+            l1d: Some(CacheConfig::new(100, 3, 64)),
+            ..HierarchyConfig::default()
+        };
+        assert!(cpu.enable_caches(&config).is_err());
+        assert!(cpu.caches().unwrap().l1i().is_some());
+        cpu.enable_caches(&HierarchyConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn test_run_elf() {
+        let mut cpu = Processor::init("./example/elf/calls.elf").unwrap();
+        cpu.set_trace(false);
+        assert_eq!(cpu.pc(), 0x2000);
+        cpu.enable_stats();
+        while !cpu.is_halt() {
+            cpu.step().ok().unwrap();
+        }
+        // 1 + 4 + ... + 100
+        assert_eq!(cpu.memory.head(1), [385]);
+
+        let symbols = SymbolTable::from_file("./example/elf/calls.elf").unwrap();
+        let report = cpu.stats().unwrap().report(Some(&symbols), 3);
+        assert_eq!(report.hot_functions[0].name, "square");
+        assert_eq!(report.branches[0].mnemonic, "beq");
+        assert_eq!(
+            report.mix.iter().map(|entry| entry.count).sum::<u64>(),
+            report.instructions
+        );
+
+        let rv64 = Processor::init_with_xlen("./example/elf/calls.elf", Xlen::X64);
+        assert!(rv64.is_err());
+    }
+
+    #[test]
+    fn test_profile_call_paths() {
+        let mut cpu = Processor::init("./example/elf/calls.elf").unwrap();
+        cpu.set_trace(false);
+        cpu.enable_profiler(ProfileWeight::Instructions);
+        let mut steps = 0;
+        while !cpu.is_halt() {
+            cpu.step().ok().unwrap();
+            steps += 1;
+        }
+
+        let profiler = cpu.profiler().unwrap();
+        assert_eq!(profiler.total(), steps);
+        assert_eq!(profiler.backtrace(), [0x2000]);
+
+        let symbols = SymbolTable::from_file("./example/elf/calls.elf").unwrap();
+        let folded = profiler.folded(&symbols);
+        let paths: Vec<_> = folded
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(
+            paths,
+            ["_start", "_start;sum_squares", "_start;sum_squares;square"]
+        );
+        // square runs 5 instructions plus 4 per unit of its argument, summed over 1..=10
+        assert!(folded.contains("_start;sum_squares;square 270\n"));
+    }
+
+    #[test]
+    fn test_coverage_lcov() {
+        let mut cpu = Processor::init("./example/elf/calls.elf").unwrap();
+        cpu.set_trace(false);
+        cpu.enable_coverage();
+        while !cpu.is_halt() {
+            cpu.step().ok().unwrap();
+        }
+
+        let coverage = cpu.coverage().unwrap();
+        // the loop test of square, once per unit of its argument and once to leave
+        assert_eq!(coverage.count(0x206c), 65);
+        assert_eq!(coverage.branch(0x206c), (10, 55));
+
+        let debug = DebugInfo::from_file("./example/elf/calls.elf").unwrap();
+        let lcov = coverage.lcov(&debug, |address| cpu.instruction_at(address));
+        assert!(lcov.starts_with("TN:\nSF:calls.s\nFN:9,_start\n"));
+        assert!(lcov.contains("FNDA:10,square\n"));
+        assert!(lcov.contains("BRDA:47,0,0,10\nBRDA:47,0,1,55\n"));
+        assert!(lcov.contains("DA:47,65\n"));
+        assert!(lcov.ends_with("LF:31\nLH:31\nend_of_record\n"));
+    }
+
+    #[test]
+    fn test_describe_source_lines() {
+        let cpu = Processor::init("./example/elf/calls.elf").unwrap();
+        assert_eq!(cpu.describe(0x206c), "0x0000206c (calls.s:47 in square)");
+        assert_eq!(cpu.describe(0x3000), "0x00003000");
+
+        let listing = cpu.disassemble(0x2064, 0x206c);
+        assert_eq!(
+            listing,
+            "\nsquare:\n  ; calls.s:44\n00002064:  00000293  addi t0, zero, 0\n  \
+             ; calls.s:45\n00002068:  00050313  addi t1, a0, 0\n"
+        );
+    }
+
+    #[test]
+    fn test_fault_context() {
+        // the stack of calls.elf is past the end of a 12 KiB RAM
+        let ram = RegionConfig::new("ram", 0x0, 0x3000, Permissions::all());
+        let config = MachineConfig::new(0x2000, vec![ram]);
+        let mut cpu = Processor::init_with_config("./example/elf/calls.elf", &config).unwrap();
+        cpu.set_trace(false);
+
+        // sw ra, 12(sp) in sum_squares
+        let error = cpu.run_blocks(u64::MAX).unwrap_err();
+        assert_eq!(error.pc(), Some(0x201c));
+        assert_eq!(error.raw(), Some(0x00112623));
+        assert_eq!(error.address(), Some(0x3fffc));
+        assert_eq!(error.width(), Some(4));
+        assert!(matches!(
+            error,
+            ProcessorError::Memory {
+                kind: MainMemoryErrorType::AddressOutOfBounds,
+                ..
+            }
+        ));
+        assert_eq!(cpu.pc(), 0x201c);
+        // addi sp, sp, -16 retired right before, on the block engine
+        assert_eq!(cpu.last_instruction().unwrap().raw(), 0xff010113);
+    }
 }
-pub type ProcessorError = Box<dyn ProcessorErrorTrait>;
@@ -0,0 +1,246 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use super::{
+    decoder::{
+        decode,
+        instruction::{Instruction, InstructionCode},
+    },
+    executer::unit::{alu_word, alu_xlen, branch_operation},
+    fetcher::Fetcher,
+    xlen::Xlen,
+    Processor, ProcessorError,
+};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Longest straight-line run translated into one block.
+const MAX_BLOCK_LENGTH: usize = 64;
+
+/// One pre-resolved instruction. Returns `Some(pc)` to leave the block for `pc`,
+/// or `None` to fall through to the next op.
+pub type MicroOp = Box<dyn Fn(&mut Processor) -> Result<Option<u64>, ProcessorError>>;
+
+/// Straight-line code ending in a branch or jump, translated once and run per dispatch.
+pub struct Block {
+    pub start: u64,
+    // address just past the last instruction
+    pub end: u64,
+    // pc of each op, for reporting the faulting instruction
+    pub pcs: Vec<u64>,
+    pub ops: Vec<MicroOp>,
+}
+
+impl Block {
+    /// Translates the code at `pc` up to and including its first branch or jump.
+    /// The block stops early before an instruction it cannot translate, which is
+    /// then left to `Processor::step`; `None` when not even the first one can be.
+    pub fn translate(cpu: &Processor, pc: u64) -> Option<Self> {
+        let mut block = Self {
+            start: pc,
+            end: pc,
+            pcs: vec![],
+            ops: vec![],
+        };
+
+        while block.ops.len() < MAX_BLOCK_LENGTH {
+            let Ok(raw) = Fetcher::fetch_at(&cpu.memory, block.end) else {
+                break;
+            };
+            let Ok(inst) = decode(raw, &cpu.xlen) else {
+                break;
+            };
+            let Some((op, is_terminator)) = micro_op(&inst, block.end, cpu.xlen) else {
+                break;
+            };
+
+            block.pcs.push(block.end);
+            block.ops.push(op);
+            block.end = block.end.wrapping_add(inst.size());
+            if is_terminator {
+                break;
+            }
+        }
+
+        (!block.ops.is_empty()).then_some(block)
+    }
+}
+
+/// Resolves the operands of `inst` at `pc` into a closure, telling whether it ends the block.
+/// Only integer instructions are translated; the rest go through `Processor::step`.
+fn micro_op(inst: &Instruction, pc: u64, xlen: Xlen) -> Option<(MicroOp, bool)> {
+    let (rd, rs1, rs2, imm) = (inst.rd, inst.rs1, inst.rs2, inst.imm);
+    let next = xlen.truncate(pc.wrapping_add(inst.size()));
+
+    let op: MicroOp = match &inst.code {
+        InstructionCode::Ope(code) => {
+            let code = *code;
+            Box::new(move |cpu| {
+                let (lhs, rhs) = (cpu.register.read(rs1)?, cpu.register.read(rs2)?);
+                let value = alu_xlen(&code, lhs, rhs, &xlen);
+                cpu.register.write(rd, xlen.truncate(value))?;
+                Ok(None)
+            })
+        }
+        InstructionCode::OpeI(code) => {
+            let code = *code;
+            Box::new(move |cpu| {
+                let value = alu_xlen(&code, cpu.register.read(rs1)?, imm, &xlen);
+                cpu.register.write(rd, xlen.truncate(value))?;
+                Ok(None)
+            })
+        }
+        InstructionCode::OpeW(code) => {
+            let code = *code;
+            Box::new(move |cpu| {
+                let (lhs, rhs) = (cpu.register.read(rs1)?, cpu.register.read(rs2)?);
+                cpu.register.write(rd, alu_word(&code, lhs, rhs))?;
+                Ok(None)
+            })
+        }
+        InstructionCode::OpeIW(code) => {
+            let code = *code;
+            Box::new(move |cpu| {
+                let value = alu_word(&code, cpu.register.read(rs1)?, imm);
+                cpu.register.write(rd, value)?;
+                Ok(None)
+            })
+        }
+        InstructionCode::Lui | InstructionCode::Auipc => {
+            let value = match &inst.code {
+                InstructionCode::Lui => xlen.truncate(imm),
+                _ => xlen.truncate(pc.wrapping_add(imm)),
+            };
+            Box::new(move |cpu| {
+                cpu.register.write(rd, value)?;
+                Ok(None)
+            })
+        }
+        InstructionCode::Load(option) => {
+            let option = *option;
+            Box::new(move |cpu| {
+                let address = xlen.truncate(cpu.register.read(rs1)?.wrapping_add(imm));
+                let value = cpu.memory.read(address, &option)?;
+                cpu.register.write(rd, xlen.truncate(value))?;
+                Ok(None)
+            })
+        }
+        InstructionCode::Store(option) => {
+            let option = *option;
+            Box::new(move |cpu| {
+                let address = xlen.truncate(cpu.register.read(rs1)?.wrapping_add(imm));
+                cpu.memory
+                    .write(address, cpu.register.read(rs2)?, &option)?;
+
+                // leave a block that may just have been overwritten
+                let hits_code = cpu.invalidate_code(address, option.width());
+                Ok(hits_code.then_some(next))
+            })
+        }
+        InstructionCode::Branch(option) => {
+            let option = *option;
+            let target = xlen.truncate(pc.wrapping_add(imm));
+            let op: MicroOp = Box::new(move |cpu| {
+                let (lhs, rhs) = (cpu.register.read(rs1)?, cpu.register.read(rs2)?);
+                let taken = branch_operation(&option, lhs, rhs, &xlen);
+                Ok(Some(if taken { target } else { next }))
+            });
+            return Some((op, true));
+        }
+        InstructionCode::Jal => {
+            let target = xlen.truncate(pc.wrapping_add(imm));
+            // halt: jal x0, 0 / c.j 0
+            let is_halt = inst.raw() == 0x0000006f || inst.raw() == 0xa001;
+            let op: MicroOp = Box::new(move |cpu| {
+                cpu.register.write(rd, next)?;
+                if is_halt {
+                    cpu.is_halt = true;
+                }
+                Ok(Some(target))
+            });
+            return Some((op, true));
+        }
+        InstructionCode::Jalr => {
+            let op: MicroOp = Box::new(move |cpu| {
+                let target = xlen.truncate(cpu.register.read(rs1)?.wrapping_add(imm) & !1);
+                cpu.register.write(rd, next)?;
+                Ok(Some(target))
+            });
+            return Some((op, true));
+        }
+        _ => return None,
+    };
+
+    Some((op, false))
+}
+
+/// Translated blocks keyed by their start pc, dropped when their pages are stored to.
+pub struct BlockCache {
+    blocks: HashMap<u64, Rc<Block>>,
+    // start pcs of the blocks overlapping each page, kept once however often
+    // a block is translated again
+    pages: HashMap<u64, HashSet<u64>>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            pages: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, pc: u64) -> Option<Rc<Block>> {
+        self.blocks.get(&pc).cloned()
+    }
+
+    pub fn insert(&mut self, block: Block) -> Rc<Block> {
+        let first = block.start / PAGE_SIZE;
+        let last = block.end.wrapping_sub(1) / PAGE_SIZE;
+        for page in first..=last {
+            self.pages.entry(page).or_default().insert(block.start);
+        }
+
+        let block = Rc::new(block);
+        self.blocks.insert(block.start, Rc::clone(&block));
+        block
+    }
+
+    /// Drops the blocks on the pages written by `width` bytes at `address`.
+    /// Returns whether any block was dropped.
+    pub fn invalidate(&mut self, address: u64, width: u64) -> bool {
+        let first = address / PAGE_SIZE;
+        let last = address.wrapping_add(width.max(1) - 1) / PAGE_SIZE;
+
+        let mut dropped = false;
+        for page in [first, last] {
+            if let Some(starts) = self.pages.remove(&page) {
+                for start in starts {
+                    dropped |= self.blocks.remove(&start).is_some();
+                }
+            }
+        }
+        dropped
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.pages.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
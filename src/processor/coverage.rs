@@ -0,0 +1,293 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use crate::elf::DebugInfo;
+
+use super::decoder::instruction::{Instruction, InstructionCode, RiscvInstruction};
+
+/// Execution counts of guest code, per address and per conditional branch.
+#[derive(Default)]
+pub struct Coverage {
+    counts: HashMap<u64, u64>,
+    // pc of a conditional branch -> (taken, not taken)
+    branches: HashMap<u64, (u64, u64)>,
+}
+
+// what lcov reports for one source line
+#[derive(Default)]
+struct LineCoverage {
+    // the most executed instruction of the line
+    count: u64,
+    executed: bool,
+    branches: Vec<(u64, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `inst` retired at `pc`, which continued at `next_pc`.
+    pub fn retire(&mut self, inst: &Instruction, pc: u64, next_pc: u64) {
+        *self.counts.entry(pc).or_default() += 1;
+
+        if let InstructionCode::Branch(_) = inst.code {
+            let counts = self.branches.entry(pc).or_default();
+            if next_pc == pc.wrapping_add(inst.size()) {
+                counts.1 += 1;
+            } else {
+                counts.0 += 1;
+            }
+        }
+    }
+
+    /// How many times the instruction at `pc` was retired.
+    pub fn count(&self, pc: u64) -> u64 {
+        self.counts.get(&pc).copied().unwrap_or(0)
+    }
+
+    /// Taken and not-taken counts of the conditional branch at `pc`.
+    pub fn branch(&self, pc: u64) -> (u64, u64) {
+        self.branches.get(&pc).copied().unwrap_or((0, 0))
+    }
+
+    /// Addresses retired at least once.
+    pub fn executed(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Lowest and past-the-end address of the code executed so far.
+    pub fn span(&self) -> Option<(u64, u64)> {
+        let start = *self.counts.keys().min()?;
+        let end = *self.counts.keys().max()?;
+        // the last instruction may be a full word
+        Some((start, end + 4))
+    }
+
+    /// The lcov tracefile of the lines in `debug`, read by `genhtml` and most
+    /// coverage services. `code` decodes the instruction at an address.
+    pub fn lcov<F>(&self, debug: &DebugInfo, code: F) -> String
+    where
+        F: Fn(u64) -> Option<Instruction>,
+    {
+        let lines = &debug.lines;
+        let mut files: Vec<BTreeMap<u64, LineCoverage>> =
+            lines.files().iter().map(|_| BTreeMap::new()).collect();
+
+        for range in lines.ranges() {
+            let line = files[range.file].entry(range.line).or_default();
+            let mut address = range.start;
+            while address < range.end {
+                let Some(inst) = code(address) else {
+                    break;
+                };
+                if let Some(count) = self.counts.get(&address) {
+                    line.count = line.count.max(*count);
+                    line.executed = true;
+                }
+                if let InstructionCode::Branch(_) = inst.code {
+                    line.branches.push(self.branch(address));
+                }
+                address += inst.size();
+            }
+        }
+
+        let mut lcov = String::new();
+        for (index, (path, lines_of_file)) in lines.files().iter().zip(files).enumerate() {
+            writeln!(lcov, "TN:").unwrap();
+            writeln!(lcov, "SF:{}", path).unwrap();
+
+            // functions are the symbols starting on a line of this file
+            let functions: Vec<_> = debug
+                .symbols
+                .iter()
+                .filter_map(|symbol| {
+                    let range = lines
+                        .ranges()
+                        .iter()
+                        .find(|range| range.start == symbol.address && range.file == index)?;
+                    Some((range.line, &symbol.name, self.count(symbol.address)))
+                })
+                .collect();
+            for (line, name, _) in functions.iter() {
+                writeln!(lcov, "FN:{},{}", line, name).unwrap();
+            }
+            for (_, name, count) in functions.iter() {
+                writeln!(lcov, "FNDA:{},{}", count, name).unwrap();
+            }
+            writeln!(lcov, "FNF:{}", functions.len()).unwrap();
+            let hit = functions.iter().filter(|(_, _, count)| *count > 0).count();
+            writeln!(lcov, "FNH:{}", hit).unwrap();
+
+            let (mut found, mut hit) = (0, 0);
+            for (line, coverage) in lines_of_file.iter() {
+                for (block, (taken, not_taken)) in coverage.branches.iter().enumerate() {
+                    for (branch, count) in [taken, not_taken].into_iter().enumerate() {
+                        if coverage.executed {
+                            writeln!(lcov, "BRDA:{},{},{},{}", line, block, branch, count).unwrap();
+                        } else {
+                            writeln!(lcov, "BRDA:{},{},{},-", line, block, branch).unwrap();
+                        }
+                        found += 1;
+                        hit += (*count > 0) as usize;
+                    }
+                }
+            }
+            writeln!(lcov, "BRF:{}", found).unwrap();
+            writeln!(lcov, "BRH:{}", hit).unwrap();
+
+            for (line, coverage) in lines_of_file.iter() {
+                writeln!(lcov, "DA:{},{}", line, coverage.count).unwrap();
+            }
+            writeln!(lcov, "LF:{}", lines_of_file.len()).unwrap();
+            let hit = lines_of_file.values().filter(|line| line.executed).count();
+            writeln!(lcov, "LH:{}", hit).unwrap();
+            writeln!(lcov, "end_of_record").unwrap();
+        }
+        lcov
+    }
+
+    /// Disassembly of the code in `ranges`, each instruction with its count and
+    /// each conditional branch with how often it went either way.
+    pub fn listing<F>(&self, ranges: &[(u64, u64)], debug: &DebugInfo, code: F) -> String
+    where
+        F: Fn(u64) -> Option<Instruction>,
+    {
+        let mut listing = String::new();
+        let (mut instructions, mut executed) = (0, 0);
+        let (mut directions, mut covered) = (0, 0);
+        let mut source = None;
+
+        for (start, end) in ranges.iter() {
+            let mut address = *start;
+            while address < *end {
+                if let Some(symbol) = debug.symbols.lookup(address) {
+                    if symbol.address == address {
+                        writeln!(listing, "\n{}:", symbol.name).unwrap();
+                    }
+                }
+                let line = debug.lines.lookup(address);
+                if let Some((file, number)) = line.filter(|_| line != source) {
+                    writeln!(listing, "  ; {}:{}", file, number).unwrap();
+                }
+                source = line;
+
+                let Some(inst) = code(address) else {
+                    writeln!(
+                        listing,
+                        "{:>10}  {:08x}:  <not an instruction>",
+                        "", address
+                    )
+                    .unwrap();
+                    break;
+                };
+                let count = match self.counts.get(&address) {
+                    Some(count) => count.to_string(),
+                    None => "-".into(),
+                };
+                let raw = match inst.size() {
+                    2 => format!("{:04x}    ", inst.raw()),
+                    _ => format!("{:08x}", inst.raw()),
+                };
+                write!(
+                    listing,
+                    "{:>10}  {:08x}:  {}  {}",
+                    count,
+                    address,
+                    raw,
+                    inst.assembly()
+                )
+                .unwrap();
+                if let InstructionCode::Branch(_) = inst.code {
+                    let (taken, not_taken) = self.branch(address);
+                    write!(listing, "  [taken {}, not taken {}]", taken, not_taken).unwrap();
+                    directions += 2;
+                    covered += (taken > 0) as usize + (not_taken > 0) as usize;
+                }
+                writeln!(listing).unwrap();
+
+                instructions += 1;
+                executed += self.counts.contains_key(&address) as usize;
+                address += inst.size();
+            }
+        }
+
+        let percent = |part: usize, whole: usize| {
+            if whole == 0 {
+                100.0
+            } else {
+                part as f64 * 100.0 / whole as f64
+            }
+        };
+        writeln!(
+            listing,
+            "\ninstructions executed: {} of {} ({:.2}%)",
+            executed,
+            instructions,
+            percent(executed, instructions)
+        )
+        .unwrap();
+        writeln!(
+            listing,
+            "branch directions taken: {} of {} ({:.2}%)",
+            covered,
+            directions,
+            percent(covered, directions)
+        )
+        .unwrap();
+        listing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        elf::{DebugInfo, Symbol, SymbolTable},
+        processor::{decoder::decode, xlen::Xlen},
+    };
+
+    use super::Coverage;
+
+    #[test]
+    fn test_coverage() {
+        // addi a0, a0, -1; bnez a0, 0; addi a1, a1, 1; beqz a1, 0
+        let program = [0xfff50513, 0xfe051ee3, 0x00158593, 0xfe058ae3];
+        let code = |address: u64| {
+            let raw = *program.get(address as usize / 4)?;
+            decode(raw, &Xlen::X32).ok()
+        };
+
+        let mut coverage = Coverage::new();
+        for trip in 0..3 {
+            coverage.retire(&code(0x0).unwrap(), 0x0, 0x4);
+            let next = if trip < 2 { 0x0 } else { 0x8 };
+            coverage.retire(&code(0x4).unwrap(), 0x4, next);
+        }
+        coverage.retire(&code(0x8).unwrap(), 0x8, 0xC);
+        assert_eq!(coverage.count(0x0), 3);
+        assert_eq!(coverage.branch(0x4), (2, 1));
+        assert_eq!(coverage.executed(), 3);
+        assert_eq!(coverage.span(), Some((0x0, 0xC)));
+
+        let debug = DebugInfo {
+            symbols: SymbolTable::new(vec![Symbol {
+                name: "loop".into(),
+                address: 0x0,
+                size: 0x10,
+            }]),
+            ..Default::default()
+        };
+        let listing = coverage.listing(&[(0x0, 0x10)], &debug, code);
+        assert!(listing.starts_with("\nloop:\n"));
+        assert!(listing.contains("         3  00000000:  fff50513  addi a0, a0, -1\n"));
+        assert!(listing.contains("[taken 2, not taken 1]"));
+        assert!(listing.contains("         -  0000000c:"));
+        assert!(listing.contains("instructions executed: 3 of 4 (75.00%)"));
+        assert!(listing.contains("branch directions taken: 2 of 4 (50.00%)"));
+
+        // no line table, no source files
+        assert_eq!(coverage.lcov(&debug, code), "");
+    }
+}
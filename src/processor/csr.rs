@@ -0,0 +1,182 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+
+use super::ProcessorError;
+
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+
+// accrued exception flags in fflags
+pub const INVALID_OPERATION: u32 = 0b10000;
+pub const DIVIDE_BY_ZERO: u32 = 0b01000;
+pub const OVERFLOW: u32 = 0b00100;
+pub const UNDERFLOW: u32 = 0b00010;
+pub const INEXACT: u32 = 0b00001;
+
+// rm = 0b111 in an instruction selects the rounding mode in frm
+const DYNAMIC_ROUNDING_MODE: u8 = 0b111;
+
+pub fn csr_name(address: u16) -> String {
+    match address {
+        FFLAGS => "fflags".into(),
+        FRM => "frm".into(),
+        FCSR => "fcsr".into(),
+        _ => format!("{:#05x}", address),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl TryFrom<u8> for RoundingMode {
+    type Error = ProcessorError;
+
+    fn try_from(rm: u8) -> Result<Self, Self::Error> {
+        match rm {
+            0b000 => Ok(RoundingMode::NearestEven),
+            0b001 => Ok(RoundingMode::TowardZero),
+            0b010 => Ok(RoundingMode::Down),
+            0b011 => Ok(RoundingMode::Up),
+            0b100 => Ok(RoundingMode::NearestMaxMagnitude),
+            _ => {
+                let error_type = ControlStatusRegisterErrorType::InvalidRoundingMode(rm);
+                Err(ProcessorError::from(error_type))
+            }
+        }
+    }
+}
+
+impl Display for RoundingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RoundingMode::NearestEven => "rne",
+            RoundingMode::TowardZero => "rtz",
+            RoundingMode::Down => "rdn",
+            RoundingMode::Up => "rup",
+            RoundingMode::NearestMaxMagnitude => "rmm",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone)]
+pub struct ControlStatusRegister {
+    // frm in bits 7:5, fflags in bits 4:0
+    fcsr: u32,
+}
+
+impl ControlStatusRegister {
+    pub fn new() -> Self {
+        Self { fcsr: 0 }
+    }
+
+    pub fn save(&self, writer: &mut SnapshotWriter) {
+        writer.put_u32(self.fcsr);
+    }
+
+    pub fn restore(reader: &mut SnapshotReader) -> Result<Self> {
+        let fcsr = reader.get_u32()?;
+        Ok(Self { fcsr })
+    }
+
+    pub fn read(&self, address: u16) -> Result<u64, ProcessorError> {
+        match address {
+            FFLAGS => Ok((self.fcsr % 32) as u64),
+            FRM => Ok(((self.fcsr >> 5) % 8) as u64),
+            FCSR => Ok((self.fcsr % 256) as u64),
+            _ => {
+                let error_type = ControlStatusRegisterErrorType::UndefinedAddress(address);
+                Err(ProcessorError::from(error_type))
+            }
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u64) -> Result<(), ProcessorError> {
+        match address {
+            FFLAGS => self.fcsr = (self.fcsr & !0x1F) | (value % 32) as u32,
+            FRM => self.fcsr = (self.fcsr & !0xE0) | ((value % 8) << 5) as u32,
+            FCSR => self.fcsr = (value % 256) as u32,
+            _ => {
+                let error_type = ControlStatusRegisterErrorType::UndefinedAddress(address);
+                return Err(ProcessorError::from(error_type));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the rm field of an instruction, looking up frm for the dynamic mode.
+    pub fn rounding_mode(&self, rm: u8) -> Result<RoundingMode, ProcessorError> {
+        if rm == DYNAMIC_ROUNDING_MODE {
+            RoundingMode::try_from(((self.fcsr >> 5) % 8) as u8)
+        } else {
+            RoundingMode::try_from(rm)
+        }
+    }
+
+    pub fn accrue_exceptions(&mut self, flags: u32) {
+        self.fcsr |= flags % 32;
+    }
+
+    pub fn logging(&self) {
+        println!(" {:8}: {:#04x}", "fcsr", self.fcsr);
+    }
+}
+
+impl Default for ControlStatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ControlStatusRegisterErrorType {
+    UndefinedAddress(u16),
+    InvalidRoundingMode(u8),
+}
+
+impl Display for ControlStatusRegisterErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UndefinedAddress(address) => {
+                write!(f, "access to undefined csr: {:#05x}", address)
+            }
+            Self::InvalidRoundingMode(rm) => {
+                write!(f, "get invalid rounding mode: {:#05b}", rm)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ControlStatusRegister, RoundingMode, FCSR, FFLAGS, FRM};
+
+    #[test]
+    fn test_fcsr_fields() {
+        let mut csr = ControlStatusRegister::new();
+        csr.write(FRM, 0b001).ok().unwrap();
+        csr.accrue_exceptions(0b10001);
+        assert_eq!(csr.read(FCSR).ok().unwrap(), 0b001_10001);
+        assert_eq!(csr.read(FFLAGS).ok().unwrap(), 0b10001);
+
+        csr.write(FFLAGS, 0).ok().unwrap();
+        assert_eq!(csr.read(FCSR).ok().unwrap(), 0b001_00000);
+        assert!(csr.read(0x7FF).is_err());
+
+        let rm = csr.rounding_mode(0b111).ok().unwrap();
+        assert!(rm == RoundingMode::TowardZero);
+        assert!(csr.rounding_mode(0b101).is_err());
+        csr.write(FRM, 0b110).ok().unwrap();
+        assert!(csr.rounding_mode(0b111).is_err());
+    }
+}
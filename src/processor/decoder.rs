@@ -1,27 +1,34 @@
+pub mod cache;
+pub mod compressed;
 pub mod error;
 pub mod instruction;
 
 use anyhow::Result;
 
-use instruction::{Instruction, InstructionCode, RiscvForm};
+use compressed::{expand, is_compressed};
+use error::InstructionDecodingErrorType;
+use instruction::{AluCode, AtomicCode, ByteWideOption, Instruction, InstructionCode, RiscvForm};
 
-use super::ProcessorError;
+use super::{xlen::Xlen, ProcessorError};
 
-pub fn sign_extension(value: u32, n_top: u32) -> u32 {
+pub fn sign_extension(value: u64, n_top: u32) -> u64 {
     let sign = (value >> n_top) % 2;
-    let mut value = value % 2u32.pow(n_top);
+    let mut value = value % 2u64.pow(n_top);
     if sign == 1 {
-        value = u32::MAX - 2u32.pow(n_top) + value + 1
+        value = u64::MAX - 2u64.pow(n_top) + value + 1
     }
     value
 }
 
-fn immediate(instruction: u32, form: &RiscvForm) -> u32 {
+fn immediate(instruction: u32, form: &RiscvForm) -> u64 {
     let funct3 = ((instruction >> 12) % 8) as u8;
+    let opecode = (instruction % 128) as u8;
+    let instruction = instruction as u64;
 
     match form {
-        RiscvForm::I => match funct3 {
-            0b001 | 0b101 => sign_extension(instruction >> 20, 4),
+        RiscvForm::I => match (opecode, funct3) {
+            // shift amounts are unsigned, 6 bits wide for RV64
+            (0x13 | 0x1B, 0b001 | 0b101) => (instruction >> 20) % 64,
             _ => sign_extension(instruction >> 20, 11),
         },
         RiscvForm::S => sign_extension((instruction >> 25) * 32 + (instruction >> 7) % 32, 11),
@@ -33,7 +40,7 @@ fn immediate(instruction: u32, form: &RiscvForm) -> u32 {
             let value = (imm12 << 12) + (imm11 << 11) + (imm105 << 5) + (imm41 << 1);
             sign_extension(value, 12)
         }
-        RiscvForm::U => instruction - (instruction % 2u32.pow(12)),
+        RiscvForm::U => sign_extension(instruction - (instruction % 2u64.pow(12)), 31),
         RiscvForm::J => {
             let imm1912 = (instruction >> 12) % 256;
             let imm11 = (instruction >> 20) % 2;
@@ -46,20 +53,77 @@ fn immediate(instruction: u32, form: &RiscvForm) -> u32 {
     }
 }
 
-pub fn decode(instruction: u32) -> Result<Instruction, ProcessorError> {
+/// Rejects the encodings which only exist for the other register width.
+fn check_xlen(instruction: u32, code: &InstructionCode, xlen: &Xlen) -> Result<(), ProcessorError> {
+    let opecode = instruction % 128;
+    let funct3 = (instruction >> 12) % 8;
+    // shamt[5] for RV64 shifts, a funct7 bit for RV32
+    let shamt_high = (instruction >> 25) % 2 == 1;
+    let is_shift_immediate = opecode == 0x13 && (funct3 == 0b001 || funct3 == 0b101);
+
+    let is_defined = match xlen {
+        Xlen::X32 => {
+            let is_rv64 = match code {
+                InstructionCode::OpeW(_) | InstructionCode::OpeIW(_) => true,
+                InstructionCode::Ope(AluCode::AddUw)
+                | InstructionCode::Ope(AluCode::Sh1addUw)
+                | InstructionCode::Ope(AluCode::Sh2addUw)
+                | InstructionCode::Ope(AluCode::Sh3addUw)
+                | InstructionCode::OpeI(AluCode::SlliUw) => true,
+                InstructionCode::Ope(AluCode::ZextH) => opecode == 0x3B,
+                InstructionCode::Load(ByteWideOption::DoubleWord)
+                | InstructionCode::Load(ByteWideOption::WordUnsigned)
+                | InstructionCode::Store(ByteWideOption::DoubleWord) => true,
+                InstructionCode::FloatOpe(code, fmt) => code.requires_rv64(fmt),
+                _ => is_shift_immediate && shamt_high,
+            };
+            !is_rv64
+        }
+        Xlen::X64 => match code {
+            // the RV32 zext.h encoding in OP is pack of Zbkb for RV64
+            InstructionCode::Ope(AluCode::ZextH) => opecode == 0x3B,
+            // rev8 has a different shamt field for RV64
+            InstructionCode::OpeI(AluCode::Rev8) => shamt_high,
+            // the other unary forms have no shamt field to widen
+            InstructionCode::OpeI(code) if code.is_unary() => !shamt_high,
+            _ => true,
+        },
+    };
+
+    if is_defined {
+        Ok(())
+    } else {
+        let error_type = InstructionDecodingErrorType::UndefinedForXlen(xlen.bits());
+        Err(ProcessorError::from(error_type))
+    }
+}
+
+pub fn decode(instruction: u32, xlen: &Xlen) -> Result<Instruction, ProcessorError> {
+    if is_compressed(instruction) {
+        return expand(instruction % 2u32.pow(16), xlen);
+    }
+
     let funct7 = ((instruction >> 25) % 128) as u8;
     let funct3 = ((instruction >> 12) % 8) as u8;
     let opecode = (instruction % 128) as u8;
 
-    let code = InstructionCode::try_from((funct7, funct3, opecode))?;
-
-    let form = RiscvForm::try_from(opecode)?;
-
     let rd = ((instruction >> 7) % 32) as u8;
     let rs1 = ((instruction >> 15) % 32) as u8;
     let rs2 = ((instruction >> 20) % 32) as u8;
+
+    let code = InstructionCode::try_from((funct7, funct3, opecode, rs2))?;
+    check_xlen(instruction, &code, xlen)?;
+
+    let form = RiscvForm::try_from(opecode)?;
     let registers = [rs1, rs2, rd];
 
+    if let InstructionCode::Atomic(AtomicCode::LoadReserved, _) = code {
+        if rs2 != 0 {
+            let error_type = InstructionDecodingErrorType::ReservedLoadReservedSource(rs2);
+            return Err(ProcessorError::from(error_type));
+        }
+    }
+
     let imm = immediate(instruction, &form);
 
     Ok(Instruction::new(instruction, code, form, registers, imm))
@@ -67,32 +131,230 @@ pub fn decode(instruction: u32) -> Result<Instruction, ProcessorError> {
 
 #[cfg(test)]
 mod tests {
-    use crate::processor::decoder::{instruction::RiscvForm, sign_extension};
+    use crate::processor::{
+        decoder::{
+            instruction::{RiscvForm, RiscvInstruction},
+            sign_extension,
+        },
+        xlen::Xlen,
+    };
 
-    use super::immediate;
+    use super::{decode, immediate};
 
     #[test]
     fn test_sign_extension() {
         assert_eq!(sign_extension(1, 1), 1);
-        assert_eq!(sign_extension(1, 0), u32::MAX);
-        assert_eq!(sign_extension(2, 1), u32::MAX - 1);
+        assert_eq!(sign_extension(1, 0), u64::MAX);
+        assert_eq!(sign_extension(2, 1), u64::MAX - 1);
         assert_eq!(sign_extension(2, 0), 0);
         assert_eq!(sign_extension(8, 0), 0);
         assert_eq!(sign_extension(8, 1), 0);
         assert_eq!(sign_extension(8, 2), 0);
-        assert_eq!(sign_extension(8, 3), u32::MAX - 7);
-        assert_eq!(sign_extension(0x808, 11), 0xFFFFF808);
+        assert_eq!(sign_extension(8, 3), u64::MAX - 7);
+        assert_eq!(sign_extension(0x808, 11), 0xFFFFFFFF_FFFFF808);
         assert_eq!(sign_extension(0x008, 11), 8);
     }
 
     #[test]
     fn test_immediate() {
-        assert_eq!(immediate(0xFEDCBC37, &RiscvForm::U), 0xFEDCB000);
-        assert_eq!(immediate(0xFEDCBC6F, &RiscvForm::J), 0xFFFCBFEC);
-        assert_eq!(immediate(0xABC00067, &RiscvForm::I), 0xFFFFFABC);
-        assert_eq!(immediate(0xDC000A63, &RiscvForm::B), 0xFFFFF5D4);
-        assert_eq!(immediate(0x9E000DA3, &RiscvForm::S), 0xFFFFF9FB);
-        assert_eq!(immediate(0x01A01013, &RiscvForm::I), 0xFFFFFFFA);
-        assert_eq!(immediate(0x40A05013, &RiscvForm::I), 0x0000000A);
+        assert_eq!(immediate(0xFEDCBC37, &RiscvForm::U), 0xFFFFFFFF_FEDCB000);
+        assert_eq!(immediate(0x7EDCBC37, &RiscvForm::U), 0x00000000_7EDCB000);
+        assert_eq!(immediate(0xFEDCBC6F, &RiscvForm::J), 0xFFFFFFFF_FFFCBFEC);
+        assert_eq!(immediate(0xABC00067, &RiscvForm::I), 0xFFFFFFFF_FFFFFABC);
+        assert_eq!(immediate(0xDC000A63, &RiscvForm::B), 0xFFFFFFFF_FFFFF5D4);
+        assert_eq!(immediate(0x9E000DA3, &RiscvForm::S), 0xFFFFFFFF_FFFFF9FB);
+        // shift amounts are not sign-extended
+        assert_eq!(immediate(0x01A01013, &RiscvForm::I), 0x1A);
+        assert_eq!(immediate(0x40A05013, &RiscvForm::I), 0xA);
+        assert_eq!(immediate(0x03F01013, &RiscvForm::I), 0x3F);
+        // lh and csrrw share funct3 with the shifts
+        assert_eq!(immediate(0xFFC59503, &RiscvForm::I), 0xFFFFFFFF_FFFFFFFC);
+        assert_eq!(immediate(0x34059573, &RiscvForm::I), 0x340);
+    }
+
+    #[test]
+    fn test_decode_atomic() {
+        let assembly = |inst| decode(inst, &Xlen::X32).ok().unwrap().assembly();
+        assert_eq!(assembly(0x00B6252F), "amoadd.w a0, a1, (a2)");
+        assert_eq!(assembly(0x1406252F), "lr.w.aq a0, (a2)");
+        assert_eq!(assembly(0x1AB6252F), "sc.w.rl a0, a1, (a2)");
+        assert_eq!(assembly(0xE6B6252F), "amomaxu.w.aqrl a0, a1, (a2)");
+        assert!(decode(0x1016252F, &Xlen::X32).is_err());
+    }
+
+    #[test]
+    fn test_decode_float() {
+        let assembly = |inst| decode(inst, &Xlen::X32).ok().unwrap().assembly();
+        assert_eq!(assembly(0xFFC52007), "flw ft0, -4, (a0)");
+        assert_eq!(assembly(0x00813427), "fsd fs0, 8, (sp)");
+        assert_eq!(assembly(0x00C5F553), "fadd.s fa0, fa1, fa2");
+        assert_eq!(assembly(0x02C59553), "fadd.d fa0, fa1, fa2, rtz");
+        assert_eq!(assembly(0x5A00F053), "fsqrt.d ft0, ft1");
+        assert_eq!(assembly(0x68C5F543), "fmadd.s fa0, fa1, fa2, fa3");
+        assert_eq!(assembly(0x6AC5B54B), "fnmsub.d fa0, fa1, fa2, fa3, rup");
+        assert_eq!(assembly(0x4015F553), "fcvt.s.d fa0, fa1");
+        assert_eq!(assembly(0x42057553), "fcvt.d.s fa0, fa0");
+        // a conversion into its own format is reserved
+        assert!(decode(0x40000053, &Xlen::X32).is_err());
+        assert!(decode(0x42150553, &Xlen::X32).is_err());
+        assert_eq!(assembly(0xC0051553), "fcvt.w.s a0, fa0, rtz");
+        assert_eq!(assembly(0xC2157553), "fcvt.wu.d a0, fa0");
+        assert_eq!(assembly(0xD0057553), "fcvt.s.w fa0, a0");
+        assert_eq!(assembly(0xE0050553), "fmv.x.w a0, fa0");
+        assert_eq!(assembly(0xF0050553), "fmv.w.x fa0, a0");
+        assert_eq!(assembly(0xE2051553), "fclass.d a0, fa0");
+        assert_eq!(assembly(0xA0B52553), "feq.s a0, fa0, fa1");
+        assert_eq!(assembly(0xA2B51553), "flt.d a0, fa0, fa1");
+        assert_eq!(assembly(0x20C59553), "fsgnjn.s fa0, fa1, fa2");
+        assert_eq!(assembly(0x2AC59553), "fmax.d fa0, fa1, fa2");
+        // half precision is not supported
+        assert!(decode(0x04C5F553, &Xlen::X32).is_err());
+    }
+
+    #[test]
+    fn test_decode_csr() {
+        let assembly = |inst| decode(inst, &Xlen::X32).ok().unwrap().assembly();
+        assert_eq!(assembly(0x00359573), "csrrw a0, fcsr, a1");
+        assert_eq!(assembly(0x0011E073), "csrrsi zero, fflags, 3");
+        assert_eq!(assembly(0x3405B573), "csrrc a0, 0x340, a1");
+    }
+
+    #[test]
+    fn test_decode_bitmanip() {
+        let assembly = |inst| decode(inst, &Xlen::X32).ok().unwrap().assembly();
+        assert_eq!(assembly(0x40C58533), "sub a0, a1, a2");
+        assert_eq!(assembly(0x40C5D533), "sra a0, a1, a2");
+        assert_eq!(assembly(0x4035D513), "srai a0, a1, 3");
+        assert_eq!(assembly(0x20C5A533), "sh1add a0, a1, a2");
+        assert_eq!(assembly(0x20C5C533), "sh2add a0, a1, a2");
+        assert_eq!(assembly(0x20C5E533), "sh3add a0, a1, a2");
+        assert_eq!(assembly(0x40C5F533), "andn a0, a1, a2");
+        assert_eq!(assembly(0x40C5E533), "orn a0, a1, a2");
+        assert_eq!(assembly(0x40C5C533), "xnor a0, a1, a2");
+        assert_eq!(assembly(0x60059513), "clz a0, a1");
+        assert_eq!(assembly(0x60159513), "ctz a0, a1");
+        assert_eq!(assembly(0x60259513), "cpop a0, a1");
+        assert_eq!(assembly(0x0AC5C533), "min a0, a1, a2");
+        assert_eq!(assembly(0x0AC5D533), "minu a0, a1, a2");
+        assert_eq!(assembly(0x0AC5E533), "max a0, a1, a2");
+        assert_eq!(assembly(0x0AC5F533), "maxu a0, a1, a2");
+        assert_eq!(assembly(0x60459513), "sext.b a0, a1");
+        assert_eq!(assembly(0x60559513), "sext.h a0, a1");
+        assert_eq!(assembly(0x0805C533), "zext.h a0, a1");
+        assert_eq!(assembly(0x60C59533), "rol a0, a1, a2");
+        assert_eq!(assembly(0x60C5D533), "ror a0, a1, a2");
+        assert_eq!(assembly(0x6075D513), "rori a0, a1, 7");
+        assert_eq!(assembly(0x2875D513), "orc.b a0, a1");
+        assert_eq!(assembly(0x6985D513), "rev8 a0, a1");
+        assert_eq!(assembly(0x0AC59533), "clmul a0, a1, a2");
+        assert_eq!(assembly(0x0AC5B533), "clmulh a0, a1, a2");
+        assert_eq!(assembly(0x0AC5A533), "clmulr a0, a1, a2");
+        assert_eq!(assembly(0x48C59533), "bclr a0, a1, a2");
+        assert_eq!(assembly(0x48359513), "bclri a0, a1, 3");
+        assert_eq!(assembly(0x48C5D533), "bext a0, a1, a2");
+        assert_eq!(assembly(0x4835D513), "bexti a0, a1, 3");
+        assert_eq!(assembly(0x68C59533), "binv a0, a1, a2");
+        assert_eq!(assembly(0x68359513), "binvi a0, a1, 3");
+        assert_eq!(assembly(0x28C59533), "bset a0, a1, a2");
+        assert_eq!(assembly(0x28359513), "bseti a0, a1, 3");
+        // zext.h is only defined with rs2 = 0
+        assert!(decode(0x0815C533, &Xlen::X32).is_err());
+    }
+
+    #[test]
+    fn test_decode_multiply() {
+        let assembly = |inst| decode(inst, &Xlen::X32).ok().unwrap().assembly();
+        assert_eq!(assembly(0x02C58533), "mul a0, a1, a2");
+        assert_eq!(assembly(0x02C59533), "mulh a0, a1, a2");
+        assert_eq!(assembly(0x02C5A533), "mulhsu a0, a1, a2");
+        assert_eq!(assembly(0x02C5B533), "mulhu a0, a1, a2");
+        assert_eq!(assembly(0x02C5C533), "div a0, a1, a2");
+        assert_eq!(assembly(0x02C5D533), "divu a0, a1, a2");
+        assert_eq!(assembly(0x02C5E533), "rem a0, a1, a2");
+        assert_eq!(assembly(0x02C5F533), "remu a0, a1, a2");
+
+        let assembly = |inst| decode(inst, &Xlen::X64).ok().unwrap().assembly();
+        assert_eq!(assembly(0x02C5853B), "mulw a0, a1, a2");
+        assert_eq!(assembly(0x02C5F53B), "remuw a0, a1, a2");
+        // mulh has no W form
+        assert!(decode(0x02C5953B, &Xlen::X64).is_err());
+        assert!(decode(0x02C5853B, &Xlen::X32).is_err());
+    }
+
+    #[test]
+    fn test_decode_never_panics() {
+        // ecall and an unknown opcode are errors like any other encoding
+        assert!(decode(0x00000073, &Xlen::X32).is_err());
+        assert!(decode(0x0000007F, &Xlen::X32).is_err());
+
+        // every opcode, funct3, funct7 and rs2, and every compressed parcel
+        for xlen in [Xlen::X32, Xlen::X64] {
+            for fields in 0..1u32 << 19 {
+                // the lowest two bits of a 32-bit instruction are always set
+                let opecode = (fields % 32) << 2 | 0b11;
+                let funct3 = ((fields >> 5) % 8) << 12;
+                let rs2 = ((fields >> 8) % 32) << 20;
+                let funct7 = (fields >> 13) << 25;
+                let inst = funct7 | rs2 | 0b01011 << 15 | funct3 | 0b01010 << 7 | opecode;
+                if let Ok(inst) = decode(inst, &xlen) {
+                    inst.assembly();
+                }
+            }
+            for parcel in 0..1u32 << 16 {
+                if let Ok(inst) = decode(parcel, &xlen) {
+                    inst.assembly();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_rv64() {
+        let assembly = |inst| decode(inst, &Xlen::X64).ok().unwrap().assembly();
+        assert_eq!(assembly(0x00C5853B), "addw a0, a1, a2");
+        assert_eq!(assembly(0x40C5853B), "subw a0, a1, a2");
+        assert_eq!(assembly(0x00C5953B), "sllw a0, a1, a2");
+        assert_eq!(assembly(0x00C5D53B), "srlw a0, a1, a2");
+        assert_eq!(assembly(0x40C5D53B), "sraw a0, a1, a2");
+        assert_eq!(assembly(0xFFF5851B), "addiw a0, a1, -1");
+        assert_eq!(assembly(0x01F5951B), "slliw a0, a1, 31");
+        assert_eq!(assembly(0x0035D51B), "srliw a0, a1, 3");
+        assert_eq!(assembly(0x4035D51B), "sraiw a0, a1, 3");
+        assert_eq!(assembly(0x03F59513), "slli a0, a1, 63");
+        assert_eq!(assembly(0x4285D513), "srai a0, a1, 40");
+        assert_eq!(assembly(0xFF813503), "ld a0, -8, (sp)");
+        assert_eq!(assembly(0x00B13823), "sd a1, 16, (sp)");
+        assert_eq!(assembly(0x0045E503), "lwu a0, 4, (a1)");
+        assert_eq!(assembly(0x08C5853B), "add.uw a0, a1, a2");
+        assert_eq!(assembly(0x20C5A53B), "sh1add.uw a0, a1, a2");
+        assert_eq!(assembly(0x0A85951B), "slli.uw a0, a1, 40");
+        assert_eq!(assembly(0x0805C53B), "zext.h a0, a1");
+        assert_eq!(assembly(0x6B85D513), "rev8 a0, a1");
+        assert_eq!(assembly(0x6005951B), "clzw a0, a1");
+        assert_eq!(assembly(0x60C5953B), "rolw a0, a1, a2");
+        assert_eq!(assembly(0x6055D51B), "roriw a0, a1, 5");
+        assert_eq!(assembly(0xC2251553), "fcvt.l.d a0, fa0, rtz");
+        assert_eq!(assembly(0xC0357553), "fcvt.lu.s a0, fa0");
+        assert_eq!(assembly(0xD2257553), "fcvt.d.l fa0, a0");
+        assert_eq!(assembly(0xD0357553), "fcvt.s.lu fa0, a0");
+        assert_eq!(assembly(0xE2050553), "fmv.x.d a0, fa0");
+        assert_eq!(assembly(0xF2050553), "fmv.d.x fa0, a0");
+        // the RV32 encodings of rev8 and zext.h
+        assert!(decode(0x6985D513, &Xlen::X64).is_err());
+        assert!(decode(0x0805C533, &Xlen::X64).is_err());
+        // nor do clz and orc.b have a shamt[5]
+        assert!(decode(0x62051513, &Xlen::X64).is_err());
+        assert!(decode(0x2A75D513, &Xlen::X64).is_err());
+        assert_eq!(assembly(0x60051513), "clz a0, a0");
+        // W shifts only have a 5-bit shamt
+        assert!(decode(0x0215951B, &Xlen::X64).is_err());
+
+        // none of them is defined for RV32
+        for inst in [
+            0x00C5853B, 0xFFF5851B, 0x03F59513, 0xFF813503, 0x00B13823, 0x0045E503, 0x08C5853B,
+            0x0A85951B, 0xC2251553, 0xE2050553,
+        ] {
+            assert!(decode(inst, &Xlen::X32).is_err());
+        }
     }
 }
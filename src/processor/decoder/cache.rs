@@ -0,0 +1,116 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use super::instruction::Instruction;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Decoded instructions keyed by their pc, so that a loop is decoded once.
+/// Entries are dropped when their page is stored to, and all of them on FENCE.I.
+pub struct DecodeCache {
+    entries: HashMap<u64, Rc<Instruction>>,
+    // pcs of the cached instructions overlapping each page; a set, as the other
+    // page of a straddling instruction keeps its pc across re-inserts
+    pages: HashMap<u64, HashSet<u64>>,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            pages: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, pc: u64) -> Option<Rc<Instruction>> {
+        self.entries.get(&pc).cloned()
+    }
+
+    pub fn insert(&mut self, pc: u64, inst: Instruction) -> Rc<Instruction> {
+        let first = pc / PAGE_SIZE;
+        let last = pc.wrapping_add(inst.size() - 1) / PAGE_SIZE;
+        self.pages.entry(first).or_default().insert(pc);
+        self.pages.entry(last).or_default().insert(pc);
+
+        let inst = Rc::new(inst);
+        self.entries.insert(pc, Rc::clone(&inst));
+        inst
+    }
+
+    /// Drops every instruction on the pages written by `width` bytes at `address`.
+    pub fn invalidate(&mut self, address: u64, width: u64) {
+        let first = address / PAGE_SIZE;
+        let last = address.wrapping_add(width.max(1) - 1) / PAGE_SIZE;
+        for page in [first, last] {
+            if let Some(pcs) = self.pages.remove(&page) {
+                for pc in pcs {
+                    self.entries.remove(&pc);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pages.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::{decoder::decode, xlen::Xlen};
+
+    use super::DecodeCache;
+
+    #[test]
+    fn test_decode_cache() {
+        let mut cache = DecodeCache::new();
+        let addi = || decode(0x00150513, &Xlen::X32).ok().unwrap();
+        let c_nop = || decode(0x0001, &Xlen::X32).ok().unwrap();
+
+        cache.insert(0x1000, addi());
+        cache.insert(0x1004, c_nop());
+        // straddles the page boundary at 0x2000
+        cache.insert(0x1FFE, addi());
+        assert_eq!(cache.get(0x1004).unwrap().raw(), 0x0001);
+        assert!(cache.get(0x1002).is_none());
+
+        // a store elsewhere leaves the entries alone
+        cache.invalidate(0x3000, 4);
+        assert_eq!(cache.len(), 3);
+
+        // a store to the second page drops the straddling instruction only
+        cache.invalidate(0x2000, 1);
+        assert!(cache.get(0x1FFE).is_none());
+        assert_eq!(cache.len(), 2);
+
+        // decoding it again does not list it twice on the first page
+        cache.insert(0x1FFE, addi());
+        cache.invalidate(0x2000, 1);
+        cache.insert(0x1FFE, addi());
+        assert_eq!(cache.pages[&1].len(), 3);
+
+        cache.invalidate(0x0FFC, 8);
+        assert!(cache.is_empty());
+
+        cache.insert(0x1000, addi());
+        cache.clear();
+        assert!(cache.get(0x1000).is_none());
+    }
+}
@@ -0,0 +1,647 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+
+use crate::processor::{
+    register::{FloatRegisterAlias, RegisterAlias},
+    xlen::Xlen,
+    ProcessorError,
+};
+
+use super::{
+    error::InstructionDecodingErrorType,
+    instruction::{
+        AluCode, BranchOption, ByteWideOption, FloatFormat, Instruction, InstructionCode,
+        RiscvForm, RiscvInstruction,
+    },
+    sign_extension,
+};
+
+const REG_ZERO: u8 = 0;
+const REG_RA: u8 = 1;
+const REG_SP: u8 = 2;
+
+pub fn is_compressed(parcel: u32) -> bool {
+    parcel % 4 != 0b11
+}
+
+pub enum CompressedCode {
+    Addi4spn,
+    Lw,
+    Sw,
+    Nop,
+    Addi,
+    Jal,
+    Li,
+    Addi16sp,
+    Lui,
+    Srli,
+    Srai,
+    Andi,
+    Sub,
+    Xor,
+    Or,
+    And,
+    J,
+    Beqz,
+    Bnez,
+    Slli,
+    Lwsp,
+    Jr,
+    Mv,
+    Jalr,
+    Add,
+    Swsp,
+    Fld,
+    Flw,
+    Fsd,
+    Fsw,
+    Fldsp,
+    Flwsp,
+    Fsdsp,
+    Fswsp,
+    // RV64C
+    Ld,
+    Sd,
+    Addiw,
+    Subw,
+    Addw,
+    Ldsp,
+    Sdsp,
+}
+
+impl RiscvInstruction for CompressedCode {
+    fn assembly(&self) -> String {
+        match self {
+            CompressedCode::Addi4spn => "c.addi4spn",
+            CompressedCode::Lw => "c.lw",
+            CompressedCode::Sw => "c.sw",
+            CompressedCode::Nop => "c.nop",
+            CompressedCode::Addi => "c.addi",
+            CompressedCode::Jal => "c.jal",
+            CompressedCode::Li => "c.li",
+            CompressedCode::Addi16sp => "c.addi16sp",
+            CompressedCode::Lui => "c.lui",
+            CompressedCode::Srli => "c.srli",
+            CompressedCode::Srai => "c.srai",
+            CompressedCode::Andi => "c.andi",
+            CompressedCode::Sub => "c.sub",
+            CompressedCode::Xor => "c.xor",
+            CompressedCode::Or => "c.or",
+            CompressedCode::And => "c.and",
+            CompressedCode::J => "c.j",
+            CompressedCode::Beqz => "c.beqz",
+            CompressedCode::Bnez => "c.bnez",
+            CompressedCode::Slli => "c.slli",
+            CompressedCode::Lwsp => "c.lwsp",
+            CompressedCode::Jr => "c.jr",
+            CompressedCode::Mv => "c.mv",
+            CompressedCode::Jalr => "c.jalr",
+            CompressedCode::Add => "c.add",
+            CompressedCode::Swsp => "c.swsp",
+            CompressedCode::Fld => "c.fld",
+            CompressedCode::Flw => "c.flw",
+            CompressedCode::Fsd => "c.fsd",
+            CompressedCode::Fsw => "c.fsw",
+            CompressedCode::Fldsp => "c.fldsp",
+            CompressedCode::Flwsp => "c.flwsp",
+            CompressedCode::Fsdsp => "c.fsdsp",
+            CompressedCode::Fswsp => "c.fswsp",
+            CompressedCode::Ld => "c.ld",
+            CompressedCode::Sd => "c.sd",
+            CompressedCode::Addiw => "c.addiw",
+            CompressedCode::Subw => "c.subw",
+            CompressedCode::Addw => "c.addw",
+            CompressedCode::Ldsp => "c.ldsp",
+            CompressedCode::Sdsp => "c.sdsp",
+        }
+        .into()
+    }
+}
+
+impl CompressedCode {
+    /// Formats the operands in the compressed form, taken from the expanded instruction.
+    pub fn assembly_with(&self, inst: &Instruction) -> String {
+        let rs1 = RegisterAlias::try_from(inst.rs1).ok().unwrap();
+        let rs2 = RegisterAlias::try_from(inst.rs2).ok().unwrap();
+        let rd = RegisterAlias::try_from(inst.rd).ok().unwrap();
+        let imm = inst.imm as i64;
+        let frs2 = FloatRegisterAlias::try_from(inst.rs2).ok().unwrap();
+        let frd = FloatRegisterAlias::try_from(inst.rd).ok().unwrap();
+
+        match self {
+            CompressedCode::Nop => self.assembly(),
+            CompressedCode::Fld
+            | CompressedCode::Flw
+            | CompressedCode::Fldsp
+            | CompressedCode::Flwsp => format!("{} {}, {}, ({})", self, frd, imm, rs1),
+            CompressedCode::Fsd
+            | CompressedCode::Fsw
+            | CompressedCode::Fsdsp
+            | CompressedCode::Fswsp => format!("{} {}, {}, ({})", self, frs2, imm, rs1),
+            CompressedCode::Addi4spn => format!("{} {}, {}, {}", self, rd, rs1, imm),
+            CompressedCode::Lw
+            | CompressedCode::Lwsp
+            | CompressedCode::Ld
+            | CompressedCode::Ldsp => {
+                format!("{} {}, {}, ({})", self, rd, imm, rs1)
+            }
+            CompressedCode::Sw
+            | CompressedCode::Swsp
+            | CompressedCode::Sd
+            | CompressedCode::Sdsp => {
+                format!("{} {}, {}, ({})", self, rs2, imm, rs1)
+            }
+            CompressedCode::Addi
+            | CompressedCode::Li
+            | CompressedCode::Addi16sp
+            | CompressedCode::Lui
+            | CompressedCode::Srli
+            | CompressedCode::Srai
+            | CompressedCode::Andi
+            | CompressedCode::Slli
+            | CompressedCode::Addiw => format!("{} {}, {}", self, rd, imm),
+            CompressedCode::Sub
+            | CompressedCode::Xor
+            | CompressedCode::Or
+            | CompressedCode::And
+            | CompressedCode::Mv
+            | CompressedCode::Add
+            | CompressedCode::Subw
+            | CompressedCode::Addw => format!("{} {}, {}", self, rd, rs2),
+            CompressedCode::Jal | CompressedCode::J => format!("{} {}", self, imm),
+            CompressedCode::Beqz | CompressedCode::Bnez => {
+                format!("{} {}, {}", self, rs1, imm)
+            }
+            CompressedCode::Jr | CompressedCode::Jalr => format!("{} {}", self, rs1),
+        }
+    }
+}
+
+impl Display for CompressedCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
+fn bits(parcel: u32, high: u32, low: u32) -> u64 {
+    ((parcel >> low) % 2u32.pow(high - low + 1)) as u64
+}
+
+// rd', rs1' and rs2' only address x8-x15
+fn prime(field: u64) -> u8 {
+    (field + 8) as u8
+}
+
+fn undefined(parcel: u32) -> ProcessorError {
+    let error_type = InstructionDecodingErrorType::UndefinedCompressedInstruction(parcel as u16);
+    ProcessorError::from(error_type)
+}
+
+/// Expands a 16-bit RVC parcel into its 32-bit `Instruction` equivalent.
+/// Some encodings are reassigned between RV32C and RV64C.
+pub fn expand(parcel: u32, xlen: &Xlen) -> Result<Instruction, ProcessorError> {
+    let is_rv64 = *xlen == Xlen::X64;
+    let quadrant = parcel % 4;
+    let funct3 = bits(parcel, 15, 13);
+
+    let rd = bits(parcel, 11, 7) as u8;
+    let rs2 = bits(parcel, 6, 2) as u8;
+    let rd_prime = prime(bits(parcel, 4, 2));
+    let rs1_prime = prime(bits(parcel, 9, 7));
+
+    let imm6 = sign_extension((bits(parcel, 12, 12) << 5) + bits(parcel, 6, 2), 5);
+    let shamt = (bits(parcel, 12, 12) << 5) + bits(parcel, 6, 2);
+
+    // (compressed code, expanded code, form, [rs1, rs2, rd], imm)
+    let (ccode, code, form, registers, imm) = match (quadrant, funct3) {
+        (0b00, 0b000) => {
+            let imm = (bits(parcel, 12, 11) << 4)
+                + (bits(parcel, 10, 7) << 6)
+                + (bits(parcel, 6, 6) << 2)
+                + (bits(parcel, 5, 5) << 3);
+            if imm == 0 {
+                return Err(undefined(parcel));
+            }
+            let code = InstructionCode::OpeI(AluCode::Add);
+            let registers = [REG_SP, 0, rd_prime];
+            (CompressedCode::Addi4spn, code, RiscvForm::I, registers, imm)
+        }
+        (0b00, 0b010) => {
+            let imm =
+                (bits(parcel, 12, 10) << 3) + (bits(parcel, 6, 6) << 2) + (bits(parcel, 5, 5) << 6);
+            let code = InstructionCode::Load(ByteWideOption::Word);
+            let registers = [rs1_prime, 0, rd_prime];
+            (CompressedCode::Lw, code, RiscvForm::I, registers, imm)
+        }
+        (0b00, 0b001) | (0b00, 0b101) => {
+            let imm = (bits(parcel, 12, 10) << 3) + (bits(parcel, 6, 5) << 6);
+            let format = FloatFormat::Double;
+            if funct3 == 0b001 {
+                let code = InstructionCode::LoadFloat(format);
+                let registers = [rs1_prime, 0, rd_prime];
+                (CompressedCode::Fld, code, RiscvForm::I, registers, imm)
+            } else {
+                let code = InstructionCode::StoreFloat(format);
+                let registers = [rs1_prime, rd_prime, 0];
+                (CompressedCode::Fsd, code, RiscvForm::S, registers, imm)
+            }
+        }
+        (0b00, 0b011) | (0b00, 0b111) if is_rv64 => {
+            let imm = (bits(parcel, 12, 10) << 3) + (bits(parcel, 6, 5) << 6);
+            let option = ByteWideOption::DoubleWord;
+            if funct3 == 0b011 {
+                let code = InstructionCode::Load(option);
+                let registers = [rs1_prime, 0, rd_prime];
+                (CompressedCode::Ld, code, RiscvForm::I, registers, imm)
+            } else {
+                let code = InstructionCode::Store(option);
+                let registers = [rs1_prime, rd_prime, 0];
+                (CompressedCode::Sd, code, RiscvForm::S, registers, imm)
+            }
+        }
+        (0b00, 0b011) | (0b00, 0b111) => {
+            let imm =
+                (bits(parcel, 12, 10) << 3) + (bits(parcel, 6, 6) << 2) + (bits(parcel, 5, 5) << 6);
+            let format = FloatFormat::Single;
+            if funct3 == 0b011 {
+                let code = InstructionCode::LoadFloat(format);
+                let registers = [rs1_prime, 0, rd_prime];
+                (CompressedCode::Flw, code, RiscvForm::I, registers, imm)
+            } else {
+                let code = InstructionCode::StoreFloat(format);
+                let registers = [rs1_prime, rd_prime, 0];
+                (CompressedCode::Fsw, code, RiscvForm::S, registers, imm)
+            }
+        }
+        (0b00, 0b110) => {
+            let imm =
+                (bits(parcel, 12, 10) << 3) + (bits(parcel, 6, 6) << 2) + (bits(parcel, 5, 5) << 6);
+            let code = InstructionCode::Store(ByteWideOption::Word);
+            let registers = [rs1_prime, rd_prime, 0];
+            (CompressedCode::Sw, code, RiscvForm::S, registers, imm)
+        }
+        (0b01, 0b000) => {
+            let code = InstructionCode::OpeI(AluCode::Add);
+            let ccode = if rd == REG_ZERO {
+                CompressedCode::Nop
+            } else {
+                CompressedCode::Addi
+            };
+            (ccode, code, RiscvForm::I, [rd, 0, rd], imm6)
+        }
+        (0b01, 0b001) if is_rv64 => {
+            if rd == REG_ZERO {
+                return Err(undefined(parcel));
+            }
+            let code = InstructionCode::OpeIW(AluCode::Add);
+            (CompressedCode::Addiw, code, RiscvForm::I, [rd, 0, rd], imm6)
+        }
+        (0b01, 0b001) | (0b01, 0b101) => {
+            let value = (bits(parcel, 12, 12) << 11)
+                + (bits(parcel, 11, 11) << 4)
+                + (bits(parcel, 10, 9) << 8)
+                + (bits(parcel, 8, 8) << 10)
+                + (bits(parcel, 7, 7) << 6)
+                + (bits(parcel, 6, 6) << 7)
+                + (bits(parcel, 5, 3) << 1)
+                + (bits(parcel, 2, 2) << 5);
+            let imm = sign_extension(value, 11);
+            let (ccode, link) = if funct3 == 0b001 {
+                (CompressedCode::Jal, REG_RA)
+            } else {
+                (CompressedCode::J, REG_ZERO)
+            };
+            (ccode, InstructionCode::Jal, RiscvForm::J, [0, 0, link], imm)
+        }
+        (0b01, 0b010) => {
+            let code = InstructionCode::OpeI(AluCode::Add);
+            (
+                CompressedCode::Li,
+                code,
+                RiscvForm::I,
+                [REG_ZERO, 0, rd],
+                imm6,
+            )
+        }
+        (0b01, 0b011) if rd == REG_SP => {
+            let value = (bits(parcel, 12, 12) << 9)
+                + (bits(parcel, 6, 6) << 4)
+                + (bits(parcel, 5, 5) << 6)
+                + (bits(parcel, 4, 3) << 7)
+                + (bits(parcel, 2, 2) << 5);
+            if value == 0 {
+                return Err(undefined(parcel));
+            }
+            let imm = sign_extension(value, 9);
+            let code = InstructionCode::OpeI(AluCode::Add);
+            let registers = [REG_SP, 0, REG_SP];
+            (CompressedCode::Addi16sp, code, RiscvForm::I, registers, imm)
+        }
+        (0b01, 0b011) => {
+            let value = (bits(parcel, 12, 12) << 17) + (bits(parcel, 6, 2) << 12);
+            if value == 0 {
+                return Err(undefined(parcel));
+            }
+            let imm = sign_extension(value, 17);
+            (
+                CompressedCode::Lui,
+                InstructionCode::Lui,
+                RiscvForm::U,
+                [0, 0, rd],
+                imm,
+            )
+        }
+        (0b01, 0b100) => {
+            let registers = [rs1_prime, rd_prime, rs1_prime];
+            match bits(parcel, 11, 10) {
+                0b00 | 0b01 => {
+                    // shamt[5] must be zero for RV32C
+                    if shamt >= 32 && !is_rv64 {
+                        return Err(undefined(parcel));
+                    }
+                    let (ccode, code) = if bits(parcel, 11, 10) == 0b00 {
+                        (CompressedCode::Srli, AluCode::Srl)
+                    } else {
+                        (CompressedCode::Srai, AluCode::Sra)
+                    };
+                    let code = InstructionCode::OpeI(code);
+                    (ccode, code, RiscvForm::I, registers, shamt)
+                }
+                0b10 => {
+                    let code = InstructionCode::OpeI(AluCode::And);
+                    (CompressedCode::Andi, code, RiscvForm::I, registers, imm6)
+                }
+                _ if bits(parcel, 12, 12) == 1 => {
+                    let (ccode, code) = match bits(parcel, 6, 5) {
+                        0b00 if is_rv64 => (CompressedCode::Subw, AluCode::Sub),
+                        0b01 if is_rv64 => (CompressedCode::Addw, AluCode::Add),
+                        _ => return Err(undefined(parcel)),
+                    };
+                    let code = InstructionCode::OpeW(code);
+                    (ccode, code, RiscvForm::R, registers, 0)
+                }
+                _ => {
+                    let (ccode, code) = match bits(parcel, 6, 5) {
+                        0b00 => (CompressedCode::Sub, AluCode::Sub),
+                        0b01 => (CompressedCode::Xor, AluCode::Xor),
+                        0b10 => (CompressedCode::Or, AluCode::Or),
+                        _ => (CompressedCode::And, AluCode::And),
+                    };
+                    let code = InstructionCode::Ope(code);
+                    (ccode, code, RiscvForm::R, registers, 0)
+                }
+            }
+        }
+        (0b01, 0b110) | (0b01, 0b111) => {
+            let value = (bits(parcel, 12, 12) << 8)
+                + (bits(parcel, 11, 10) << 3)
+                + (bits(parcel, 6, 5) << 6)
+                + (bits(parcel, 4, 3) << 1)
+                + (bits(parcel, 2, 2) << 5);
+            let imm = sign_extension(value, 8);
+            let (ccode, option) = if funct3 == 0b110 {
+                (CompressedCode::Beqz, BranchOption::Equal)
+            } else {
+                (CompressedCode::Bnez, BranchOption::NotEqual)
+            };
+            let code = InstructionCode::Branch(option);
+            (ccode, code, RiscvForm::B, [rs1_prime, REG_ZERO, 0], imm)
+        }
+        (0b10, 0b000) => {
+            if shamt >= 32 && !is_rv64 {
+                return Err(undefined(parcel));
+            }
+            let code = InstructionCode::OpeI(AluCode::Sll);
+            (CompressedCode::Slli, code, RiscvForm::I, [rd, 0, rd], shamt)
+        }
+        (0b10, 0b010) => {
+            if rd == REG_ZERO {
+                return Err(undefined(parcel));
+            }
+            let imm =
+                (bits(parcel, 12, 12) << 5) + (bits(parcel, 6, 4) << 2) + (bits(parcel, 3, 2) << 6);
+            let code = InstructionCode::Load(ByteWideOption::Word);
+            (
+                CompressedCode::Lwsp,
+                code,
+                RiscvForm::I,
+                [REG_SP, 0, rd],
+                imm,
+            )
+        }
+        (0b10, 0b100) => match (bits(parcel, 12, 12), rd, rs2) {
+            (0, REG_ZERO, _) => return Err(undefined(parcel)),
+            (0, _, REG_ZERO) => {
+                let registers = [rd, 0, REG_ZERO];
+                (
+                    CompressedCode::Jr,
+                    InstructionCode::Jalr,
+                    RiscvForm::I,
+                    registers,
+                    0,
+                )
+            }
+            (0, _, _) => {
+                let code = InstructionCode::Ope(AluCode::Add);
+                (
+                    CompressedCode::Mv,
+                    code,
+                    RiscvForm::R,
+                    [REG_ZERO, rs2, rd],
+                    0,
+                )
+            }
+            // c.ebreak: no environment call support yet
+            (_, REG_ZERO, REG_ZERO) => return Err(undefined(parcel)),
+            (_, _, REG_ZERO) => {
+                let registers = [rd, 0, REG_RA];
+                (
+                    CompressedCode::Jalr,
+                    InstructionCode::Jalr,
+                    RiscvForm::I,
+                    registers,
+                    0,
+                )
+            }
+            (_, _, _) => {
+                let code = InstructionCode::Ope(AluCode::Add);
+                (CompressedCode::Add, code, RiscvForm::R, [rd, rs2, rd], 0)
+            }
+        },
+        (0b10, 0b001) => {
+            let imm =
+                (bits(parcel, 12, 12) << 5) + (bits(parcel, 6, 5) << 3) + (bits(parcel, 4, 2) << 6);
+            let code = InstructionCode::LoadFloat(FloatFormat::Double);
+            (
+                CompressedCode::Fldsp,
+                code,
+                RiscvForm::I,
+                [REG_SP, 0, rd],
+                imm,
+            )
+        }
+        (0b10, 0b011) if is_rv64 => {
+            if rd == REG_ZERO {
+                return Err(undefined(parcel));
+            }
+            let imm =
+                (bits(parcel, 12, 12) << 5) + (bits(parcel, 6, 5) << 3) + (bits(parcel, 4, 2) << 6);
+            let code = InstructionCode::Load(ByteWideOption::DoubleWord);
+            (
+                CompressedCode::Ldsp,
+                code,
+                RiscvForm::I,
+                [REG_SP, 0, rd],
+                imm,
+            )
+        }
+        (0b10, 0b011) => {
+            let imm =
+                (bits(parcel, 12, 12) << 5) + (bits(parcel, 6, 4) << 2) + (bits(parcel, 3, 2) << 6);
+            let code = InstructionCode::LoadFloat(FloatFormat::Single);
+            (
+                CompressedCode::Flwsp,
+                code,
+                RiscvForm::I,
+                [REG_SP, 0, rd],
+                imm,
+            )
+        }
+        (0b10, 0b101) => {
+            let imm = (bits(parcel, 12, 10) << 3) + (bits(parcel, 9, 7) << 6);
+            let code = InstructionCode::StoreFloat(FloatFormat::Double);
+            (
+                CompressedCode::Fsdsp,
+                code,
+                RiscvForm::S,
+                [REG_SP, rs2, 0],
+                imm,
+            )
+        }
+        (0b10, 0b111) if is_rv64 => {
+            let imm = (bits(parcel, 12, 10) << 3) + (bits(parcel, 9, 7) << 6);
+            let code = InstructionCode::Store(ByteWideOption::DoubleWord);
+            (
+                CompressedCode::Sdsp,
+                code,
+                RiscvForm::S,
+                [REG_SP, rs2, 0],
+                imm,
+            )
+        }
+        (0b10, 0b111) => {
+            let imm = (bits(parcel, 12, 9) << 2) + (bits(parcel, 8, 7) << 6);
+            let code = InstructionCode::StoreFloat(FloatFormat::Single);
+            (
+                CompressedCode::Fswsp,
+                code,
+                RiscvForm::S,
+                [REG_SP, rs2, 0],
+                imm,
+            )
+        }
+        (0b10, 0b110) => {
+            let imm = (bits(parcel, 12, 9) << 2) + (bits(parcel, 8, 7) << 6);
+            let code = InstructionCode::Store(ByteWideOption::Word);
+            (
+                CompressedCode::Swsp,
+                code,
+                RiscvForm::S,
+                [REG_SP, rs2, 0],
+                imm,
+            )
+        }
+        _ => return Err(undefined(parcel)),
+    };
+
+    Ok(Instruction::new_compressed(
+        parcel, ccode, code, form, registers, imm,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::{decoder::instruction::RiscvInstruction, xlen::Xlen};
+
+    use super::{expand, is_compressed};
+
+    #[test]
+    fn test_is_compressed() {
+        assert!(is_compressed(0x0001));
+        assert!(is_compressed(0x8082));
+        assert!(!is_compressed(0x00100593));
+    }
+
+    #[test]
+    fn test_expand() {
+        let assembly = |parcel| expand(parcel, &Xlen::X32).ok().unwrap().assembly();
+        assert_eq!(assembly(0x0808), "c.addi4spn a0, sp, 16");
+        assert_eq!(assembly(0x41C8), "c.lw a0, 4, (a1)");
+        assert_eq!(assembly(0xC22C), "c.sw a1, 64, (a2)");
+        assert_eq!(assembly(0x0001), "c.nop");
+        assert_eq!(assembly(0x1575), "c.addi a0, -3");
+        assert_eq!(assembly(0x3001), "c.jal -2048");
+        assert_eq!(assembly(0x47FD), "c.li a5, 31");
+        assert_eq!(assembly(0x7139), "c.addi16sp sp, -64");
+        assert_eq!(assembly(0x75FD), "c.lui a1, -4096");
+        assert_eq!(assembly(0x810D), "c.srli a0, 3");
+        assert_eq!(assembly(0x85FD), "c.srai a1, 31");
+        assert_eq!(assembly(0x9A7D), "c.andi a2, -1");
+        assert_eq!(assembly(0x8D0D), "c.sub a0, a1");
+        assert_eq!(assembly(0x8D2D), "c.xor a0, a1");
+        assert_eq!(assembly(0x8D4D), "c.or a0, a1");
+        assert_eq!(assembly(0x8D6D), "c.and a0, a1");
+        assert_eq!(assembly(0xAFFD), "c.j 2046");
+        assert_eq!(assembly(0xD101), "c.beqz a0, -256");
+        assert_eq!(assembly(0xEDFD), "c.bnez a1, 254");
+        assert_eq!(assembly(0x057E), "c.slli a0, 31");
+        assert_eq!(assembly(0x557E), "c.lwsp a0, 252, (sp)");
+        assert_eq!(assembly(0x8082), "c.jr ra");
+        assert_eq!(assembly(0x852E), "c.mv a0, a1");
+        assert_eq!(assembly(0x9502), "c.jalr a0");
+        assert_eq!(assembly(0x952E), "c.add a0, a1");
+        assert_eq!(assembly(0xDEAE), "c.swsp a1, 124, (sp)");
+        assert_eq!(assembly(0x3DE8), "c.fld fa0, 248, (a1)");
+        assert_eq!(assembly(0x7E6C), "c.flw fa1, 124, (a2)");
+        assert_eq!(assembly(0xA690), "c.fsd fa2, 8, (a3)");
+        assert_eq!(assembly(0xE334), "c.fsw fa3, 64, (a4)");
+        assert_eq!(assembly(0x347E), "c.fldsp fs0, 504, (sp)");
+        assert_eq!(assembly(0x707E), "c.flwsp ft0, 252, (sp)");
+        assert_eq!(assembly(0xBFA6), "c.fsdsp fs1, 504, (sp)");
+        assert_eq!(assembly(0xFF86), "c.fswsp ft1, 252, (sp)");
+    }
+
+    #[test]
+    fn test_expand_reserved() {
+        // all-zero parcel is defined to be illegal
+        assert!(expand(0x0000, &Xlen::X32).is_err());
+        // c.lwsp with rd = x0
+        assert!(expand(0x4002, &Xlen::X32).is_err());
+        // c.jr with rs1 = x0
+        assert!(expand(0x8002, &Xlen::X32).is_err());
+    }
+
+    #[test]
+    fn test_expand_rv64() {
+        let assembly = |parcel| expand(parcel, &Xlen::X64).ok().unwrap().assembly();
+        assert_eq!(assembly(0x6588), "c.ld a0, 8, (a1)");
+        assert_eq!(assembly(0xFDE8), "c.sd a0, 248, (a1)");
+        assert_eq!(assembly(0x357D), "c.addiw a0, -1");
+        assert_eq!(assembly(0x9D0D), "c.subw a0, a1");
+        assert_eq!(assembly(0x9D2D), "c.addw a0, a1");
+        assert_eq!(assembly(0x757E), "c.ldsp a0, 504, (sp)");
+        assert_eq!(assembly(0xFFAA), "c.sdsp a0, 504, (sp)");
+        assert_eq!(assembly(0x157E), "c.slli a0, 63");
+        assert_eq!(assembly(0x9581), "c.srai a1, 32");
+        // the RV64C encodings mean something else, or nothing, for RV32C
+        assert_eq!(
+            expand(0x6588, &Xlen::X32).ok().unwrap().assembly(),
+            "c.flw fa0, 8, (a1)"
+        );
+        assert!(expand(0x157E, &Xlen::X32).is_err());
+        assert!(expand(0x9D0D, &Xlen::X32).is_err());
+        // c.addiw with rd = x0
+        assert!(expand(0x3001, &Xlen::X64).is_err());
+    }
+}
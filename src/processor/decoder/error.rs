@@ -1,13 +1,22 @@
 use std::fmt::Display;
 
-use crate::processor::ProcessorErrorTrait;
-
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum InstructionDecodingErrorType {
     UndefinedBranchOption(u8),
     UndefinedByteWideOption(u8),
     UndefinedRiscvForm,
     StoreMustBeSigned,
     InvalidAluOperation,
+    UndefinedAtomicOperation(u8),
+    ReservedLoadReservedSource(u8),
+    UndefinedCompressedInstruction(u16),
+    UndefinedFloatFormat(u8),
+    InvalidFloatOperation(u8, u8),
+    UndefinedCsrOperation(u8),
+    UndefinedForXlen(u32),
+    UndefinedFenceOption(u8),
+    UndefinedOpcode(u8),
+    UnsupportedSystemInstruction,
 }
 
 impl Display for InstructionDecodingErrorType {
@@ -28,29 +37,43 @@ impl Display for InstructionDecodingErrorType {
             Self::InvalidAluOperation => {
                 write!(f, "invalid (funct7, funct3) value given")
             }
+            Self::UndefinedAtomicOperation(funct5) => {
+                write!(f, "get undefined atomic operation: {}", funct5)
+            }
+            Self::ReservedLoadReservedSource(rs2) => {
+                write!(f, "lr.w must have rs2 = 0, but get: {}", rs2)
+            }
+            Self::UndefinedCompressedInstruction(parcel) => {
+                write!(f, "get undefined compressed instruction: {:#06x}", parcel)
+            }
+            Self::UndefinedFloatFormat(fmt) => {
+                write!(f, "get undefined floating-point format: {}", fmt)
+            }
+            Self::InvalidFloatOperation(funct5, funct3) => {
+                write!(
+                    f,
+                    "invalid floating-point (funct5, funct3) value given: ({}, {})",
+                    funct5, funct3
+                )
+            }
+            Self::UndefinedCsrOperation(funct3) => {
+                write!(f, "get undefined csr operation: {}", funct3)
+            }
+            Self::UndefinedForXlen(xlen) => {
+                write!(f, "instruction is not defined for xlen = {}", xlen)
+            }
+            Self::UndefinedFenceOption(funct3) => {
+                write!(f, "get undefined fence option: {}", funct3)
+            }
+            Self::UndefinedOpcode(opecode) => {
+                write!(f, "get undefined opcode: {:#04x}", opecode)
+            }
+            Self::UnsupportedSystemInstruction => {
+                write!(
+                    f,
+                    "ecall, ebreak and privileged instructions are not supported"
+                )
+            }
         }
     }
 }
-
-pub struct InstructionDecodingError {
-    error_type: InstructionDecodingErrorType,
-}
-
-impl InstructionDecodingError {
-    pub fn new(error_type: InstructionDecodingErrorType) -> Box<Self> {
-        let error = Self { error_type };
-        Box::new(error)
-    }
-}
-
-impl Display for InstructionDecodingError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.form())
-    }
-}
-
-impl ProcessorErrorTrait for InstructionDecodingError {
-    fn form(&self) -> String {
-        format!("decode instruction is failed - {}", self.error_type)
-    }
-}
@@ -2,17 +2,19 @@ use std::fmt::Display;
 
 use anyhow::Result;
 
-use crate::processor::{register::RegisterAlias, ProcessorError};
-
-use super::{
-    error::{InstructionDecodingError, InstructionDecodingErrorType},
-    sign_extension,
+use crate::processor::{
+    csr::{csr_name, RoundingMode},
+    register::{FloatRegisterAlias, RegisterAlias},
+    ProcessorError,
 };
 
+use super::{compressed::CompressedCode, error::InstructionDecodingErrorType, sign_extension};
+
 pub trait RiscvInstruction {
     fn assembly(&self) -> String;
 }
 
+#[derive(Clone, Copy)]
 pub enum AluCode {
     Add,
     Sub,
@@ -24,6 +26,163 @@ pub enum AluCode {
     Xor,
     Or,
     And,
+    // Zba
+    Sh1add,
+    Sh2add,
+    Sh3add,
+    AddUw,
+    Sh1addUw,
+    Sh2addUw,
+    Sh3addUw,
+    SlliUw,
+    // Zbb
+    Andn,
+    Orn,
+    Xnor,
+    Clz,
+    Ctz,
+    Cpop,
+    Min,
+    MinUnsigned,
+    Max,
+    MaxUnsigned,
+    SextB,
+    SextH,
+    ZextH,
+    Rol,
+    Ror,
+    OrcB,
+    Rev8,
+    // Zbc
+    Clmul,
+    Clmulh,
+    Clmulr,
+    // Zbs
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
+    // M
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    DivUnsigned,
+    Rem,
+    RemUnsigned,
+}
+
+impl AluCode {
+    /// Whether the operation takes rs1 only, with rs2 (or the immediate) selecting it.
+    pub fn is_unary(&self) -> bool {
+        matches!(
+            self,
+            AluCode::Clz
+                | AluCode::Ctz
+                | AluCode::Cpop
+                | AluCode::SextB
+                | AluCode::SextH
+                | AluCode::ZextH
+                | AluCode::OrcB
+                | AluCode::Rev8
+        )
+    }
+
+    /// Decodes OP-32 and OP-IMM-32 of RV64.
+    /// The `.uw` forms and zext.h work on the full register, the others are W operations.
+    fn try_from_word(
+        funct7: u8,
+        funct3: u8,
+        rs2: u8,
+        imm: bool,
+    ) -> Result<InstructionCode, ProcessorError> {
+        let error_type = InstructionDecodingErrorType::InvalidAluOperation;
+        let error = ProcessorError::from(error_type);
+
+        let full = match (imm, funct7, funct3) {
+            (false, 0b0000100, 0b000) => Some(AluCode::AddUw),
+            (false, 0b0010000, 0b010) => Some(AluCode::Sh1addUw),
+            (false, 0b0010000, 0b100) => Some(AluCode::Sh2addUw),
+            (false, 0b0010000, 0b110) => Some(AluCode::Sh3addUw),
+            (false, 0b0000100, 0b100) if rs2 == 0 => Some(AluCode::ZextH),
+            // slli.uw has a 6-bit shamt, taking the lowest bit of funct7
+            (true, 0b0000100 | 0b0000101, 0b001) => Some(AluCode::SlliUw),
+            _ => None,
+        };
+        if let Some(code) = full {
+            return if imm {
+                Ok(InstructionCode::OpeI(code))
+            } else {
+                Ok(InstructionCode::Ope(code))
+            };
+        }
+
+        let code = match (imm, funct7, funct3) {
+            (true, _, 0b000) => AluCode::Add,
+            (false, 0b0000000, 0b000) => AluCode::Add,
+            (false, 0b0100000, 0b000) => AluCode::Sub,
+            (_, 0b0000000, 0b001) => AluCode::Sll,
+            (_, 0b0000000, 0b101) => AluCode::Srl,
+            (_, 0b0100000, 0b101) => AluCode::Sra,
+            (false, 0b0000001, 0b000) => AluCode::Mul,
+            (false, 0b0000001, 0b100) => AluCode::Div,
+            (false, 0b0000001, 0b101) => AluCode::DivUnsigned,
+            (false, 0b0000001, 0b110) => AluCode::Rem,
+            (false, 0b0000001, 0b111) => AluCode::RemUnsigned,
+            (false, 0b0110000, 0b001) => AluCode::Rol,
+            (_, 0b0110000, 0b101) => AluCode::Ror,
+            (true, 0b0110000, 0b001) => match rs2 {
+                0b00000 => AluCode::Clz,
+                0b00001 => AluCode::Ctz,
+                0b00010 => AluCode::Cpop,
+                _ => return Err(error),
+            },
+            _ => return Err(error),
+        };
+        if imm {
+            Ok(InstructionCode::OpeIW(code))
+        } else {
+            Ok(InstructionCode::OpeW(code))
+        }
+    }
+
+    fn try_from_bitmanip(funct7: u8, funct3: u8, rs2: u8, imm: bool) -> Option<Self> {
+        let code = match (imm, funct7, funct3) {
+            (false, 0b0010000, 0b010) => AluCode::Sh1add,
+            (false, 0b0010000, 0b100) => AluCode::Sh2add,
+            (false, 0b0010000, 0b110) => AluCode::Sh3add,
+            (false, 0b0100000, 0b111) => AluCode::Andn,
+            (false, 0b0100000, 0b110) => AluCode::Orn,
+            (false, 0b0100000, 0b100) => AluCode::Xnor,
+            (true, 0b0110000, 0b001) => match rs2 {
+                0b00000 => AluCode::Clz,
+                0b00001 => AluCode::Ctz,
+                0b00010 => AluCode::Cpop,
+                0b00100 => AluCode::SextB,
+                0b00101 => AluCode::SextH,
+                _ => return None,
+            },
+            (false, 0b0000101, 0b100) => AluCode::Min,
+            (false, 0b0000101, 0b101) => AluCode::MinUnsigned,
+            (false, 0b0000101, 0b110) => AluCode::Max,
+            (false, 0b0000101, 0b111) => AluCode::MaxUnsigned,
+            (false, 0b0000100, 0b100) if rs2 == 0 => AluCode::ZextH,
+            (false, 0b0110000, 0b001) => AluCode::Rol,
+            (_, 0b0110000, 0b101) => AluCode::Ror,
+            (true, 0b0010100, 0b101) if rs2 == 0b00111 => AluCode::OrcB,
+            (true, 0b0110100, 0b101) if rs2 == 0b11000 => AluCode::Rev8,
+            (false, 0b0000101, 0b001) => AluCode::Clmul,
+            (false, 0b0000101, 0b011) => AluCode::Clmulh,
+            (false, 0b0000101, 0b010) => AluCode::Clmulr,
+            (_, 0b0100100, 0b001) => AluCode::Bclr,
+            (_, 0b0100100, 0b101) => AluCode::Bext,
+            (_, 0b0110100, 0b001) => AluCode::Binv,
+            (_, 0b0010100, 0b001) => AluCode::Bset,
+            _ => return None,
+        };
+        Some(code)
+    }
 }
 
 impl RiscvInstruction for AluCode {
@@ -39,42 +198,91 @@ impl RiscvInstruction for AluCode {
             AluCode::Xor => "xor",
             AluCode::Or => "or",
             AluCode::And => "and",
+            AluCode::Sh1add => "sh1add",
+            AluCode::Sh2add => "sh2add",
+            AluCode::Sh3add => "sh3add",
+            AluCode::AddUw => "add.uw",
+            AluCode::Sh1addUw => "sh1add.uw",
+            AluCode::Sh2addUw => "sh2add.uw",
+            AluCode::Sh3addUw => "sh3add.uw",
+            AluCode::SlliUw => "slli.uw",
+            AluCode::Andn => "andn",
+            AluCode::Orn => "orn",
+            AluCode::Xnor => "xnor",
+            AluCode::Clz => "clz",
+            AluCode::Ctz => "ctz",
+            AluCode::Cpop => "cpop",
+            AluCode::Min => "min",
+            AluCode::MinUnsigned => "minu",
+            AluCode::Max => "max",
+            AluCode::MaxUnsigned => "maxu",
+            AluCode::SextB => "sext.b",
+            AluCode::SextH => "sext.h",
+            AluCode::ZextH => "zext.h",
+            AluCode::Rol => "rol",
+            AluCode::Ror => "ror",
+            AluCode::OrcB => "orc.b",
+            AluCode::Rev8 => "rev8",
+            AluCode::Clmul => "clmul",
+            AluCode::Clmulh => "clmulh",
+            AluCode::Clmulr => "clmulr",
+            AluCode::Bclr => "bclr",
+            AluCode::Bext => "bext",
+            AluCode::Binv => "binv",
+            AluCode::Bset => "bset",
+            AluCode::Mul => "mul",
+            AluCode::Mulh => "mulh",
+            AluCode::Mulhsu => "mulhsu",
+            AluCode::Mulhu => "mulhu",
+            AluCode::Div => "div",
+            AluCode::DivUnsigned => "divu",
+            AluCode::Rem => "rem",
+            AluCode::RemUnsigned => "remu",
         }
         .into()
     }
 }
 
-impl TryFrom<(u8, u8, bool)> for AluCode {
+impl TryFrom<(u8, u8, u8, bool)> for AluCode {
     type Error = ProcessorError;
 
-    fn try_from((funct7, funct3, imm): (u8, u8, bool)) -> Result<Self, Self::Error> {
+    fn try_from((funct7, funct3, rs2, imm): (u8, u8, u8, bool)) -> Result<Self, Self::Error> {
         let error_type = InstructionDecodingErrorType::InvalidAluOperation;
-        let error = InstructionDecodingError::new(error_type);
+        let error = ProcessorError::from(error_type);
 
-        match funct3 {
-            0b000 => {
-                if imm {
-                    Ok(AluCode::Add)
-                } else {
-                    match funct7 {
-                        0b000000 => Ok(AluCode::Add),
-                        0b010000 => Ok(AluCode::Sub),
-                        _ => Err(error),
-                    }
-                }
-            }
-            0b001 => Ok(AluCode::Sll),
-            0b010 => Ok(AluCode::Slt),
-            0b011 => Ok(AluCode::Sltu),
-            0b100 => Ok(AluCode::Xor),
-            0b101 => match funct7 {
-                0b000000 => Ok(AluCode::Srl),
-                0b010000 => Ok(AluCode::Sra),
-                _ => Err(error),
-            },
-            0b110 => Ok(AluCode::Or),
-            0b111 => Ok(AluCode::And),
-            _ => unreachable!(),
+        // immediate forms other than shifts have no funct7
+        if imm && funct3 != 0b001 && funct3 != 0b101 {
+            return match funct3 {
+                0b000 => Ok(AluCode::Add),
+                0b010 => Ok(AluCode::Slt),
+                0b011 => Ok(AluCode::Sltu),
+                0b100 => Ok(AluCode::Xor),
+                0b110 => Ok(AluCode::Or),
+                0b111 => Ok(AluCode::And),
+                _ => unreachable!(),
+            };
+        }
+
+        match (funct7, funct3) {
+            (0b0000000, 0b000) => Ok(AluCode::Add),
+            (0b0100000, 0b000) if !imm => Ok(AluCode::Sub),
+            (0b0000000, 0b001) => Ok(AluCode::Sll),
+            (0b0000000, 0b010) => Ok(AluCode::Slt),
+            (0b0000000, 0b011) => Ok(AluCode::Sltu),
+            (0b0000000, 0b100) => Ok(AluCode::Xor),
+            (0b0000000, 0b101) => Ok(AluCode::Srl),
+            (0b0100000, 0b101) => Ok(AluCode::Sra),
+            (0b0000000, 0b110) => Ok(AluCode::Or),
+            (0b0000000, 0b111) => Ok(AluCode::And),
+            (0b0000001, 0b000) if !imm => Ok(AluCode::Mul),
+            (0b0000001, 0b001) if !imm => Ok(AluCode::Mulh),
+            (0b0000001, 0b010) if !imm => Ok(AluCode::Mulhsu),
+            (0b0000001, 0b011) if !imm => Ok(AluCode::Mulhu),
+            (0b0000001, 0b100) if !imm => Ok(AluCode::Div),
+            (0b0000001, 0b101) if !imm => Ok(AluCode::DivUnsigned),
+            (0b0000001, 0b110) if !imm => Ok(AluCode::Rem),
+            (0b0000001, 0b111) if !imm => Ok(AluCode::RemUnsigned),
+            _ => AluCode::try_from_bitmanip(funct7, funct3, rs2, imm).ok_or(error),
         }
     }
 }
@@ -85,6 +293,7 @@ impl Display for AluCode {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum BranchOption {
     Equal,
     NotEqual,
@@ -121,7 +330,7 @@ impl TryFrom<u8> for BranchOption {
             0b111 => Ok(BranchOption::GraterEqualUnsigned),
             _ => {
                 let error_type = InstructionDecodingErrorType::UndefinedBranchOption(funct3);
-                Err(InstructionDecodingError::new(error_type))
+                Err(ProcessorError::from(error_type))
             }
         }
     }
@@ -133,38 +342,38 @@ impl Display for BranchOption {
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum ByteWideOption {
     Byte,
     HalfWord,
     Word,
+    DoubleWord,
     ByteUnsigned,
     HalfWordUnsigned,
+    WordUnsigned,
 }
 
 impl ByteWideOption {
-    pub fn trim(&self, raw: u32, diff: u32) -> u32 {
+    /// Access width in bytes.
+    pub fn width(&self) -> u64 {
         match self {
-            ByteWideOption::Byte => {
-                let value = (raw >> (diff * 8)) % 2u32.pow(8);
-                sign_extension(value, 7)
-            }
-            ByteWideOption::ByteUnsigned => (raw >> (diff * 8)) % 2u32.pow(8),
-            ByteWideOption::HalfWord => {
-                let value = (raw >> (diff * 8)) % 2u32.pow(16);
-                sign_extension(value, 15)
-            }
-            ByteWideOption::HalfWordUnsigned => (raw >> (diff * 8)) % 2u32.pow(16),
-            ByteWideOption::Word => raw,
+            ByteWideOption::Byte | ByteWideOption::ByteUnsigned => 1,
+            ByteWideOption::HalfWord | ByteWideOption::HalfWordUnsigned => 2,
+            ByteWideOption::Word | ByteWideOption::WordUnsigned => 4,
+            ByteWideOption::DoubleWord => 8,
         }
     }
 
-    pub fn overwrite_mask(&self, diff: u32) -> u32 {
+    /// Extends the loaded little-endian bytes to 64 bits, by sign for signed loads.
+    pub fn extend(&self, raw: u64) -> u64 {
         match self {
-            ByteWideOption::Byte | ByteWideOption::ByteUnsigned => (1 << (8 * diff)) * 0xFF,
-            ByteWideOption::HalfWord | ByteWideOption::HalfWordUnsigned => {
-                (1 << (8 * diff)) * 0xFFFF
-            }
-            ByteWideOption::Word => 0xFFFF_FFFF,
+            ByteWideOption::Byte => sign_extension(raw % 2u64.pow(8), 7),
+            ByteWideOption::ByteUnsigned => raw % 2u64.pow(8),
+            ByteWideOption::HalfWord => sign_extension(raw % 2u64.pow(16), 15),
+            ByteWideOption::HalfWordUnsigned => raw % 2u64.pow(16),
+            ByteWideOption::Word => sign_extension(raw % 2u64.pow(32), 31),
+            ByteWideOption::WordUnsigned => raw % 2u64.pow(32),
+            ByteWideOption::DoubleWord => raw,
         }
     }
 }
@@ -177,6 +386,8 @@ impl RiscvInstruction for ByteWideOption {
             ByteWideOption::HalfWord => "h",
             ByteWideOption::HalfWordUnsigned => "hu",
             ByteWideOption::Word => "w",
+            ByteWideOption::WordUnsigned => "wu",
+            ByteWideOption::DoubleWord => "d",
         }
         .into()
     }
@@ -190,11 +401,13 @@ impl TryFrom<u8> for ByteWideOption {
             0b000 => Ok(ByteWideOption::Byte),
             0b001 => Ok(ByteWideOption::HalfWord),
             0b010 => Ok(ByteWideOption::Word),
+            0b011 => Ok(ByteWideOption::DoubleWord),
             0b100 => Ok(ByteWideOption::ByteUnsigned),
             0b101 => Ok(ByteWideOption::HalfWordUnsigned),
+            0b110 => Ok(ByteWideOption::WordUnsigned),
             _ => {
                 let error_type = InstructionDecodingErrorType::UndefinedByteWideOption(funct3);
-                Err(InstructionDecodingError::new(error_type))
+                Err(ProcessorError::from(error_type))
             }
         }
     }
@@ -206,6 +419,429 @@ impl Display for ByteWideOption {
     }
 }
 
+pub enum AtomicCode {
+    LoadReserved,
+    StoreConditional,
+    Swap,
+    Add,
+    Xor,
+    And,
+    Or,
+    Min,
+    Max,
+    MinUnsigned,
+    MaxUnsigned,
+}
+
+impl RiscvInstruction for AtomicCode {
+    fn assembly(&self) -> String {
+        match self {
+            AtomicCode::LoadReserved => "lr",
+            AtomicCode::StoreConditional => "sc",
+            AtomicCode::Swap => "amoswap",
+            AtomicCode::Add => "amoadd",
+            AtomicCode::Xor => "amoxor",
+            AtomicCode::And => "amoand",
+            AtomicCode::Or => "amoor",
+            AtomicCode::Min => "amomin",
+            AtomicCode::Max => "amomax",
+            AtomicCode::MinUnsigned => "amominu",
+            AtomicCode::MaxUnsigned => "amomaxu",
+        }
+        .into()
+    }
+}
+
+impl TryFrom<u8> for AtomicCode {
+    type Error = ProcessorError;
+
+    fn try_from(funct5: u8) -> Result<Self, Self::Error> {
+        match funct5 {
+            0b00010 => Ok(AtomicCode::LoadReserved),
+            0b00011 => Ok(AtomicCode::StoreConditional),
+            0b00001 => Ok(AtomicCode::Swap),
+            0b00000 => Ok(AtomicCode::Add),
+            0b00100 => Ok(AtomicCode::Xor),
+            0b01100 => Ok(AtomicCode::And),
+            0b01000 => Ok(AtomicCode::Or),
+            0b10000 => Ok(AtomicCode::Min),
+            0b10100 => Ok(AtomicCode::Max),
+            0b11000 => Ok(AtomicCode::MinUnsigned),
+            0b11100 => Ok(AtomicCode::MaxUnsigned),
+            _ => {
+                let error_type = InstructionDecodingErrorType::UndefinedAtomicOperation(funct5);
+                Err(ProcessorError::from(error_type))
+            }
+        }
+    }
+}
+
+impl Display for AtomicCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
+pub enum AtomicOrdering {
+    Relaxed,
+    Acquire,
+    Release,
+    AcquireRelease,
+}
+
+impl RiscvInstruction for AtomicOrdering {
+    fn assembly(&self) -> String {
+        match self {
+            AtomicOrdering::Relaxed => "",
+            AtomicOrdering::Acquire => ".aq",
+            AtomicOrdering::Release => ".rl",
+            AtomicOrdering::AcquireRelease => ".aqrl",
+        }
+        .into()
+    }
+}
+
+impl From<u8> for AtomicOrdering {
+    fn from(funct7: u8) -> Self {
+        match funct7 % 4 {
+            0b00 => AtomicOrdering::Relaxed,
+            0b10 => AtomicOrdering::Acquire,
+            0b01 => AtomicOrdering::Release,
+            0b11 => AtomicOrdering::AcquireRelease,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Display for AtomicOrdering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
+pub enum FloatFormat {
+    Single,
+    Double,
+}
+
+impl RiscvInstruction for FloatFormat {
+    fn assembly(&self) -> String {
+        match self {
+            FloatFormat::Single => "s",
+            FloatFormat::Double => "d",
+        }
+        .into()
+    }
+}
+
+impl TryFrom<u8> for FloatFormat {
+    type Error = ProcessorError;
+
+    fn try_from(fmt: u8) -> Result<Self, Self::Error> {
+        match fmt {
+            0b00 => Ok(FloatFormat::Single),
+            0b01 => Ok(FloatFormat::Double),
+            _ => {
+                let error_type = InstructionDecodingErrorType::UndefinedFloatFormat(fmt);
+                Err(ProcessorError::from(error_type))
+            }
+        }
+    }
+}
+
+impl FloatFormat {
+    /// Suffix of the memory access and move mnemonics.
+    pub fn width(&self) -> &str {
+        match self {
+            FloatFormat::Single => "w",
+            FloatFormat::Double => "d",
+        }
+    }
+
+    /// Size of a value in memory.
+    pub fn bytes(&self) -> u64 {
+        match self {
+            FloatFormat::Single => 4,
+            FloatFormat::Double => 8,
+        }
+    }
+}
+
+impl Display for FloatFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
+pub enum FloatCode {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Sqrt,
+    SignInject,
+    SignInjectNegate,
+    SignInjectXor,
+    Min,
+    Max,
+    // fcvt.s.d / fcvt.d.s, converting into the instruction format
+    ConvertFloat,
+    Equal,
+    LessThan,
+    LessEqual,
+    ConvertToInt,
+    ConvertToIntUnsigned,
+    ConvertFromInt,
+    ConvertFromIntUnsigned,
+    // RV64 only
+    ConvertToLong,
+    ConvertToLongUnsigned,
+    ConvertFromLong,
+    ConvertFromLongUnsigned,
+    MoveToInt,
+    MoveFromInt,
+    Classify,
+}
+
+impl FloatCode {
+    /// Whether rd is an integer register.
+    pub fn writes_integer(&self) -> bool {
+        matches!(
+            self,
+            FloatCode::Equal
+                | FloatCode::LessThan
+                | FloatCode::LessEqual
+                | FloatCode::ConvertToInt
+                | FloatCode::ConvertToIntUnsigned
+                | FloatCode::ConvertToLong
+                | FloatCode::ConvertToLongUnsigned
+                | FloatCode::MoveToInt
+                | FloatCode::Classify
+        )
+    }
+
+    /// Whether rs1 is an integer register.
+    pub fn reads_integer(&self) -> bool {
+        matches!(
+            self,
+            FloatCode::ConvertFromInt
+                | FloatCode::ConvertFromIntUnsigned
+                | FloatCode::ConvertFromLong
+                | FloatCode::ConvertFromLongUnsigned
+                | FloatCode::MoveFromInt
+        )
+    }
+
+    /// Whether rs2 is a second source rather than an extension of the opcode.
+    pub fn reads_second(&self) -> bool {
+        matches!(
+            self,
+            FloatCode::Add
+                | FloatCode::Sub
+                | FloatCode::Mul
+                | FloatCode::Div
+                | FloatCode::SignInject
+                | FloatCode::SignInjectNegate
+                | FloatCode::SignInjectXor
+                | FloatCode::Min
+                | FloatCode::Max
+                | FloatCode::Equal
+                | FloatCode::LessThan
+                | FloatCode::LessEqual
+        )
+    }
+
+    /// Whether the funct3 field is a rounding mode rather than a sub-operation.
+    pub fn uses_rounding_mode(&self) -> bool {
+        matches!(
+            self,
+            FloatCode::Add
+                | FloatCode::Sub
+                | FloatCode::Mul
+                | FloatCode::Div
+                | FloatCode::Sqrt
+                | FloatCode::ConvertFloat
+                | FloatCode::ConvertToInt
+                | FloatCode::ConvertToIntUnsigned
+                | FloatCode::ConvertFromInt
+                | FloatCode::ConvertFromIntUnsigned
+                | FloatCode::ConvertToLong
+                | FloatCode::ConvertToLongUnsigned
+                | FloatCode::ConvertFromLong
+                | FloatCode::ConvertFromLongUnsigned
+        )
+    }
+
+    /// Whether the operation is only defined for RV64 with the format.
+    pub fn requires_rv64(&self, format: &FloatFormat) -> bool {
+        match self {
+            FloatCode::ConvertToLong
+            | FloatCode::ConvertToLongUnsigned
+            | FloatCode::ConvertFromLong
+            | FloatCode::ConvertFromLongUnsigned => true,
+            FloatCode::MoveToInt | FloatCode::MoveFromInt => {
+                matches!(format, FloatFormat::Double)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl RiscvInstruction for FloatCode {
+    fn assembly(&self) -> String {
+        match self {
+            FloatCode::Add => "fadd",
+            FloatCode::Sub => "fsub",
+            FloatCode::Mul => "fmul",
+            FloatCode::Div => "fdiv",
+            FloatCode::Sqrt => "fsqrt",
+            FloatCode::SignInject => "fsgnj",
+            FloatCode::SignInjectNegate => "fsgnjn",
+            FloatCode::SignInjectXor => "fsgnjx",
+            FloatCode::Min => "fmin",
+            FloatCode::Max => "fmax",
+            FloatCode::ConvertFloat => "fcvt",
+            FloatCode::Equal => "feq",
+            FloatCode::LessThan => "flt",
+            FloatCode::LessEqual => "fle",
+            FloatCode::ConvertToInt | FloatCode::ConvertFromInt => "fcvt",
+            FloatCode::ConvertToIntUnsigned | FloatCode::ConvertFromIntUnsigned => "fcvt",
+            FloatCode::ConvertToLong | FloatCode::ConvertFromLong => "fcvt",
+            FloatCode::ConvertToLongUnsigned | FloatCode::ConvertFromLongUnsigned => "fcvt",
+            FloatCode::MoveToInt | FloatCode::MoveFromInt => "fmv",
+            FloatCode::Classify => "fclass",
+        }
+        .into()
+    }
+}
+
+impl TryFrom<(u8, u8, u8)> for FloatCode {
+    type Error = ProcessorError;
+
+    fn try_from((funct5, funct3, rs2): (u8, u8, u8)) -> Result<Self, Self::Error> {
+        let error_type = InstructionDecodingErrorType::InvalidFloatOperation(funct5, funct3);
+        let error = ProcessorError::from(error_type);
+
+        match (funct5, funct3, rs2) {
+            (0b00000, _, _) => Ok(FloatCode::Add),
+            (0b00001, _, _) => Ok(FloatCode::Sub),
+            (0b00010, _, _) => Ok(FloatCode::Mul),
+            (0b00011, _, _) => Ok(FloatCode::Div),
+            (0b01011, _, 0) => Ok(FloatCode::Sqrt),
+            (0b00100, 0b000, _) => Ok(FloatCode::SignInject),
+            (0b00100, 0b001, _) => Ok(FloatCode::SignInjectNegate),
+            (0b00100, 0b010, _) => Ok(FloatCode::SignInjectXor),
+            (0b00101, 0b000, _) => Ok(FloatCode::Min),
+            (0b00101, 0b001, _) => Ok(FloatCode::Max),
+            (0b01000, _, 0 | 1) => Ok(FloatCode::ConvertFloat),
+            (0b10100, 0b010, _) => Ok(FloatCode::Equal),
+            (0b10100, 0b001, _) => Ok(FloatCode::LessThan),
+            (0b10100, 0b000, _) => Ok(FloatCode::LessEqual),
+            (0b11000, _, 0) => Ok(FloatCode::ConvertToInt),
+            (0b11000, _, 1) => Ok(FloatCode::ConvertToIntUnsigned),
+            (0b11000, _, 2) => Ok(FloatCode::ConvertToLong),
+            (0b11000, _, 3) => Ok(FloatCode::ConvertToLongUnsigned),
+            (0b11010, _, 0) => Ok(FloatCode::ConvertFromInt),
+            (0b11010, _, 1) => Ok(FloatCode::ConvertFromIntUnsigned),
+            (0b11010, _, 2) => Ok(FloatCode::ConvertFromLong),
+            (0b11010, _, 3) => Ok(FloatCode::ConvertFromLongUnsigned),
+            (0b11100, 0b000, 0) => Ok(FloatCode::MoveToInt),
+            (0b11100, 0b001, 0) => Ok(FloatCode::Classify),
+            (0b11110, 0b000, 0) => Ok(FloatCode::MoveFromInt),
+            _ => Err(error),
+        }
+    }
+}
+
+impl Display for FloatCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
+pub enum FusedCode {
+    MulAdd,
+    MulSub,
+    NegateMulSub,
+    NegateMulAdd,
+}
+
+impl RiscvInstruction for FusedCode {
+    fn assembly(&self) -> String {
+        match self {
+            FusedCode::MulAdd => "fmadd",
+            FusedCode::MulSub => "fmsub",
+            FusedCode::NegateMulSub => "fnmsub",
+            FusedCode::NegateMulAdd => "fnmadd",
+        }
+        .into()
+    }
+}
+
+impl Display for FusedCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
+pub enum CsrCode {
+    ReadWrite,
+    ReadSet,
+    ReadClear,
+    ReadWriteImmediate,
+    ReadSetImmediate,
+    ReadClearImmediate,
+}
+
+impl CsrCode {
+    /// Whether the source operand is the 5-bit zimm in the rs1 field.
+    pub fn is_immediate(&self) -> bool {
+        matches!(
+            self,
+            CsrCode::ReadWriteImmediate | CsrCode::ReadSetImmediate | CsrCode::ReadClearImmediate
+        )
+    }
+}
+
+impl RiscvInstruction for CsrCode {
+    fn assembly(&self) -> String {
+        match self {
+            CsrCode::ReadWrite => "csrrw",
+            CsrCode::ReadSet => "csrrs",
+            CsrCode::ReadClear => "csrrc",
+            CsrCode::ReadWriteImmediate => "csrrwi",
+            CsrCode::ReadSetImmediate => "csrrsi",
+            CsrCode::ReadClearImmediate => "csrrci",
+        }
+        .into()
+    }
+}
+
+impl TryFrom<u8> for CsrCode {
+    type Error = ProcessorError;
+
+    fn try_from(funct3: u8) -> Result<Self, Self::Error> {
+        match funct3 {
+            0b001 => Ok(CsrCode::ReadWrite),
+            0b010 => Ok(CsrCode::ReadSet),
+            0b011 => Ok(CsrCode::ReadClear),
+            0b101 => Ok(CsrCode::ReadWriteImmediate),
+            0b110 => Ok(CsrCode::ReadSetImmediate),
+            0b111 => Ok(CsrCode::ReadClearImmediate),
+            _ => {
+                let error_type = InstructionDecodingErrorType::UndefinedCsrOperation(funct3);
+                Err(ProcessorError::from(error_type))
+            }
+        }
+    }
+}
+
+impl Display for CsrCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
 pub enum InstructionCode {
     Lui,
     Auipc,
@@ -216,12 +852,22 @@ pub enum InstructionCode {
     Store(ByteWideOption),
     Ope(AluCode),
     OpeI(AluCode),
+    OpeW(AluCode),
+    OpeIW(AluCode),
+    Atomic(AtomicCode, AtomicOrdering),
+    LoadFloat(FloatFormat),
+    StoreFloat(FloatFormat),
+    FloatOpe(FloatCode, FloatFormat),
+    Fused(FusedCode, FloatFormat),
+    Csr(CsrCode),
+    Fence,
+    FenceI,
 }
 
-impl TryFrom<(u8, u8, u8)> for InstructionCode {
+impl TryFrom<(u8, u8, u8, u8)> for InstructionCode {
     type Error = ProcessorError;
 
-    fn try_from((funct7, funct3, opecode): (u8, u8, u8)) -> Result<Self, Self::Error> {
+    fn try_from((funct7, funct3, opecode, rs2): (u8, u8, u8, u8)) -> Result<Self, Self::Error> {
         match opecode {
             0x37 => Ok(InstructionCode::Lui),
             0x17 => Ok(InstructionCode::Auipc),
@@ -241,39 +887,119 @@ impl TryFrom<(u8, u8, u8)> for InstructionCode {
                 // STORE
                 let bytewide_option = ByteWideOption::try_from(funct3)?;
                 match bytewide_option {
-                    ByteWideOption::ByteUnsigned | ByteWideOption::HalfWordUnsigned => {
+                    ByteWideOption::ByteUnsigned
+                    | ByteWideOption::HalfWordUnsigned
+                    | ByteWideOption::WordUnsigned => {
                         let error_type = InstructionDecodingErrorType::StoreMustBeSigned;
-                        let error = InstructionDecodingError::new(error_type);
+                        let error = ProcessorError::from(error_type);
                         Err(error)
                     }
                     _ => Ok(InstructionCode::Store(bytewide_option)),
                 }
             }
             0x13 => {
-                // OpeI
-                let code = AluCode::try_from((funct7, funct3, true))?;
+                // OpeI: the lowest bit of funct7 is shamt[5] for RV64 shifts
+                let funct7 = match funct3 {
+                    0b001 | 0b101 => funct7 & !1,
+                    _ => funct7,
+                };
+                let code = AluCode::try_from((funct7, funct3, rs2, true))?;
                 Ok(InstructionCode::OpeI(code))
             }
+            0x1B => {
+                // OpeIW
+                AluCode::try_from_word(funct7, funct3, rs2, true)
+            }
+            0x3B => {
+                // OpeW
+                AluCode::try_from_word(funct7, funct3, rs2, false)
+            }
             0x33 => {
                 // Ope
-                let code = AluCode::try_from((funct7, funct3, false))?;
+                let code = AluCode::try_from((funct7, funct3, rs2, false))?;
                 Ok(InstructionCode::Ope(code))
             }
+            0x2F => {
+                // AMO: the doubleword forms of RV64A are not supported
+                if funct3 != 0b010 {
+                    let error_type = InstructionDecodingErrorType::UndefinedByteWideOption(funct3);
+                    return Err(ProcessorError::from(error_type));
+                }
+                let code = AtomicCode::try_from(funct7 >> 2)?;
+                let ordering = AtomicOrdering::from(funct7);
+                Ok(InstructionCode::Atomic(code, ordering))
+            }
+            0x07 | 0x27 => {
+                // LOAD-FP / STORE-FP
+                let format = match funct3 {
+                    0b010 => FloatFormat::Single,
+                    0b011 => FloatFormat::Double,
+                    _ => {
+                        let error_type =
+                            InstructionDecodingErrorType::UndefinedByteWideOption(funct3);
+                        return Err(ProcessorError::from(error_type));
+                    }
+                };
+                if opecode == 0x07 {
+                    Ok(InstructionCode::LoadFloat(format))
+                } else {
+                    Ok(InstructionCode::StoreFloat(format))
+                }
+            }
+            0x53 => {
+                // OP-FP
+                let format = FloatFormat::try_from(funct7 % 4)?;
+                let code = FloatCode::try_from((funct7 >> 2, funct3, rs2))?;
+                // fcvt between formats names the other one as its source in rs2
+                if matches!(code, FloatCode::ConvertFloat) && rs2 != (funct7 % 4) ^ 1 {
+                    let error_type =
+                        InstructionDecodingErrorType::InvalidFloatOperation(funct7 >> 2, funct3);
+                    return Err(ProcessorError::from(error_type));
+                }
+                Ok(InstructionCode::FloatOpe(code, format))
+            }
+            0x43 | 0x47 | 0x4B | 0x4F => {
+                // fused multiply-add
+                let format = FloatFormat::try_from(funct7 % 4)?;
+                let code = match opecode {
+                    0x43 => FusedCode::MulAdd,
+                    0x47 => FusedCode::MulSub,
+                    0x4B => FusedCode::NegateMulSub,
+                    _ => FusedCode::NegateMulAdd,
+                };
+                Ok(InstructionCode::Fused(code, format))
+            }
             0x0F => {
-                // fence
-                unimplemented!()
+                // MISC-MEM: memory is sequentially consistent here, so pred/succ are ignored
+                match funct3 {
+                    0b000 => Ok(InstructionCode::Fence),
+                    0b001 => Ok(InstructionCode::FenceI),
+                    _ => {
+                        let error_type = InstructionDecodingErrorType::UndefinedFenceOption(funct3);
+                        Err(ProcessorError::from(error_type))
+                    }
+                }
             }
             0x73 => {
                 // csrr
-                unimplemented!()
+                if funct3 == 0b000 {
+                    let error_type = InstructionDecodingErrorType::UnsupportedSystemInstruction;
+                    return Err(ProcessorError::from(error_type));
+                }
+                let code = CsrCode::try_from(funct3)?;
+                Ok(InstructionCode::Csr(code))
+            }
+            _ => {
+                let error_type = InstructionDecodingErrorType::UndefinedOpcode(opecode);
+                Err(ProcessorError::from(error_type))
             }
-            _ => unimplemented!(),
         }
     }
 }
 
 pub enum RiscvForm {
     R,
+    R4,
     I,
     B,
     S,
@@ -288,15 +1014,16 @@ impl TryFrom<u8> for RiscvForm {
 
     fn try_from(opecode: u8) -> Result<Self, Self::Error> {
         match opecode {
-            51 => Ok(RiscvForm::R),
-            3 | 19 | 103 => Ok(RiscvForm::I),
+            47 | 51 | 59 | 83 => Ok(RiscvForm::R),
+            67 | 71 | 75 | 79 => Ok(RiscvForm::R4),
+            3 | 7 | 15 | 19 | 27 | 103 | 115 => Ok(RiscvForm::I),
             99 => Ok(RiscvForm::B),
-            35 => Ok(RiscvForm::S),
+            35 | 39 => Ok(RiscvForm::S),
             111 => Ok(RiscvForm::J),
             23 | 55 => Ok(RiscvForm::U),
             _ => {
                 let error_type = InstructionDecodingErrorType::UndefinedRiscvForm;
-                Err(InstructionDecodingError::new(error_type))
+                Err(ProcessorError::from(error_type))
             }
         }
     }
@@ -309,8 +1036,12 @@ pub struct Instruction {
     pub rs1: u8,
     pub rs2: u8,
     pub rd: u8,
-    pub imm: u32,
+    pub rs3: u8,
+    // rounding mode of floating-point operations, otherwise funct3
+    pub rm: u8,
+    pub imm: u64,
     pub is_halt: bool,
+    pub compressed: Option<CompressedCode>,
 }
 
 impl Instruction {
@@ -319,7 +1050,7 @@ impl Instruction {
         code: InstructionCode,
         form: RiscvForm,
         registers: [u8; 3],
-        imm: u32,
+        imm: u64,
     ) -> Self {
         let is_halt = instruction == 0;
 
@@ -330,18 +1061,71 @@ impl Instruction {
             rs1: registers[0],
             rs2: registers[1],
             rd: registers[2],
+            rs3: (instruction >> 27) as u8,
+            rm: ((instruction >> 12) % 8) as u8,
             imm,
             is_halt,
+            compressed: None,
+        }
+    }
+
+    pub fn new_compressed(
+        parcel: u32,
+        compressed: CompressedCode,
+        code: InstructionCode,
+        form: RiscvForm,
+        registers: [u8; 3],
+        imm: u64,
+    ) -> Self {
+        let mut inst = Self::new(parcel, code, form, registers, imm);
+        inst.compressed = Some(compressed);
+        inst
+    }
+
+    /// CSR address held in the immediate field of Zicsr instructions.
+    pub fn csr(&self) -> u16 {
+        (self.imm % 4096) as u16
+    }
+
+    /// The encoding as fetched; a compressed parcel is in the lower 16 bits.
+    pub fn raw(&self) -> u32 {
+        self._inst
+    }
+
+    /// Length of the encoding in bytes.
+    pub fn size(&self) -> u64 {
+        match self.compressed {
+            Some(_) => 2,
+            None => 4,
         }
     }
 }
 
 impl RiscvInstruction for Instruction {
     fn assembly(&self) -> String {
+        match &self.compressed {
+            Some(compressed) => compressed.assembly_with(self),
+            None => self.expanded_assembly(),
+        }
+    }
+}
+
+impl Instruction {
+    /// Name of the operation, the same for a compressed instruction and its expansion.
+    pub fn mnemonic(&self) -> String {
+        let assembly = self.expanded_assembly();
+        match assembly.split_once(' ') {
+            Some((mnemonic, _)) => mnemonic.into(),
+            None => assembly,
+        }
+    }
+
+    // the assembly of the 32-bit form
+    fn expanded_assembly(&self) -> String {
         let rs1 = RegisterAlias::try_from(self.rs1).ok().unwrap();
         let rs2 = RegisterAlias::try_from(self.rs2).ok().unwrap();
         let rd = RegisterAlias::try_from(self.rd).ok().unwrap();
-        let imm = self.imm as i32;
+        let imm = self.imm as i64;
 
         match &self.code {
             InstructionCode::Auipc => {
@@ -365,19 +1149,133 @@ impl RiscvInstruction for Instruction {
             InstructionCode::Lui => {
                 format!("lui {}, {}", rd, imm)
             }
+            InstructionCode::Ope(ope) | InstructionCode::OpeI(ope) if ope.is_unary() => {
+                format!("{} {}, {}", ope, rd, rs1)
+            }
             InstructionCode::Ope(ope) => {
                 format!("{} {}, {}, {}", ope, rd, rs1, rs2)
             }
+            InstructionCode::OpeI(AluCode::SlliUw) => {
+                format!("slli.uw {}, {}, {}", rd, rs1, imm)
+            }
             InstructionCode::OpeI(ope) => {
                 format!("{}i {}, {}, {}", ope, rd, rs1, imm)
             }
+            InstructionCode::OpeW(ope) | InstructionCode::OpeIW(ope) if ope.is_unary() => {
+                format!("{}w {}, {}", ope, rd, rs1)
+            }
+            InstructionCode::OpeW(ope) => {
+                format!("{}w {}, {}, {}", ope, rd, rs1, rs2)
+            }
+            InstructionCode::OpeIW(ope) => {
+                format!("{}iw {}, {}, {}", ope, rd, rs1, imm)
+            }
+            InstructionCode::Atomic(AtomicCode::LoadReserved, ord) => {
+                format!("lr.w{} {}, ({})", ord, rd, rs1)
+            }
+            InstructionCode::Atomic(code, ord) => {
+                format!("{}.w{} {}, {}, ({})", code, ord, rd, rs2, rs1)
+            }
+            InstructionCode::LoadFloat(fmt) => {
+                let frd = FloatRegisterAlias::try_from(self.rd).ok().unwrap();
+                format!("fl{} {}, {}, ({})", fmt.width(), frd, imm, rs1)
+            }
+            InstructionCode::StoreFloat(fmt) => {
+                let frs2 = FloatRegisterAlias::try_from(self.rs2).ok().unwrap();
+                format!("fs{} {}, {}, ({})", fmt.width(), frs2, imm, rs1)
+            }
+            InstructionCode::FloatOpe(code, fmt) => self.float_assembly(code, fmt),
+            InstructionCode::Fused(code, fmt) => {
+                let frd = FloatRegisterAlias::try_from(self.rd).ok().unwrap();
+                let frs1 = FloatRegisterAlias::try_from(self.rs1).ok().unwrap();
+                let frs2 = FloatRegisterAlias::try_from(self.rs2).ok().unwrap();
+                let frs3 = FloatRegisterAlias::try_from(self.rs3).ok().unwrap();
+                format!(
+                    "{}.{} {}, {}, {}, {}{}",
+                    code,
+                    fmt,
+                    frd,
+                    frs1,
+                    frs2,
+                    frs3,
+                    self.rounding_suffix()
+                )
+            }
+            InstructionCode::Csr(code) => {
+                let csr = csr_name(self.csr());
+                if code.is_immediate() {
+                    format!("{} {}, {}, {}", code, rd, csr, self.rs1)
+                } else {
+                    format!("{} {}, {}, {}", code, rd, csr, rs1)
+                }
+            }
+            InstructionCode::Fence => "fence".into(),
+            InstructionCode::FenceI => "fence.i".into(),
+        }
+    }
+
+    fn float_assembly(&self, code: &FloatCode, fmt: &FloatFormat) -> String {
+        let rs1 = RegisterAlias::try_from(self.rs1).ok().unwrap();
+        let rd = RegisterAlias::try_from(self.rd).ok().unwrap();
+        let frs1 = FloatRegisterAlias::try_from(self.rs1).ok().unwrap();
+        let frs2 = FloatRegisterAlias::try_from(self.rs2).ok().unwrap();
+        let frd = FloatRegisterAlias::try_from(self.rd).ok().unwrap();
+        let rm = if code.uses_rounding_mode() {
+            self.rounding_suffix()
+        } else {
+            "".into()
+        };
+        let other = match fmt {
+            FloatFormat::Single => FloatFormat::Double,
+            FloatFormat::Double => FloatFormat::Single,
+        };
+
+        match code {
+            FloatCode::Sqrt => format!("{}.{} {}, {}{}", code, fmt, frd, frs1, rm),
+            FloatCode::ConvertFloat => {
+                format!("{}.{}.{} {}, {}{}", code, fmt, other, frd, frs1, rm)
+            }
+            FloatCode::Equal | FloatCode::LessThan | FloatCode::LessEqual => {
+                format!("{}.{} {}, {}, {}", code, fmt, rd, frs1, frs2)
+            }
+            FloatCode::ConvertToInt => format!("{}.w.{} {}, {}{}", code, fmt, rd, frs1, rm),
+            FloatCode::ConvertToIntUnsigned => {
+                format!("{}.wu.{} {}, {}{}", code, fmt, rd, frs1, rm)
+            }
+            FloatCode::ConvertFromInt => format!("{}.{}.w {}, {}{}", code, fmt, frd, rs1, rm),
+            FloatCode::ConvertFromIntUnsigned => {
+                format!("{}.{}.wu {}, {}{}", code, fmt, frd, rs1, rm)
+            }
+            FloatCode::ConvertToLong => format!("{}.l.{} {}, {}{}", code, fmt, rd, frs1, rm),
+            FloatCode::ConvertToLongUnsigned => {
+                format!("{}.lu.{} {}, {}{}", code, fmt, rd, frs1, rm)
+            }
+            FloatCode::ConvertFromLong => format!("{}.{}.l {}, {}{}", code, fmt, frd, rs1, rm),
+            FloatCode::ConvertFromLongUnsigned => {
+                format!("{}.{}.lu {}, {}{}", code, fmt, frd, rs1, rm)
+            }
+            FloatCode::MoveToInt => format!("{}.x.{} {}, {}", code, fmt.width(), rd, frs1),
+            FloatCode::MoveFromInt => format!("{}.{}.x {}, {}", code, fmt.width(), frd, rs1),
+            FloatCode::Classify => format!("{}.{} {}, {}", code, fmt, rd, frs1),
+            _ => format!("{}.{} {}, {}, {}{}", code, fmt, frd, frs1, frs2, rm),
+        }
+    }
+
+    // the rounding mode is only spelled out when it is not the dynamic one
+    fn rounding_suffix(&self) -> String {
+        match RoundingMode::try_from(self.rm) {
+            Ok(rm) => format!(", {}", rm),
+            Err(_) => "".into(),
         }
     }
 }
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}; {:#010x}", self.assembly(), self._inst)
+        match self.compressed {
+            Some(_) => write!(f, "{}; {:#06x}", self.assembly(), self._inst),
+            None => write!(f, "{}; {:#010x}", self.assembly(), self._inst),
+        }
     }
 }
 
@@ -388,16 +1286,21 @@ pub mod tests {
     #[test]
     pub fn test_byte_wide_option() {
         let opt = ByteWideOption::Byte;
-        assert_eq!(opt.overwrite_mask(0), 0x000000FF);
-        assert_eq!(opt.overwrite_mask(1), 0x0000FF00);
-        assert_eq!(opt.overwrite_mask(2), 0x00FF0000);
-        assert_eq!(opt.overwrite_mask(3), 0xFF000000);
+        assert_eq!(opt.width(), 1);
+        assert_eq!(opt.extend(0x80), 0xFFFF_FFFF_FFFF_FF80);
+        assert_eq!(ByteWideOption::ByteUnsigned.extend(0x180), 0x80);
         let opt = ByteWideOption::HalfWord;
-        assert_eq!(opt.overwrite_mask(0), 0x0000FFFF);
-        assert_eq!(opt.overwrite_mask(1), 0x00FFFF00);
-        assert_eq!(opt.overwrite_mask(2), 0xFFFF0000);
-        assert_eq!(!opt.overwrite_mask(2), 0x0000FFFF);
+        assert_eq!(opt.width(), 2);
+        assert_eq!(opt.extend(0x7FFF), 0x7FFF);
+        assert_eq!(opt.extend(0x8000), 0xFFFF_FFFF_FFFF_8000);
+        assert_eq!(ByteWideOption::HalfWordUnsigned.extend(0x8000), 0x8000);
         let opt = ByteWideOption::Word;
-        assert_eq!(opt.overwrite_mask(0), 0xFFFFFFFF);
+        assert_eq!(opt.width(), 4);
+        assert_eq!(opt.extend(0x8000_0000), 0xFFFF_FFFF_8000_0000);
+        assert_eq!(
+            ByteWideOption::WordUnsigned.extend(0x8000_0000),
+            0x8000_0000
+        );
+        assert_eq!(ByteWideOption::DoubleWord.width(), 8);
     }
 }
@@ -0,0 +1,193 @@
+use std::{error::Error, fmt::Display};
+
+use crate::memory::MainMemoryErrorType;
+
+use super::{
+    csr::ControlStatusRegisterErrorType, decoder::error::InstructionDecodingErrorType,
+    register::RegisterErrorType,
+};
+
+/// The guest instruction an error happened on, attached as the error leaves
+/// the processor.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ErrorContext {
+    pub pc: Option<u64>,
+    // none when the instruction itself could not be fetched
+    pub raw: Option<u32>,
+}
+
+/// Everything that can stop the processor.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum ProcessorError {
+    Decode {
+        kind: InstructionDecodingErrorType,
+        context: ErrorContext,
+    },
+    Register {
+        kind: RegisterErrorType,
+        context: ErrorContext,
+    },
+    Csr {
+        kind: ControlStatusRegisterErrorType,
+        context: ErrorContext,
+    },
+    Memory {
+        kind: MainMemoryErrorType,
+        // first byte and width of the access
+        address: u64,
+        width: u64,
+        context: ErrorContext,
+    },
+}
+
+impl ProcessorError {
+    pub fn memory(kind: MainMemoryErrorType, address: u64, width: u64) -> Self {
+        Self::Memory {
+            kind,
+            address,
+            width,
+            context: ErrorContext::default(),
+        }
+    }
+
+    pub fn context(&self) -> &ErrorContext {
+        match self {
+            Self::Decode { context, .. }
+            | Self::Register { context, .. }
+            | Self::Csr { context, .. }
+            | Self::Memory { context, .. } => context,
+        }
+    }
+
+    /// Attaches the faulting instruction, unless an inner step already has.
+    pub fn at(mut self, pc: u64, raw: Option<u32>) -> Self {
+        let context = match &mut self {
+            Self::Decode { context, .. }
+            | Self::Register { context, .. }
+            | Self::Csr { context, .. }
+            | Self::Memory { context, .. } => context,
+        };
+        if context.pc.is_none() {
+            *context = ErrorContext { pc: Some(pc), raw };
+        }
+        self
+    }
+
+    pub fn pc(&self) -> Option<u64> {
+        self.context().pc
+    }
+
+    pub fn raw(&self) -> Option<u32> {
+        self.context().raw
+    }
+
+    /// Address of the faulting memory access.
+    pub fn address(&self) -> Option<u64> {
+        match self {
+            Self::Memory { address, .. } => Some(*address),
+            _ => None,
+        }
+    }
+
+    /// Width in bytes of the faulting memory access.
+    pub fn width(&self) -> Option<u64> {
+        match self {
+            Self::Memory { width, .. } => Some(*width),
+            _ => None,
+        }
+    }
+}
+
+impl From<InstructionDecodingErrorType> for ProcessorError {
+    fn from(kind: InstructionDecodingErrorType) -> Self {
+        Self::Decode {
+            kind,
+            context: ErrorContext::default(),
+        }
+    }
+}
+
+impl From<RegisterErrorType> for ProcessorError {
+    fn from(kind: RegisterErrorType) -> Self {
+        Self::Register {
+            kind,
+            context: ErrorContext::default(),
+        }
+    }
+}
+
+impl From<ControlStatusRegisterErrorType> for ProcessorError {
+    fn from(kind: ControlStatusRegisterErrorType) -> Self {
+        Self::Csr {
+            kind,
+            context: ErrorContext::default(),
+        }
+    }
+}
+
+impl Display for ProcessorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decode { kind, .. } => write!(f, "decode instruction is failed - {}", kind)?,
+            Self::Register { kind, .. } => write!(f, "register access is failed - {}", kind)?,
+            Self::Csr { kind, .. } => write!(f, "csr access is failed - {}", kind)?,
+            Self::Memory {
+                kind,
+                address,
+                width,
+                ..
+            } => write!(
+                f,
+                "memory access is failed - {} at address: {:#010x}, width: {}",
+                kind, address, width
+            )?,
+        }
+
+        let context = self.context();
+        if let Some(pc) = context.pc {
+            write!(f, " (pc: {:#010x}", pc)?;
+            if let Some(raw) = context.raw {
+                write!(f, ", instruction: {:#010x}", raw)?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ProcessorError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{Access, MainMemoryErrorType};
+
+    use super::ProcessorError;
+
+    #[test]
+    fn test_error_context() {
+        let error = ProcessorError::memory(
+            MainMemoryErrorType::MisalignedAccess(Access::Read),
+            0x1001,
+            4,
+        );
+        assert_eq!(
+            error.to_string(),
+            "memory access is failed - misaligned read at address: 0x00001001, width: 4"
+        );
+
+        // the innermost context wins
+        let error = error.at(0x2000, Some(0x00102503)).at(0x3000, None);
+        assert_eq!(error.pc(), Some(0x2000));
+        assert_eq!(error.raw(), Some(0x00102503));
+        assert_eq!(error.address(), Some(0x1001));
+        assert_eq!(error.width(), Some(4));
+        assert!(error
+            .to_string()
+            .ends_with("width: 4 (pc: 0x00002000, instruction: 0x00102503)"));
+
+        // usable with `?` into anyhow
+        let error: anyhow::Error = error.into();
+        assert!(error.downcast_ref::<ProcessorError>().is_some());
+    }
+}
@@ -1,28 +1,39 @@
-use self::unit::{alu, branch_operation};
+use self::unit::{alu_word, alu_xlen, branch_operation};
 
-use super::decoder::instruction::{Instruction, InstructionCode};
+use super::{
+    decoder::instruction::{Instruction, InstructionCode},
+    xlen::Xlen,
+};
 
+pub mod float;
 pub mod unit;
 
-pub fn execute(inst: &Instruction, rs1: u32, rs2: u32, pc: u32) -> (u32, u32) {
-    match &inst.code {
-        InstructionCode::Ope(code) => (alu(code, rs1, rs2), pc + 4),
-        InstructionCode::OpeI(code) => (alu(code, rs1, inst.imm), pc + 4),
-        InstructionCode::Lui => (inst.imm, pc + 4),
-        InstructionCode::Auipc => (pc.wrapping_add(inst.imm), pc + 4),
+pub fn execute(inst: &Instruction, rs1: u64, rs2: u64, pc: u64, xlen: &Xlen) -> (u64, u64) {
+    let next = pc.wrapping_add(inst.size());
+
+    let (rd, next_pc) = match &inst.code {
+        InstructionCode::Ope(code) => (alu_xlen(code, rs1, rs2, xlen), next),
+        InstructionCode::OpeI(code) => (alu_xlen(code, rs1, inst.imm, xlen), next),
+        InstructionCode::OpeW(code) => (alu_word(code, rs1, rs2), next),
+        InstructionCode::OpeIW(code) => (alu_word(code, rs1, inst.imm), next),
+        InstructionCode::Lui => (inst.imm, next),
+        InstructionCode::Auipc => (pc.wrapping_add(inst.imm), next),
         InstructionCode::Branch(option) => {
-            let next_pc = if branch_operation(option, rs1, rs2) {
+            let next_pc = if branch_operation(option, rs1, rs2, xlen) {
                 pc.wrapping_add(inst.imm)
             } else {
-                pc + 4
+                next
             };
 
             (0, next_pc)
         }
-        InstructionCode::Jal => (pc + 4, pc.wrapping_add(inst.imm)),
-        InstructionCode::Jalr => (pc + 4, rs1.wrapping_add(inst.imm)),
+        InstructionCode::Jal => (next, pc.wrapping_add(inst.imm)),
+        InstructionCode::Jalr => (next, rs1.wrapping_add(inst.imm) & !1),
         // InstructionCode::Load(option) => {}
         // InstructionCode::Store(option) => {}
-        _ => (0, pc + 4),
-    }
+        _ => (0, next),
+    };
+
+    // results and addresses wrap at the register width
+    (xlen.truncate(rd), xlen.truncate(next_pc))
 }
@@ -0,0 +1,875 @@
+use std::{
+    cmp::Ordering,
+    num::FpCategory,
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+use crate::processor::{
+    csr::{RoundingMode, DIVIDE_BY_ZERO, INEXACT, INVALID_OPERATION, OVERFLOW, UNDERFLOW},
+    decoder::instruction::{FloatCode, FloatFormat, FusedCode},
+    register::{nan_box, nan_unbox},
+};
+
+/// Host floating-point type standing for one RISC-V format.
+/// Values move in and out of the 64-bit register file through `unpack`/`pack`.
+pub trait FloatValue:
+    Copy
+    + PartialEq
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    const WIDTH: u32;
+    const ZERO: Self;
+    const MAX: Self;
+    const MIN_POSITIVE: Self;
+    const SIGN_BIT: u64;
+    const QUIET_BIT: u64;
+    const CANONICAL_NAN: u64;
+
+    fn unpack(raw: u64) -> Self;
+    fn pack(self) -> u64;
+    fn from_f64(value: f64) -> Self;
+    fn from_i128(value: i128) -> Self;
+    fn to_f64(self) -> f64;
+    fn bits(self) -> u64;
+    fn from_bits(bits: u64) -> Self;
+    fn is_nan(self) -> bool;
+    fn is_infinite(self) -> bool;
+    fn is_sign_negative(self) -> bool;
+    fn category(self) -> FpCategory;
+    fn abs(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn mul_add(self, a: Self, b: Self) -> Self;
+    fn next_up(self) -> Self;
+    fn next_down(self) -> Self;
+
+    fn is_signaling(self) -> bool {
+        self.is_nan() && self.bits() & Self::QUIET_BIT == 0
+    }
+
+    fn canonical_nan() -> Self {
+        Self::from_bits(Self::CANONICAL_NAN)
+    }
+}
+
+macro_rules! impl_float_value {
+    ($float:ty, $bits:ty, $quiet:expr, $nan:expr, $unpack:expr, $pack:expr) => {
+        impl FloatValue for $float {
+            const WIDTH: u32 = <$bits>::BITS;
+            const ZERO: Self = 0.0;
+            const MAX: Self = <$float>::MAX;
+            const MIN_POSITIVE: Self = <$float>::MIN_POSITIVE;
+            const SIGN_BIT: u64 = 1 << (<$bits>::BITS - 1);
+            const QUIET_BIT: u64 = $quiet;
+            const CANONICAL_NAN: u64 = $nan;
+
+            fn unpack(raw: u64) -> Self {
+                <$float>::from_bits($unpack(raw))
+            }
+            fn pack(self) -> u64 {
+                $pack(self.to_bits())
+            }
+            fn from_f64(value: f64) -> Self {
+                value as $float
+            }
+            fn from_i128(value: i128) -> Self {
+                value as $float
+            }
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+            fn bits(self) -> u64 {
+                self.to_bits() as u64
+            }
+            fn from_bits(bits: u64) -> Self {
+                <$float>::from_bits(bits as $bits)
+            }
+            fn is_nan(self) -> bool {
+                <$float>::is_nan(self)
+            }
+            fn is_infinite(self) -> bool {
+                <$float>::is_infinite(self)
+            }
+            fn is_sign_negative(self) -> bool {
+                <$float>::is_sign_negative(self)
+            }
+            fn category(self) -> FpCategory {
+                <$float>::classify(self)
+            }
+            fn abs(self) -> Self {
+                <$float>::abs(self)
+            }
+            fn sqrt(self) -> Self {
+                <$float>::sqrt(self)
+            }
+            fn mul_add(self, a: Self, b: Self) -> Self {
+                <$float>::mul_add(self, a, b)
+            }
+            fn next_up(self) -> Self {
+                <$float>::next_up(self)
+            }
+            fn next_down(self) -> Self {
+                <$float>::next_down(self)
+            }
+        }
+    };
+}
+
+impl_float_value!(f32, u32, 0x0040_0000, 0x7FC0_0000, nan_unbox, nan_box);
+impl_float_value!(
+    f64,
+    u64,
+    0x0008_0000_0000_0000,
+    0x7FF8_0000_0000_0000,
+    |raw| raw,
+    |bits| bits
+);
+
+/// Executes OP-FP. `rs1` is the integer source; `frs1` and `frs2` are raw register contents.
+/// Returns the raw result, for an integer or a floating-point rd, and the raised fflags.
+/// Integer results are sign-extended to 64 bits.
+pub fn float_operation(
+    code: &FloatCode,
+    format: &FloatFormat,
+    rs1: u64,
+    frs1: u64,
+    frs2: u64,
+    rm: RoundingMode,
+) -> (u64, u32) {
+    match (code, format) {
+        (FloatCode::ConvertFloat, FloatFormat::Single) => convert_float::<f64, f32>(frs1, rm),
+        (FloatCode::ConvertFloat, FloatFormat::Double) => convert_float::<f32, f64>(frs1, rm),
+        (_, FloatFormat::Single) => operation::<f32>(code, rs1, frs1, frs2, rm),
+        (_, FloatFormat::Double) => operation::<f64>(code, rs1, frs1, frs2, rm),
+    }
+}
+
+pub fn fused_operation(
+    code: &FusedCode,
+    format: &FloatFormat,
+    frs: [u64; 3],
+    rm: RoundingMode,
+) -> (u64, u32) {
+    let mut flags = 0;
+    let value = match format {
+        FloatFormat::Single => fused::<f32>(code, frs, rm, &mut flags),
+        FloatFormat::Double => fused::<f64>(code, frs, rm, &mut flags),
+    };
+    (value, flags)
+}
+
+fn operation<F: FloatValue>(
+    code: &FloatCode,
+    rs1: u64,
+    frs1: u64,
+    frs2: u64,
+    rm: RoundingMode,
+) -> (u64, u32) {
+    let a = F::unpack(frs1);
+    let b = F::unpack(frs2);
+    let mut flags = 0;
+
+    let value = match code {
+        FloatCode::Add => add(a, b, rm, &mut flags).pack(),
+        FloatCode::Sub => add(a, -b, rm, &mut flags).pack(),
+        FloatCode::Mul => mul(a, b, rm, &mut flags).pack(),
+        FloatCode::Div => div(a, b, rm, &mut flags).pack(),
+        FloatCode::Sqrt => sqrt(a, rm, &mut flags).pack(),
+        FloatCode::SignInject => {
+            F::from_bits((a.bits() & !F::SIGN_BIT) | (b.bits() & F::SIGN_BIT)).pack()
+        }
+        FloatCode::SignInjectNegate => {
+            F::from_bits((a.bits() & !F::SIGN_BIT) | (!b.bits() & F::SIGN_BIT)).pack()
+        }
+        FloatCode::SignInjectXor => F::from_bits(a.bits() ^ (b.bits() & F::SIGN_BIT)).pack(),
+        FloatCode::Min => min_max(a, b, true, &mut flags).pack(),
+        FloatCode::Max => min_max(a, b, false, &mut flags).pack(),
+        FloatCode::ConvertFloat => unreachable!(),
+        FloatCode::Equal => {
+            if a.is_signaling() || b.is_signaling() {
+                flags |= INVALID_OPERATION;
+            }
+            (a == b) as u64
+        }
+        FloatCode::LessThan | FloatCode::LessEqual => {
+            if a.is_nan() || b.is_nan() {
+                flags |= INVALID_OPERATION;
+            }
+            match code {
+                FloatCode::LessThan => (a < b) as u64,
+                _ => (a <= b) as u64,
+            }
+        }
+        FloatCode::ConvertToInt => to_int(a.to_f64(), 32, true, rm, &mut flags),
+        FloatCode::ConvertToIntUnsigned => to_int(a.to_f64(), 32, false, rm, &mut flags),
+        FloatCode::ConvertToLong => to_int(a.to_f64(), 64, true, rm, &mut flags),
+        FloatCode::ConvertToLongUnsigned => to_int(a.to_f64(), 64, false, rm, &mut flags),
+        FloatCode::ConvertFromInt => {
+            from_int::<F>(rs1 as u32 as i32 as i128, rm, &mut flags).pack()
+        }
+        FloatCode::ConvertFromIntUnsigned => {
+            from_int::<F>(rs1 as u32 as i128, rm, &mut flags).pack()
+        }
+        FloatCode::ConvertFromLong => from_int::<F>(rs1 as i64 as i128, rm, &mut flags).pack(),
+        FloatCode::ConvertFromLongUnsigned => from_int::<F>(rs1 as i128, rm, &mut flags).pack(),
+        // moves keep the bit pattern, so the register is not unboxed
+        FloatCode::MoveToInt => {
+            let shift = 64 - F::WIDTH;
+            (((frs1 << shift) as i64) >> shift) as u64
+        }
+        FloatCode::MoveFromInt => F::from_bits(rs1).pack(),
+        FloatCode::Classify => classify(a) as u64,
+    };
+
+    (value, flags)
+}
+
+/// Moves the round-to-nearest-even host result `value` to the one `rm` selects,
+/// knowing how the exact result compares with it and whether it was a tie.
+fn round<F: FloatValue>(value: F, exact: Ordering, tie: bool, rm: RoundingMode) -> F {
+    match (rm, exact) {
+        (_, Ordering::Equal) | (RoundingMode::NearestEven, _) => value,
+        (RoundingMode::NearestMaxMagnitude, Ordering::Greater) => {
+            if tie && !value.is_sign_negative() {
+                value.next_up()
+            } else {
+                value
+            }
+        }
+        (RoundingMode::NearestMaxMagnitude, Ordering::Less) => {
+            if tie && value.is_sign_negative() {
+                value.next_down()
+            } else {
+                value
+            }
+        }
+        (RoundingMode::TowardZero, Ordering::Greater) => {
+            if value.is_sign_negative() {
+                value.next_up()
+            } else {
+                value
+            }
+        }
+        (RoundingMode::TowardZero, Ordering::Less) => {
+            if value.is_sign_negative() {
+                value
+            } else {
+                value.next_down()
+            }
+        }
+        (RoundingMode::Down, Ordering::Less) => value.next_down(),
+        (RoundingMode::Up, Ordering::Greater) => value.next_up(),
+        (RoundingMode::Down, _) | (RoundingMode::Up, _) => value,
+    }
+}
+
+fn overflow<F: FloatValue>(negative: bool, rm: RoundingMode, flags: &mut u32) -> F {
+    *flags |= OVERFLOW | INEXACT;
+
+    let infinity = F::MAX + F::MAX;
+    let value = match (rm, negative) {
+        (RoundingMode::NearestEven, _) | (RoundingMode::NearestMaxMagnitude, _) => infinity,
+        (RoundingMode::TowardZero, _) => F::MAX,
+        (RoundingMode::Down, false) | (RoundingMode::Up, true) => F::MAX,
+        (RoundingMode::Down, true) | (RoundingMode::Up, false) => infinity,
+    };
+
+    if negative {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Rounds `value`, whose error against the exact result is `error`, and raises flags.
+/// Handles the NaN, overflow and underflow cases common to arithmetic operations.
+fn finish<F: FloatValue>(
+    value: F,
+    error: F,
+    operands: &[F],
+    rm: RoundingMode,
+    flags: &mut u32,
+) -> F {
+    if operands.iter().any(|x| x.is_signaling()) {
+        *flags |= INVALID_OPERATION;
+    }
+    if value.is_nan() {
+        if !operands.iter().any(|x| x.is_nan()) {
+            *flags |= INVALID_OPERATION;
+        }
+        return F::canonical_nan();
+    }
+    if value.is_infinite() {
+        if operands.iter().all(|x| !x.is_infinite()) {
+            return overflow(value.is_sign_negative(), rm, flags);
+        }
+        return value;
+    }
+
+    let exact = error.partial_cmp(&F::ZERO).unwrap_or(Ordering::Equal);
+    if exact == Ordering::Equal {
+        return value;
+    }
+
+    let neighbor = match exact {
+        Ordering::Greater => value.next_up(),
+        _ => value.next_down(),
+    };
+    let tie = (neighbor - value).abs() == error.abs() + error.abs();
+    let rounded = round(value, exact, tie, rm);
+
+    *flags |= INEXACT;
+    if rounded.abs() < F::MIN_POSITIVE {
+        *flags |= UNDERFLOW;
+    }
+    if rounded.is_infinite() {
+        *flags |= OVERFLOW;
+    }
+    rounded
+}
+
+fn add<F: FloatValue>(a: F, b: F, rm: RoundingMode, flags: &mut u32) -> F {
+    let sum = a + b;
+
+    // TwoSum: error is exactly (a + b) - sum
+    let b_virtual = sum - a;
+    let error = (a - (sum - b_virtual)) + (b - b_virtual);
+
+    // an exact zero sum of opposite signs is -0 only when rounding down
+    if sum == F::ZERO && a.is_sign_negative() != b.is_sign_negative() {
+        return match rm {
+            RoundingMode::Down => -F::ZERO,
+            _ => F::ZERO,
+        };
+    }
+
+    finish(sum, error, &[a, b], rm, flags)
+}
+
+fn mul<F: FloatValue>(a: F, b: F, rm: RoundingMode, flags: &mut u32) -> F {
+    let product = a * b;
+    let error = a.mul_add(b, -product);
+
+    finish(product, error, &[a, b], rm, flags)
+}
+
+fn div<F: FloatValue>(a: F, b: F, rm: RoundingMode, flags: &mut u32) -> F {
+    let quotient = a / b;
+
+    if b == F::ZERO && !a.is_nan() && a != F::ZERO && !a.is_infinite() {
+        *flags |= DIVIDE_BY_ZERO;
+        return quotient;
+    }
+
+    // the remainder a - quotient * b is exact and carries the error direction
+    let remainder = (-quotient).mul_add(b, a);
+    let error = if b.is_sign_negative() {
+        -remainder
+    } else {
+        remainder
+    };
+
+    finish(quotient, error, &[a, b], rm, flags)
+}
+
+fn sqrt<F: FloatValue>(a: F, rm: RoundingMode, flags: &mut u32) -> F {
+    let root = a.sqrt();
+    let error = (-root).mul_add(root, a);
+
+    finish(root, error, &[a], rm, flags)
+}
+
+fn fused<F: FloatValue>(code: &FusedCode, frs: [u64; 3], rm: RoundingMode, flags: &mut u32) -> u64 {
+    let a = F::unpack(frs[0]);
+    let b = F::unpack(frs[1]);
+    let c = F::unpack(frs[2]);
+
+    let (a, c) = match code {
+        FusedCode::MulAdd => (a, c),
+        FusedCode::MulSub => (a, -c),
+        FusedCode::NegateMulSub => (-a, c),
+        FusedCode::NegateMulAdd => (-a, -c),
+    };
+
+    // 0 * inf is invalid even when the addend is a quiet NaN
+    let zero_times_infinity =
+        (a == F::ZERO && b.is_infinite()) || (a.is_infinite() && b == F::ZERO);
+    if zero_times_infinity {
+        *flags |= INVALID_OPERATION;
+    }
+
+    let value = a.mul_add(b, c);
+
+    // the exact result is sum + sum_error + product_error, from the error-free
+    // product and a TwoSum of it with the addend
+    let product = a * b;
+    let product_error = a.mul_add(b, -product);
+    let sum = product + c;
+    let c_virtual = sum - product;
+    let sum_error = (product - (sum - c_virtual)) + (c - c_virtual);
+    let error = (sum - value) + (sum_error + product_error);
+
+    finish(value, error, &[a, b, c], rm, flags).pack()
+}
+
+fn min_max<F: FloatValue>(a: F, b: F, is_min: bool, flags: &mut u32) -> F {
+    if a.is_signaling() || b.is_signaling() {
+        *flags |= INVALID_OPERATION;
+    }
+
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => F::canonical_nan(),
+        (true, false) => b,
+        (false, true) => a,
+        // -0 is less than +0 here
+        _ if a == b => {
+            if a.is_sign_negative() == is_min {
+                a
+            } else {
+                b
+            }
+        }
+        _ if (a < b) == is_min => a,
+        _ => b,
+    }
+}
+
+/// Rounds a value exactly held in f64 into the format `F`.
+fn from_exact<F: FloatValue>(exact: f64, rm: RoundingMode, flags: &mut u32) -> F {
+    let value = F::from_f64(exact);
+
+    if value.is_infinite() && !exact.is_infinite() {
+        return overflow(exact.is_sign_negative(), rm, flags);
+    }
+
+    let ordering = exact
+        .partial_cmp(&value.to_f64())
+        .unwrap_or(Ordering::Equal);
+    if ordering == Ordering::Equal {
+        return value;
+    }
+
+    let neighbor = match ordering {
+        Ordering::Greater => value.next_up(),
+        _ => value.next_down(),
+    };
+    let error = exact - value.to_f64();
+    let tie = (neighbor.to_f64() - value.to_f64()).abs() == error.abs() * 2.0;
+    let rounded = round(value, ordering, tie, rm);
+
+    *flags |= INEXACT;
+    if rounded.abs() < F::MIN_POSITIVE {
+        *flags |= UNDERFLOW;
+    }
+    rounded
+}
+
+/// Rounds an integer into the format `F`. No integer source can overflow it.
+fn from_int<F: FloatValue>(value: i128, rm: RoundingMode, flags: &mut u32) -> F {
+    let rounded = F::from_i128(value);
+
+    // a rounded result is an integer of at least 2^24 and exact as i128
+    let nearest = rounded.to_f64() as i128;
+    let ordering = value.cmp(&nearest);
+    if ordering == Ordering::Equal {
+        return rounded;
+    }
+
+    let neighbor = match ordering {
+        Ordering::Greater => rounded.next_up(),
+        _ => rounded.next_down(),
+    };
+    let error = value - nearest;
+    let tie = (neighbor.to_f64() as i128 - nearest).abs() == error.abs() * 2;
+
+    *flags |= INEXACT;
+    round(rounded, ordering, tie, rm)
+}
+
+/// fcvt.s.d / fcvt.d.s from the format `S` into the format `D`.
+fn convert_float<S: FloatValue, D: FloatValue>(raw: u64, rm: RoundingMode) -> (u64, u32) {
+    let source = S::unpack(raw);
+    let mut flags = 0;
+
+    if source.is_signaling() {
+        flags |= INVALID_OPERATION;
+    }
+    if source.is_nan() {
+        return (D::canonical_nan().pack(), flags);
+    }
+
+    let value = from_exact::<D>(source.to_f64(), rm, &mut flags);
+    (value.pack(), flags)
+}
+
+/// Converts into a `width`-bit integer, returned sign-extended to 64 bits
+/// as RV64 writes it back, even for fcvt.wu.
+fn to_int(value: f64, width: u32, signed: bool, rm: RoundingMode, flags: &mut u32) -> u64 {
+    let rounded = match rm {
+        RoundingMode::NearestEven => value.round_ties_even(),
+        RoundingMode::TowardZero => value.trunc(),
+        RoundingMode::Down => value.floor(),
+        RoundingMode::Up => value.ceil(),
+        RoundingMode::NearestMaxMagnitude => value.round(),
+    };
+
+    // the range is [min, limit), both bounds are powers of two and exact in f64
+    let (min, limit) = if signed {
+        (-(2f64.powi(width as i32 - 1)), 2f64.powi(width as i32 - 1))
+    } else {
+        (0.0, 2f64.powi(width as i32))
+    };
+
+    let result = if value.is_nan() || rounded >= limit {
+        *flags |= INVALID_OPERATION;
+        limit as i128 - 1
+    } else if rounded < min {
+        *flags |= INVALID_OPERATION;
+        min as i128
+    } else {
+        if rounded != value {
+            *flags |= INEXACT;
+        }
+        rounded as i128
+    };
+
+    let shift = 128 - width;
+    ((result << shift) >> shift) as u64
+}
+
+fn classify<F: FloatValue>(value: F) -> u32 {
+    let negative = value.is_sign_negative();
+    let bit = match value.category() {
+        FpCategory::Infinite if negative => 0,
+        FpCategory::Normal if negative => 1,
+        FpCategory::Subnormal if negative => 2,
+        FpCategory::Zero if negative => 3,
+        FpCategory::Zero => 4,
+        FpCategory::Subnormal => 5,
+        FpCategory::Normal => 6,
+        FpCategory::Infinite => 7,
+        FpCategory::Nan if value.is_signaling() => 8,
+        FpCategory::Nan => 9,
+    };
+    1 << bit
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::{
+        csr::{RoundingMode, DIVIDE_BY_ZERO, INEXACT, INVALID_OPERATION, OVERFLOW},
+        decoder::instruction::{FloatCode, FloatFormat, FusedCode},
+        register::nan_box,
+    };
+
+    use super::{float_operation, fused_operation};
+
+    fn single(value: f32) -> u64 {
+        nan_box(value.to_bits())
+    }
+
+    fn op_s(code: FloatCode, a: f32, b: f32, rm: RoundingMode) -> (u64, u32) {
+        float_operation(&code, &FloatFormat::Single, 0, single(a), single(b), rm)
+    }
+
+    #[test]
+    fn test_arithmetic_rounding() {
+        let rne = RoundingMode::NearestEven;
+        assert_eq!(op_s(FloatCode::Add, 1.5, 2.25, rne), (single(3.75), 0));
+
+        // 1 + 2^-24 lies exactly between 1 and the next single
+        let half_ulp = f32::from_bits(0x3380_0000);
+        assert_eq!(
+            op_s(FloatCode::Add, 1.0, half_ulp, rne),
+            (single(1.0), INEXACT)
+        );
+        let (rmm, _) = op_s(
+            FloatCode::Add,
+            1.0,
+            half_ulp,
+            RoundingMode::NearestMaxMagnitude,
+        );
+        assert_eq!(rmm, single(1.0f32.next_up()));
+        let (rup, _) = op_s(FloatCode::Add, 1.0, half_ulp, RoundingMode::Up);
+        assert_eq!(rup, single(1.0f32.next_up()));
+        let (rtz, _) = op_s(FloatCode::Sub, -1.0, half_ulp, RoundingMode::TowardZero);
+        assert_eq!(rtz, single(-1.0));
+        let (rdn, _) = op_s(FloatCode::Sub, -1.0, half_ulp, RoundingMode::Down);
+        assert_eq!(rdn, single((-1.0f32).next_down()));
+
+        // the nearest single to 1 / 3 is above it, rounding down moves one ulp
+        let (near, flags) = op_s(FloatCode::Div, 1.0, 3.0, rne);
+        assert_eq!(flags, INEXACT);
+        let (up, _) = op_s(FloatCode::Div, 1.0, 3.0, RoundingMode::Up);
+        let (down, _) = op_s(FloatCode::Div, 1.0, 3.0, RoundingMode::Down);
+        assert_eq!(near, single(1.0f32 / 3.0));
+        assert_eq!(up, single(1.0f32 / 3.0));
+        assert_eq!(down, single((1.0f32 / 3.0).next_down()));
+
+        // x - x is -0 only when rounding down
+        assert_eq!(op_s(FloatCode::Sub, 2.0, 2.0, rne).0, single(0.0));
+        assert_eq!(
+            op_s(FloatCode::Sub, 2.0, 2.0, RoundingMode::Down).0,
+            single(-0.0)
+        );
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let rne = RoundingMode::NearestEven;
+        let (value, flags) = op_s(FloatCode::Div, 1.0, 0.0, rne);
+        assert_eq!((value, flags), (single(f32::INFINITY), DIVIDE_BY_ZERO));
+
+        let (value, flags) = op_s(FloatCode::Sub, f32::INFINITY, f32::INFINITY, rne);
+        assert_eq!((value, flags), (nan_box(0x7FC00000), INVALID_OPERATION));
+
+        let (value, flags) = op_s(FloatCode::Mul, f32::MAX, 2.0, RoundingMode::TowardZero);
+        assert_eq!((value, flags), (single(f32::MAX), OVERFLOW | INEXACT));
+
+        let (value, flags) = op_s(FloatCode::Sqrt, -1.0, 0.0, rne);
+        assert_eq!((value, flags), (nan_box(0x7FC00000), INVALID_OPERATION));
+
+        // a signaling NaN raises NV even in a quiet comparison
+        let snan = f32::from_bits(0x7F800001);
+        assert_eq!(
+            op_s(FloatCode::Equal, snan, 1.0, rne),
+            (0, INVALID_OPERATION)
+        );
+        assert_eq!(op_s(FloatCode::Equal, f32::NAN, 1.0, rne), (0, 0));
+        assert_eq!(
+            op_s(FloatCode::LessThan, f32::NAN, 1.0, rne),
+            (0, INVALID_OPERATION)
+        );
+    }
+
+    #[test]
+    fn test_min_max_sign_injection_classify() {
+        let rne = RoundingMode::NearestEven;
+        assert_eq!(op_s(FloatCode::Min, -0.0, 0.0, rne).0, single(-0.0));
+        assert_eq!(op_s(FloatCode::Max, -0.0, 0.0, rne).0, single(0.0));
+        assert_eq!(op_s(FloatCode::Min, f32::NAN, 2.0, rne).0, single(2.0));
+
+        assert_eq!(op_s(FloatCode::SignInject, 1.0, -2.0, rne).0, single(-1.0));
+        assert_eq!(
+            op_s(FloatCode::SignInjectNegate, 1.0, -2.0, rne).0,
+            single(1.0)
+        );
+        assert_eq!(
+            op_s(FloatCode::SignInjectXor, -1.0, -2.0, rne).0,
+            single(1.0)
+        );
+
+        assert_eq!(
+            op_s(FloatCode::Classify, f32::NEG_INFINITY, 0.0, rne).0,
+            1 << 0
+        );
+        assert_eq!(op_s(FloatCode::Classify, -0.0, 0.0, rne).0, 1 << 3);
+        assert_eq!(op_s(FloatCode::Classify, 1.0, 0.0, rne).0, 1 << 6);
+        assert_eq!(op_s(FloatCode::Classify, f32::NAN, 0.0, rne).0, 1 << 9);
+
+        // an improperly boxed single reads as the canonical NaN
+        let (value, _) = float_operation(
+            &FloatCode::Classify,
+            &FloatFormat::Single,
+            0,
+            1.0f64.to_bits(),
+            0,
+            rne,
+        );
+        assert_eq!(value, 1 << 9);
+    }
+
+    #[test]
+    fn test_conversion() {
+        let convert =
+            |code, format, rs1, frs1, rm| float_operation(&code, &format, rs1, frs1, 0, rm);
+        let rne = RoundingMode::NearestEven;
+        let rtz = RoundingMode::TowardZero;
+
+        let (value, flags) = convert(
+            FloatCode::ConvertToInt,
+            FloatFormat::Single,
+            0,
+            single(-2.5),
+            rne,
+        );
+        assert_eq!((value, flags), (-2i64 as u64, INEXACT));
+        let (value, _) = convert(
+            FloatCode::ConvertToInt,
+            FloatFormat::Single,
+            0,
+            single(-2.5),
+            rtz,
+        );
+        assert_eq!(value, -2i64 as u64);
+        let (value, _) = convert(
+            FloatCode::ConvertToInt,
+            FloatFormat::Double,
+            0,
+            2.5f64.to_bits(),
+            RoundingMode::NearestMaxMagnitude,
+        );
+        assert_eq!(value, 3);
+        let (value, flags) = convert(
+            FloatCode::ConvertToIntUnsigned,
+            FloatFormat::Single,
+            0,
+            single(-1.0),
+            rne,
+        );
+        assert_eq!((value, flags), (0, INVALID_OPERATION));
+        let (value, flags) = convert(
+            FloatCode::ConvertToInt,
+            FloatFormat::Single,
+            0,
+            single(f32::NAN),
+            rne,
+        );
+        assert_eq!((value, flags), (i32::MAX as u64, INVALID_OPERATION));
+
+        let (value, flags) = convert(
+            FloatCode::ConvertFromInt,
+            FloatFormat::Single,
+            0x0100_0001,
+            0,
+            rne,
+        );
+        assert_eq!((value, flags), (single(16777216.0), INEXACT));
+        let (value, _) = convert(
+            FloatCode::ConvertFromInt,
+            FloatFormat::Single,
+            0x0100_0001,
+            0,
+            RoundingMode::Up,
+        );
+        assert_eq!(value, single(16777218.0));
+        let (value, flags) = convert(
+            FloatCode::ConvertFromInt,
+            FloatFormat::Double,
+            -7i32 as u32 as u64,
+            0,
+            rne,
+        );
+        assert_eq!((value, flags), ((-7.0f64).to_bits(), 0));
+
+        // the word conversions sign-extend their result, the unsigned one too
+        let (value, _) = convert(
+            FloatCode::ConvertToIntUnsigned,
+            FloatFormat::Double,
+            0,
+            3e9f64.to_bits(),
+            rne,
+        );
+        assert_eq!(value, 3_000_000_000u32 as i32 as i64 as u64);
+        let (value, flags) = convert(
+            FloatCode::ConvertToLong,
+            FloatFormat::Double,
+            0,
+            (-3e18f64).to_bits(),
+            rne,
+        );
+        assert_eq!((value, flags), (-3_000_000_000_000_000_000i64 as u64, 0));
+        let (value, flags) = convert(
+            FloatCode::ConvertToLong,
+            FloatFormat::Double,
+            0,
+            9.3e18f64.to_bits(),
+            rne,
+        );
+        assert_eq!((value, flags), (i64::MAX as u64, INVALID_OPERATION));
+        let (value, flags) = convert(
+            FloatCode::ConvertToLongUnsigned,
+            FloatFormat::Single,
+            0,
+            single(f32::INFINITY),
+            rne,
+        );
+        assert_eq!((value, flags), (u64::MAX, INVALID_OPERATION));
+
+        // 2^53 + 1 is a tie between two doubles
+        let (value, flags) = convert(
+            FloatCode::ConvertFromLong,
+            FloatFormat::Double,
+            (1 << 53) + 1,
+            0,
+            rne,
+        );
+        assert_eq!((value, flags), (9007199254740992f64.to_bits(), INEXACT));
+        let (value, _) = convert(
+            FloatCode::ConvertFromLong,
+            FloatFormat::Double,
+            (1 << 53) + 1,
+            0,
+            RoundingMode::NearestMaxMagnitude,
+        );
+        assert_eq!(value, 9007199254740994f64.to_bits());
+        let (value, _) = convert(
+            FloatCode::ConvertFromLongUnsigned,
+            FloatFormat::Single,
+            u64::MAX,
+            0,
+            RoundingMode::TowardZero,
+        );
+        assert_eq!(value, single(18446742974197923840.0));
+
+        let (value, _) = convert(
+            FloatCode::MoveToInt,
+            FloatFormat::Single,
+            0,
+            single(-1.0),
+            rne,
+        );
+        assert_eq!(value, 0xFFFFFFFF_BF800000);
+        let (value, _) = convert(
+            FloatCode::MoveToInt,
+            FloatFormat::Double,
+            0,
+            (-1.0f64).to_bits(),
+            rne,
+        );
+        assert_eq!(value, (-1.0f64).to_bits());
+
+        let (value, flags) = convert(
+            FloatCode::ConvertFloat,
+            FloatFormat::Single,
+            0,
+            0.1f64.to_bits(),
+            rne,
+        );
+        assert_eq!((value, flags), (single(0.1), INEXACT));
+        let (value, flags) = convert(
+            FloatCode::ConvertFloat,
+            FloatFormat::Double,
+            0,
+            single(0.1),
+            rne,
+        );
+        assert_eq!((value, flags), ((0.1f32 as f64).to_bits(), 0));
+    }
+
+    #[test]
+    fn test_fused() {
+        let rne = RoundingMode::NearestEven;
+        let frs = [2.0f64.to_bits(), 3.0f64.to_bits(), 1.0f64.to_bits()];
+        let fma = |code| fused_operation(&code, &FloatFormat::Double, frs, rne).0;
+        assert_eq!(fma(FusedCode::MulAdd), 7.0f64.to_bits());
+        assert_eq!(fma(FusedCode::MulSub), 5.0f64.to_bits());
+        assert_eq!(fma(FusedCode::NegateMulSub), (-5.0f64).to_bits());
+        assert_eq!(fma(FusedCode::NegateMulAdd), (-7.0f64).to_bits());
+
+        // 2^-40 * 2^-40 + 1 is just above 1, far below its ulp
+        let tiny = 2.0f64.powi(-40).to_bits();
+        let frs = [tiny, tiny, 1.0f64.to_bits()];
+        let fma = |rm| fused_operation(&FusedCode::MulAdd, &FloatFormat::Double, frs, rm);
+        assert_eq!(fma(rne), (1.0f64.to_bits(), INEXACT));
+        assert_eq!(fma(RoundingMode::Up), (1.0f64.next_up().to_bits(), INEXACT));
+
+        let frs = [single(0.0), single(f32::INFINITY), single(f32::NAN)];
+        let (value, flags) = fused_operation(&FusedCode::MulAdd, &FloatFormat::Single, frs, rne);
+        assert_eq!((value, flags), (nan_box(0x7FC00000), INVALID_OPERATION));
+    }
+}
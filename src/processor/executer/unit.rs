@@ -1,36 +1,167 @@
-use crate::processor::decoder::instruction::{AluCode, BranchOption};
+use crate::processor::{
+    decoder::instruction::{AluCode, AtomicCode, BranchOption, CsrCode},
+    xlen::Xlen,
+};
 
-pub fn alu(code: &AluCode, lhs: u32, rhs: u32) -> u32 {
-    match code {
-        AluCode::Add => lhs.wrapping_add(rhs),
-        AluCode::Sub => lhs.wrapping_sub(rhs),
-        AluCode::Slt => ((lhs as i32) < (rhs as i32)) as u32,
-        AluCode::Sltu => (lhs < rhs) as u32,
-        AluCode::Sll => lhs << rhs,
-        AluCode::Srl => lhs >> rhs,
-        AluCode::Sra => ((lhs as i32) >> (rhs as i32)) as u32,
-        AluCode::Xor => lhs ^ rhs,
-        AluCode::Or => lhs | rhs,
-        AluCode::And => lhs & rhs,
+// The same ALU serves both register widths, instantiated with the unsigned,
+// signed and double-width integer types of XLEN.
+macro_rules! alu {
+    ($(#[$attr:meta])* $name:ident, $unsigned:ty, $signed:ty, $wide:ty, $signed_wide:ty) => {
+        $(#[$attr])*
+        pub fn $name(code: &AluCode, lhs: $unsigned, rhs: $unsigned) -> $unsigned {
+            const XLEN: u32 = <$unsigned>::BITS;
+            // shift amounts and bit indexes only use the lower log2(XLEN) bits
+            let shamt = (rhs % XLEN as $unsigned) as u32;
+            // *.uw operations take the zero-extended lower word of rs1
+            let word = lhs as u32 as $unsigned;
+
+            match code {
+                AluCode::Add => lhs.wrapping_add(rhs),
+                AluCode::Sub => lhs.wrapping_sub(rhs),
+                AluCode::Slt => ((lhs as $signed) < (rhs as $signed)) as $unsigned,
+                AluCode::Sltu => (lhs < rhs) as $unsigned,
+                AluCode::Sll => lhs << shamt,
+                AluCode::Srl => lhs >> shamt,
+                AluCode::Sra => ((lhs as $signed) >> shamt) as $unsigned,
+                AluCode::Xor => lhs ^ rhs,
+                AluCode::Or => lhs | rhs,
+                AluCode::And => lhs & rhs,
+                AluCode::Sh1add => (lhs << 1).wrapping_add(rhs),
+                AluCode::Sh2add => (lhs << 2).wrapping_add(rhs),
+                AluCode::Sh3add => (lhs << 3).wrapping_add(rhs),
+                AluCode::AddUw => word.wrapping_add(rhs),
+                AluCode::Sh1addUw => (word << 1).wrapping_add(rhs),
+                AluCode::Sh2addUw => (word << 2).wrapping_add(rhs),
+                AluCode::Sh3addUw => (word << 3).wrapping_add(rhs),
+                AluCode::SlliUw => word << shamt,
+                AluCode::Andn => lhs & !rhs,
+                AluCode::Orn => lhs | !rhs,
+                AluCode::Xnor => !(lhs ^ rhs),
+                AluCode::Clz => lhs.leading_zeros() as $unsigned,
+                AluCode::Ctz => lhs.trailing_zeros() as $unsigned,
+                AluCode::Cpop => lhs.count_ones() as $unsigned,
+                AluCode::Min => (lhs as $signed).min(rhs as $signed) as $unsigned,
+                AluCode::MinUnsigned => lhs.min(rhs),
+                AluCode::Max => (lhs as $signed).max(rhs as $signed) as $unsigned,
+                AluCode::MaxUnsigned => lhs.max(rhs),
+                AluCode::SextB => lhs as u8 as i8 as $signed as $unsigned,
+                AluCode::SextH => lhs as u16 as i16 as $signed as $unsigned,
+                AluCode::ZextH => lhs % (1 << 16),
+                AluCode::Rol => lhs.rotate_left(shamt),
+                AluCode::Ror => lhs.rotate_right(shamt),
+                AluCode::OrcB => (0..XLEN / 8)
+                    .map(|i| 0xFF << (i * 8))
+                    .filter(|byte| lhs & byte != 0)
+                    .fold(0, |acc, byte| acc | byte),
+                AluCode::Rev8 => lhs.swap_bytes(),
+                AluCode::Clmul => carryless_multiply(lhs as u64, rhs as u64, XLEN) as $unsigned,
+                AluCode::Clmulh => {
+                    (carryless_multiply(lhs as u64, rhs as u64, XLEN) >> XLEN) as $unsigned
+                }
+                AluCode::Clmulr => {
+                    (carryless_multiply(lhs as u64, rhs as u64, XLEN) >> (XLEN - 1)) as $unsigned
+                }
+                AluCode::Bclr => lhs & !(1 << shamt),
+                AluCode::Bext => (lhs >> shamt) & 1,
+                AluCode::Binv => lhs ^ (1 << shamt),
+                AluCode::Bset => lhs | (1 << shamt),
+                AluCode::Mul => lhs.wrapping_mul(rhs),
+                AluCode::Mulh => {
+                    let product = lhs as $signed as $signed_wide * rhs as $signed as $signed_wide;
+                    (product >> XLEN) as $unsigned
+                }
+                AluCode::Mulhsu => {
+                    let product = lhs as $signed as $signed_wide * rhs as $wide as $signed_wide;
+                    (product >> XLEN) as $unsigned
+                }
+                AluCode::Mulhu => ((lhs as $wide * rhs as $wide) >> XLEN) as $unsigned,
+                // division by zero gives all ones, and the overflow of MIN / -1 gives MIN
+                AluCode::Div => match rhs {
+                    0 => <$unsigned>::MAX,
+                    _ => (lhs as $signed).wrapping_div(rhs as $signed) as $unsigned,
+                },
+                AluCode::DivUnsigned => lhs.checked_div(rhs).unwrap_or(<$unsigned>::MAX),
+                // the remainder by zero is the dividend
+                AluCode::Rem => match rhs {
+                    0 => lhs,
+                    _ => (lhs as $signed).wrapping_rem(rhs as $signed) as $unsigned,
+                },
+                AluCode::RemUnsigned => lhs.checked_rem(rhs).unwrap_or(lhs),
+            }
+        }
+    };
+}
+
+alu!(alu, u32, i32, u64, i64);
+alu!(alu64, u64, i64, u128, i128);
+
+/// W operations of RV64: computes on the lower words as RV32 does
+/// and sign-extends the 32-bit result.
+pub fn alu_word(code: &AluCode, lhs: u64, rhs: u64) -> u64 {
+    alu(code, lhs as u32, rhs as u32) as i32 as i64 as u64
+}
+
+/// Runs the ALU at the register width. Operands of RV32 are taken from the lower words.
+pub fn alu_xlen(code: &AluCode, lhs: u64, rhs: u64, xlen: &Xlen) -> u64 {
+    match xlen {
+        Xlen::X32 => alu(code, lhs as u32, rhs as u32) as u64,
+        Xlen::X64 => alu64(code, lhs, rhs),
     }
 }
 
-pub fn branch_operation(option: &BranchOption, rs1: u32, rs2: u32) -> bool {
+fn carryless_multiply(lhs: u64, rhs: u64, width: u32) -> u128 {
+    (0..width)
+        .filter(|i| (rhs >> i) & 1 == 1)
+        .fold(0, |acc, i| acc ^ ((lhs as u128) << i))
+}
+
+pub fn branch_operation(option: &BranchOption, rs1: u64, rs2: u64, xlen: &Xlen) -> bool {
+    let (lhs, rhs) = (xlen.signed(rs1), xlen.signed(rs2));
+
     match option {
         BranchOption::Equal => rs1 == rs2,
         BranchOption::NotEqual => rs1 != rs2,
-        BranchOption::GraterEqual => (rs1 as i32) >= (rs2 as i32),
+        BranchOption::GraterEqual => lhs >= rhs,
         BranchOption::GraterEqualUnsigned => rs1 >= rs2,
-        BranchOption::LessThan => (rs1 as i32) < (rs2 as i32),
+        BranchOption::LessThan => lhs < rhs,
         BranchOption::LessThanUnsigned => rs1 < rs2,
     }
 }
 
+/// Computes the value an AMO writes back from the loaded word and rs2.
+/// LR/SC are handled by the memory reservation and never reach here.
+pub fn atomic_operation(code: &AtomicCode, mem: u32, rs2: u32) -> u32 {
+    match code {
+        AtomicCode::Swap => rs2,
+        AtomicCode::Add => mem.wrapping_add(rs2),
+        AtomicCode::Xor => mem ^ rs2,
+        AtomicCode::And => mem & rs2,
+        AtomicCode::Or => mem | rs2,
+        AtomicCode::Min => (mem as i32).min(rs2 as i32) as u32,
+        AtomicCode::Max => (mem as i32).max(rs2 as i32) as u32,
+        AtomicCode::MinUnsigned => mem.min(rs2),
+        AtomicCode::MaxUnsigned => mem.max(rs2),
+        AtomicCode::LoadReserved | AtomicCode::StoreConditional => unreachable!(),
+    }
+}
+
+/// Computes the new CSR value from the old one and rs1 (or zimm).
+pub fn csr_operation(code: &CsrCode, csr: u64, operand: u64) -> u64 {
+    match code {
+        CsrCode::ReadWrite | CsrCode::ReadWriteImmediate => operand,
+        CsrCode::ReadSet | CsrCode::ReadSetImmediate => csr | operand,
+        CsrCode::ReadClear | CsrCode::ReadClearImmediate => csr & !operand,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::processor::decoder::instruction::AluCode;
+    use crate::processor::{
+        decoder::instruction::{AluCode, AtomicCode, BranchOption},
+        xlen::Xlen,
+    };
 
-    use super::alu;
+    use super::{alu, alu64, alu_word, atomic_operation, branch_operation};
 
     #[test]
     fn test_alu() {
@@ -48,5 +179,161 @@ mod tests {
         assert_eq!(alu(&AluCode::Xor, 0b00001111, 0b10101010), 0b10100101);
         assert_eq!(alu(&AluCode::Or, 0b11110000, 0b01010101), 0b11110101);
         assert_eq!(alu(&AluCode::And, 0b00001111, 0b01010101), 0b00000101);
+        // shift amounts wrap at the register width
+        assert_eq!(alu(&AluCode::Sll, 0x1, 0x21), 0x2);
+        assert_eq!(alu(&AluCode::Sra, 0x80000000, 0xFFFFFFE1), 0xC0000000);
+    }
+
+    #[test]
+    fn test_alu_bitmanip() {
+        assert_eq!(alu(&AluCode::Sh1add, 0x3, 0x10), 0x16);
+        assert_eq!(alu(&AluCode::Sh2add, 0x3, 0x10), 0x1C);
+        assert_eq!(alu(&AluCode::Sh3add, 0x3, 0x10), 0x28);
+        assert_eq!(alu(&AluCode::Andn, 0b1100, 0b1010), 0b0100);
+        assert_eq!(alu(&AluCode::Orn, 0b1100, 0b1010), 0xFFFFFFFD);
+        assert_eq!(alu(&AluCode::Xnor, 0b1100, 0b1010), 0xFFFFFFF9);
+        assert_eq!(alu(&AluCode::Clz, 0x00010000, 0), 15);
+        assert_eq!(alu(&AluCode::Clz, 0, 0), 32);
+        assert_eq!(alu(&AluCode::Ctz, 0x00010000, 0), 16);
+        assert_eq!(alu(&AluCode::Ctz, 0, 0), 32);
+        assert_eq!(alu(&AluCode::Cpop, 0xF0F0000F, 0), 12);
+        assert_eq!(alu(&AluCode::Min, 0xFFFFFFFF, 0x1), 0xFFFFFFFF);
+        assert_eq!(alu(&AluCode::MinUnsigned, 0xFFFFFFFF, 0x1), 0x1);
+        assert_eq!(alu(&AluCode::Max, 0xFFFFFFFF, 0x1), 0x1);
+        assert_eq!(alu(&AluCode::MaxUnsigned, 0xFFFFFFFF, 0x1), 0xFFFFFFFF);
+        assert_eq!(alu(&AluCode::SextB, 0x12345680, 0), 0xFFFFFF80);
+        assert_eq!(alu(&AluCode::SextH, 0x12347FFF, 0), 0x00007FFF);
+        assert_eq!(alu(&AluCode::ZextH, 0x1234F000, 0), 0x0000F000);
+        assert_eq!(alu(&AluCode::Rol, 0x80000001, 0x1), 0x00000003);
+        assert_eq!(alu(&AluCode::Ror, 0x80000001, 0x21), 0xC0000000);
+        assert_eq!(alu(&AluCode::OrcB, 0x00100F00, 0), 0x00FFFF00);
+        assert_eq!(alu(&AluCode::Rev8, 0x12345678, 0), 0x78563412);
+        assert_eq!(alu(&AluCode::Clmul, 0b0110, 0b0011), 0b1010);
+        assert_eq!(alu(&AluCode::Clmulh, 0x80000000, 0x6), 0x3);
+        assert_eq!(alu(&AluCode::Clmulr, 0x80000000, 0x6), 0x6);
+        assert_eq!(alu(&AluCode::Bclr, 0xFF, 0x3), 0xF7);
+        assert_eq!(alu(&AluCode::Bext, 0x8, 0x3), 0x1);
+        assert_eq!(alu(&AluCode::Binv, 0xFF, 0x24), 0xEF);
+        assert_eq!(alu(&AluCode::Bset, 0x0, 0x1F), 0x80000000);
+    }
+
+    #[test]
+    fn test_alu_multiply() {
+        assert_eq!(alu(&AluCode::Mul, 0xFFFFFFFF, 0x3), 0xFFFFFFFD);
+        assert_eq!(alu(&AluCode::Mulh, 0xFFFFFFFF, 0x3), 0xFFFFFFFF);
+        assert_eq!(alu(&AluCode::Mulhsu, 0xFFFFFFFF, 0xFFFFFFFF), 0xFFFFFFFF);
+        assert_eq!(alu(&AluCode::Mulhu, 0xFFFFFFFF, 0xFFFFFFFF), 0xFFFFFFFE);
+        assert_eq!(alu(&AluCode::Div, 0xFFFFFFF9, 0x2), 0xFFFFFFFD);
+        assert_eq!(alu(&AluCode::DivUnsigned, 0xFFFFFFF9, 0x2), 0x7FFFFFFC);
+        assert_eq!(alu(&AluCode::Rem, 0xFFFFFFF9, 0x2), 0xFFFFFFFF);
+        assert_eq!(alu(&AluCode::RemUnsigned, 0xFFFFFFF9, 0x2), 0x1);
+        // division by zero and signed overflow do not trap
+        assert_eq!(alu(&AluCode::Div, 0x7, 0x0), 0xFFFFFFFF);
+        assert_eq!(alu(&AluCode::DivUnsigned, 0x7, 0x0), 0xFFFFFFFF);
+        assert_eq!(alu(&AluCode::Rem, 0x7, 0x0), 0x7);
+        assert_eq!(alu(&AluCode::RemUnsigned, 0x7, 0x0), 0x7);
+        assert_eq!(alu(&AluCode::Div, 0x80000000, 0xFFFFFFFF), 0x80000000);
+        assert_eq!(alu(&AluCode::Rem, 0x80000000, 0xFFFFFFFF), 0x0);
+
+        assert_eq!(alu64(&AluCode::Mulh, u64::MAX, 0x3), u64::MAX);
+        assert_eq!(alu64(&AluCode::Mulhu, u64::MAX, u64::MAX), u64::MAX - 1);
+        assert_eq!(alu64(&AluCode::Div, 1 << 63, u64::MAX), 1 << 63);
+        assert_eq!(
+            alu_word(&AluCode::DivUnsigned, 0xFFFFFFFF_FFFFFFF9, 0x2),
+            0x7FFFFFFC
+        );
+        assert_eq!(alu_word(&AluCode::Mul, 0x10000, 0x10000), 0x0);
+    }
+
+    #[test]
+    fn test_alu64() {
+        assert_eq!(alu64(&AluCode::Add, u64::MAX, 0x2), 0x1);
+        assert_eq!(alu64(&AluCode::Slt, 0xFFFFFFFF, 0), 0);
+        assert_eq!(alu64(&AluCode::Slt, u64::MAX, 0), 1);
+        assert_eq!(alu64(&AluCode::Sll, 0x1, 0x3F), 0x80000000_00000000);
+        assert_eq!(alu64(&AluCode::Sll, 0x1, 0x40), 0x1);
+        assert_eq!(
+            alu64(&AluCode::Sra, 0x80000000_00000000, 0x3C),
+            u64::MAX - 7
+        );
+        assert_eq!(alu64(&AluCode::Srl, 0x80000000, 0x20), 0);
+        assert_eq!(alu64(&AluCode::AddUw, 0xFFFFFFFF_80000000, 0x1), 0x80000001);
+        assert_eq!(alu64(&AluCode::Sh3addUw, 0xFFFFFFFF_00000001, 0x1), 0x9);
+        assert_eq!(
+            alu64(&AluCode::SlliUw, 0xFFFFFFFF_80000000, 0x4),
+            0x8_00000000
+        );
+        assert_eq!(alu64(&AluCode::Clz, 0x1, 0), 63);
+        assert_eq!(alu64(&AluCode::SextB, 0x80, 0), u64::MAX - 0x7F);
+        assert_eq!(
+            alu64(&AluCode::OrcB, 0x01000000_00100000, 0),
+            0xFF000000_00FF0000
+        );
+        assert_eq!(
+            alu64(&AluCode::Rev8, 0x01234567_89ABCDEF, 0),
+            0xEFCDAB89_67452301
+        );
+        assert_eq!(alu64(&AluCode::Clmulh, 0x80000000_00000000, 0x6), 0x3);
+        assert_eq!(alu64(&AluCode::Bset, 0x0, 0x3F), 0x80000000_00000000);
+    }
+
+    #[test]
+    fn test_alu_word() {
+        assert_eq!(
+            alu_word(&AluCode::Add, 0x7FFFFFFF, 0x1),
+            0xFFFFFFFF_80000000
+        );
+        assert_eq!(alu_word(&AluCode::Add, 0x12345678_00000001, 0x1), 0x2);
+        assert_eq!(alu_word(&AluCode::Sub, 0x0, 0x1), u64::MAX);
+        assert_eq!(alu_word(&AluCode::Sll, 0x1, 0x1F), 0xFFFFFFFF_80000000);
+        assert_eq!(
+            alu_word(&AluCode::Srl, 0xFFFFFFFF_80000000, 0x1),
+            0x40000000
+        );
+        assert_eq!(
+            alu_word(&AluCode::Sra, 0x80000000, 0x1),
+            0xFFFFFFFF_C0000000
+        );
+        assert_eq!(alu_word(&AluCode::Clz, 0xFFFFFFFF_00000001, 0), 31);
+        assert_eq!(
+            alu_word(&AluCode::Rol, 0x40000000, 0x1),
+            0xFFFFFFFF_80000000
+        );
+    }
+
+    #[test]
+    fn test_branch_operation() {
+        let less = |rs1, rs2, xlen| branch_operation(&BranchOption::LessThan, rs1, rs2, &xlen);
+        assert!(less(0xFFFFFFFF, 0, Xlen::X32));
+        assert!(!less(0xFFFFFFFF, 0, Xlen::X64));
+        assert!(less(u64::MAX, 0, Xlen::X64));
+        assert!(branch_operation(
+            &BranchOption::LessThanUnsigned,
+            0x1,
+            0xFFFFFFFF,
+            &Xlen::X32
+        ));
+    }
+
+    #[test]
+    fn test_atomic_operation() {
+        assert_eq!(atomic_operation(&AtomicCode::Swap, 0x1, 0x2), 0x2);
+        assert_eq!(atomic_operation(&AtomicCode::Add, 0xFFFFFFFF, 0x2), 0x1);
+        assert_eq!(atomic_operation(&AtomicCode::Xor, 0b1100, 0b1010), 0b0110);
+        assert_eq!(atomic_operation(&AtomicCode::And, 0b1100, 0b1010), 0b1000);
+        assert_eq!(atomic_operation(&AtomicCode::Or, 0b1100, 0b1010), 0b1110);
+        assert_eq!(
+            atomic_operation(&AtomicCode::Min, 0xFFFFFFFE, 0x1),
+            0xFFFFFFFE
+        );
+        assert_eq!(atomic_operation(&AtomicCode::Max, 0xFFFFFFFE, 0x1), 0x1);
+        assert_eq!(
+            atomic_operation(&AtomicCode::MinUnsigned, 0xFFFFFFFE, 0x1),
+            0x1
+        );
+        assert_eq!(
+            atomic_operation(&AtomicCode::MaxUnsigned, 0xFFFFFFFE, 0x1),
+            0xFFFFFFFE
+        );
     }
 }
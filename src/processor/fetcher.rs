@@ -1,49 +1,33 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-};
+use crate::memory::MainMemory;
 
-use anyhow::Result;
-
-pub const INSTRUCTION_MEMORY_SIZE: usize = 0x10000;
-pub const INSTRUCTION_MEMORY_INIT: usize = 0x2000;
+use super::{decoder::compressed::is_compressed, ProcessorError};
 
 pub struct Fetcher {
-    pub pc: u32,
-    mem: [u32; INSTRUCTION_MEMORY_SIZE],
+    pub pc: u64,
 }
 
 impl Fetcher {
-    pub fn new() -> Self {
-        Self {
-            pc: INSTRUCTION_MEMORY_INIT as u32,
-            mem: [0; INSTRUCTION_MEMORY_SIZE],
-        }
+    pub fn new(reset_vector: u64) -> Self {
+        Self { pc: reset_vector }
     }
 
-    pub fn update_program_counter(&mut self, pc: u32) {
+    pub fn update_program_counter(&mut self, pc: u64) {
         self.pc = pc;
     }
 
-    pub fn fetch(&self) -> u32 {
-        let index = self.pc >> 2;
-        self.mem[index as usize]
+    /// Fetches one instruction at `pc`, which only has to be 2-byte aligned.
+    /// A compressed instruction is returned in the lower 16 bits.
+    pub fn fetch(&self, memory: &MainMemory) -> Result<u32, ProcessorError> {
+        Self::fetch_at(memory, self.pc)
     }
 
-    pub fn load_hex<P>(&mut self, path: P, diff: usize) -> Result<()>
-    where
-        P: AsRef<Path>,
-    {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let mut index = diff >> 2;
-
-        for line in reader.lines() {
-            self.mem[index] = u32::from_str_radix(line?.as_str(), 16)?;
-            index += 1;
+    pub fn fetch_at(memory: &MainMemory, address: u64) -> Result<u32, ProcessorError> {
+        let low = memory.fetch_parcel(address)?;
+        if is_compressed(low) {
+            Ok(low)
+        } else {
+            let high = memory.fetch_parcel(address.wrapping_add(2))?;
+            Ok((high << 16) + low)
         }
-
-        Ok(())
     }
 }
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+
+use super::csr::ControlStatusRegister;
+
+/// Old state overwritten by one step, enough to undo it.
+pub struct UndoRecord {
+    pub pc: u64,
+    pub is_halt: bool,
+    pub csr: ControlStatusRegister,
+    pub reservation: Option<u64>,
+    // (index, old value)
+    pub registers: Vec<(u8, u64)>,
+    pub float_registers: Vec<(u8, u64)>,
+    // (address, width, old value)
+    pub memory: Vec<(u64, u64, u64)>,
+}
+
+impl UndoRecord {
+    pub fn new(
+        pc: u64,
+        is_halt: bool,
+        csr: ControlStatusRegister,
+        reservation: Option<u64>,
+    ) -> Self {
+        Self {
+            pc,
+            is_halt,
+            csr,
+            reservation,
+            registers: vec![],
+            float_registers: vec![],
+            memory: vec![],
+        }
+    }
+}
+
+/// Undo log of the most recent steps, bounded by `limit` records.
+pub struct History {
+    records: VecDeque<UndoRecord>,
+    limit: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Starts the record of a new step, forgetting the oldest one when full.
+    pub fn begin(&mut self, record: UndoRecord) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.records.len() == self.limit {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn current(&mut self) -> Option<&mut UndoRecord> {
+        self.records.back_mut()
+    }
+
+    pub fn pop(&mut self) -> Option<UndoRecord> {
+        self.records.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::csr::ControlStatusRegister;
+
+    use super::{History, UndoRecord};
+
+    #[test]
+    fn test_history_limit() {
+        let record = |pc| UndoRecord::new(pc, false, ControlStatusRegister::new(), None);
+        let mut history = History::new(2);
+        history.begin(record(0x0));
+        history.begin(record(0x4));
+        history.begin(record(0x8));
+        assert_eq!(history.len(), 2);
+
+        history.current().unwrap().registers.push((1, 5));
+        let last = history.pop().unwrap();
+        assert_eq!(last.pc, 0x8);
+        assert_eq!(last.registers, [(1, 5)]);
+        assert_eq!(history.pop().unwrap().pc, 0x4);
+        assert!(history.pop().is_none());
+
+        let mut history = History::new(0);
+        history.begin(record(0x0));
+        assert!(history.is_empty());
+    }
+}
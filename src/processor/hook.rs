@@ -0,0 +1,174 @@
+use super::{decoder::instruction::Instruction, ProcessorError};
+
+/// What a hook asks of the processor after an event.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum HookAction {
+    #[default]
+    Continue,
+    // finish the current instruction, then stop the run
+    Stop,
+}
+
+/// Callbacks on the events of every step, for analyzers built on `Processor`.
+///
+/// Every method gets the pc of the instruction causing the event and does nothing
+/// by default, so a hook only implements what it watches. Events of one
+/// instruction come in program order: CSR access, memory, register write, retire.
+pub trait Hook {
+    /// `inst` at `pc` retired, continuing at `next_pc`.
+    fn retire(&mut self, _inst: &Instruction, _pc: u64, _next_pc: u64) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// `width` bytes read at `address`; a load's value before sign extension.
+    fn memory_read(&mut self, _pc: u64, _address: u64, _width: u64, _value: u64) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// `width` bytes written at `address`.
+    fn memory_write(&mut self, _pc: u64, _address: u64, _width: u64, _value: u64) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Integer register `index` written, x0 excepted.
+    fn register_write(&mut self, _pc: u64, _index: u8, _value: u64) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Floating-point register `index` written, NaN-boxed.
+    fn float_register_write(&mut self, _pc: u64, _index: u8, _value: u64) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// CSR `address` read as `old`, and written with `new` unless the
+    /// instruction only reads it.
+    fn csr_access(&mut self, _pc: u64, _address: u16, _old: u64, _new: Option<u64>) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// The instruction at `pc` raised `error` instead of retiring. The processor
+    /// has no trap vector to enter, so the run ends with this error and there
+    /// is no matching trap exit.
+    fn trap(&mut self, _pc: u64, _error: &ProcessorError) -> HookAction {
+        HookAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        machine::{MachineConfig, RegionConfig},
+        memory::Permissions,
+        processor::{decoder::instruction::Instruction, Processor, ProcessorError},
+    };
+
+    use super::{Hook, HookAction};
+
+    #[derive(Default)]
+    struct Log {
+        retired: u64,
+        // (pc, address, width, value)
+        writes: Vec<(u64, u64, u64, u64)>,
+        a0: Vec<u64>,
+        traps: Vec<u64>,
+    }
+
+    // stops at the first store to `address`
+    struct Watch {
+        address: u64,
+        log: Rc<RefCell<Log>>,
+    }
+
+    impl Hook for Watch {
+        fn retire(&mut self, _inst: &Instruction, _pc: u64, _next_pc: u64) -> HookAction {
+            self.log.borrow_mut().retired += 1;
+            HookAction::Continue
+        }
+
+        fn memory_write(&mut self, pc: u64, address: u64, width: u64, value: u64) -> HookAction {
+            self.log
+                .borrow_mut()
+                .writes
+                .push((pc, address, width, value));
+            match address == self.address {
+                true => HookAction::Stop,
+                false => HookAction::Continue,
+            }
+        }
+
+        fn register_write(&mut self, _pc: u64, index: u8, value: u64) -> HookAction {
+            if index == 10 {
+                self.log.borrow_mut().a0.push(value);
+            }
+            HookAction::Continue
+        }
+
+        fn trap(&mut self, pc: u64, _error: &ProcessorError) -> HookAction {
+            self.log.borrow_mut().traps.push(pc);
+            HookAction::Continue
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut cpu = Processor::init("./example/elf/calls.elf").unwrap();
+        cpu.set_trace(false);
+        let log = Rc::new(RefCell::new(Log::default()));
+        cpu.add_hook(Box::new(Watch {
+            address: 0x0,
+            log: log.clone(),
+        }));
+
+        let count = cpu.run_blocks(u64::MAX).unwrap();
+        // the store of the result retires, then the run stops short of the halt
+        assert!(cpu.stop_requested());
+        assert!(!cpu.is_halt());
+        assert_eq!(cpu.pc(), 0x2014);
+
+        let log = log.borrow();
+        assert_eq!(log.retired, count);
+        assert_eq!(log.writes.last(), Some(&(0x2010, 0x0, 4, 385)));
+        // ra, s0 and s1 saved by sum_squares
+        assert_eq!(log.writes.len(), 4);
+        assert_eq!(log.a0.first(), Some(&10));
+        assert_eq!(log.a0.last(), Some(&385));
+        assert!(log.traps.is_empty());
+
+        assert_eq!(cpu.take_hooks().len(), 1);
+        cpu.step().unwrap();
+        assert!(!cpu.stop_requested());
+        assert!(cpu.is_halt());
+
+        // the block engine resumes after a stop as well
+        let mut cpu = Processor::init("./example/elf/calls.elf").unwrap();
+        cpu.set_trace(false);
+        cpu.add_hook(Box::new(Watch {
+            address: 0x0,
+            log: Rc::new(RefCell::new(Log::default())),
+        }));
+        cpu.run_blocks(u64::MAX).unwrap();
+        assert!(cpu.stop_requested());
+        assert_eq!(cpu.run_blocks(u64::MAX).unwrap(), 1);
+        assert!(!cpu.stop_requested());
+        assert!(cpu.is_halt());
+    }
+
+    #[test]
+    fn test_trap_hook() {
+        // the stack of calls.elf is past the end of the RAM
+        let ram = RegionConfig::new("ram", 0x0, 0x3000, Permissions::all());
+        let config = MachineConfig::new(0x2000, vec![ram]);
+        let mut cpu = Processor::init_with_config("./example/elf/calls.elf", &config).unwrap();
+        cpu.set_trace(false);
+        let log = Rc::new(RefCell::new(Log::default()));
+        cpu.add_hook(Box::new(Watch {
+            address: 0x0,
+            log: log.clone(),
+        }));
+
+        assert!(cpu.run_blocks(u64::MAX).is_err());
+        assert_eq!(log.borrow().traps, [0x201c]);
+    }
+}
@@ -0,0 +1,414 @@
+use std::fmt::{Display, Write};
+
+use super::decoder::instruction::{Instruction, InstructionCode, RiscvInstruction};
+
+/// Stages of the classic in-order pipeline.
+pub const STAGES: [&str; 5] = ["IF", "ID", "EX", "MEM", "WB"];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Operand {
+    Int(u8),
+    Float(u8),
+}
+
+/// Registers an instruction reads and the one it writes, if any.
+fn operands(inst: &Instruction) -> (Vec<Operand>, Option<Operand>) {
+    use Operand::{Float, Int};
+    let (rs1, rs2, rs3, rd) = (inst.rs1, inst.rs2, inst.rs3, inst.rd);
+
+    let (sources, dest) = match &inst.code {
+        InstructionCode::Lui | InstructionCode::Auipc | InstructionCode::Jal => {
+            (vec![], Some(Int(rd)))
+        }
+        InstructionCode::Jalr
+        | InstructionCode::Load(_)
+        | InstructionCode::OpeI(_)
+        | InstructionCode::OpeIW(_) => (vec![Int(rs1)], Some(Int(rd))),
+        InstructionCode::Branch(_) | InstructionCode::Store(_) => (vec![Int(rs1), Int(rs2)], None),
+        InstructionCode::Ope(_) | InstructionCode::OpeW(_) | InstructionCode::Atomic(_, _) => {
+            (vec![Int(rs1), Int(rs2)], Some(Int(rd)))
+        }
+        InstructionCode::LoadFloat(_) => (vec![Int(rs1)], Some(Float(rd))),
+        InstructionCode::StoreFloat(_) => (vec![Int(rs1), Float(rs2)], None),
+        InstructionCode::FloatOpe(code, _) => {
+            let sources = if code.reads_integer() {
+                vec![Int(rs1)]
+            } else if code.reads_second() {
+                vec![Float(rs1), Float(rs2)]
+            } else {
+                vec![Float(rs1)]
+            };
+            let dest = if code.writes_integer() {
+                Int(rd)
+            } else {
+                Float(rd)
+            };
+            (sources, Some(dest))
+        }
+        InstructionCode::Fused(_, _) => (vec![Float(rs1), Float(rs2), Float(rs3)], Some(Float(rd))),
+        InstructionCode::Csr(code) if code.is_immediate() => (vec![], Some(Int(rd))),
+        InstructionCode::Csr(_) => (vec![Int(rs1)], Some(Int(rd))),
+        InstructionCode::Fence | InstructionCode::FenceI => (vec![], None),
+    };
+
+    // x0 is never a hazard
+    let sources = sources.into_iter().filter(|op| *op != Int(0)).collect();
+    let dest = dest.filter(|op| *op != Int(0));
+    (sources, dest)
+}
+
+/// Cycles at which one instruction enters each stage.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StageTiming {
+    pub fetch: u64,
+    pub decode: u64,
+    pub execute: u64,
+    pub memory: u64,
+    pub writeback: u64,
+}
+
+impl StageTiming {
+    /// Stage index occupied at `cycle`, if any. A stalled instruction stays in its stage.
+    pub fn stage_at(&self, cycle: u64) -> Option<usize> {
+        let bounds = [
+            self.fetch,
+            self.decode,
+            self.execute,
+            self.memory,
+            self.writeback,
+            self.writeback + 1,
+        ];
+        (0..5).find(|i| bounds[*i] <= cycle && cycle < bounds[i + 1])
+    }
+}
+
+/// Latest cycle the value of a register is written, and when it can be used.
+#[derive(Clone, Copy)]
+struct Producer {
+    // first EX cycle that can take the value over a forwarding path
+    forwarded: u64,
+    writeback: u64,
+}
+
+/// Trace-driven timing of a classic IF/ID/EX/MEM/WB pipeline.
+///
+/// The functional model executes each instruction and reports it here as it retires,
+/// so the architectural results are those of `Processor::step`; this only decides
+/// in which cycle each instruction passes each stage. Branches and jumps resolve in
+/// EX and fetch falls through until then, so a taken one flushes two instructions.
+pub struct Pipeline {
+    forwarding: bool,
+    last: Option<StageTiming>,
+    // the previous instruction redirected fetch after its EX
+    redirect: Option<u64>,
+    int_producers: [Option<Producer>; 32],
+    float_producers: [Option<Producer>; 32],
+    instructions: u64,
+    data_stalls: u64,
+    flush_cycles: u64,
+    // extra IF and MEM cycles of the next instruction, from the cache model
+    pending_fetch: u64,
+    pending_memory: u64,
+    memory_stalls: u64,
+    // (pc, disassembly, timing) of the first instructions, for the diagram
+    records: Vec<(u64, String, StageTiming)>,
+    record_limit: usize,
+}
+
+impl Pipeline {
+    pub fn new(forwarding: bool) -> Self {
+        Self {
+            forwarding,
+            last: None,
+            redirect: None,
+            int_producers: [None; 32],
+            float_producers: [None; 32],
+            instructions: 0,
+            data_stalls: 0,
+            flush_cycles: 0,
+            pending_fetch: 0,
+            pending_memory: 0,
+            memory_stalls: 0,
+            records: vec![],
+            record_limit: 32,
+        }
+    }
+
+    /// Keeps the timing of up to `limit` instructions for `diagram`.
+    pub fn set_record_limit(&mut self, limit: usize) {
+        self.record_limit = limit;
+    }
+
+    /// Holds the next retired instruction `fetch` extra cycles in IF and `memory` in MEM,
+    /// for misses in the caches in front of them.
+    pub fn delay(&mut self, fetch: u64, memory: u64) {
+        self.pending_fetch += fetch;
+        self.pending_memory += memory;
+    }
+
+    /// Schedules an instruction retired by the functional model at `pc`, which
+    /// continued at `next_pc`.
+    pub fn retire(&mut self, inst: &Instruction, pc: u64, next_pc: u64) -> StageTiming {
+        let (sources, dest) = operands(inst);
+        let last = self.last.unwrap_or(StageTiming {
+            fetch: 0,
+            decode: 0,
+            execute: 0,
+            memory: 0,
+            writeback: 0,
+        });
+        let is_first = self.last.is_none();
+
+        // an instruction enters a stage once its predecessor has left it
+        let mut fetch = if is_first { 0 } else { last.decode };
+        let mut decode = (fetch + 1).max(last.execute);
+        if let Some(redirect) = self.redirect.take() {
+            // cycles lost against falling through, not counting a stall of the branch itself
+            self.flush_cycles += redirect + 1 - decode;
+            fetch = redirect;
+            decode = fetch + 1;
+        }
+        let fetch_delay = std::mem::take(&mut self.pending_fetch);
+        let memory_delay = std::mem::take(&mut self.pending_memory);
+        if fetch + 1 + fetch_delay > decode {
+            self.memory_stalls += fetch + 1 + fetch_delay - decode;
+            decode = fetch + 1 + fetch_delay;
+        }
+
+        let structural = (decode + 1).max(last.memory);
+        let ready = sources
+            .iter()
+            .filter_map(|op| self.producer(*op))
+            .map(|producer| {
+                if self.forwarding {
+                    producer.forwarded
+                } else {
+                    // the register file is written in the first half of WB and read in the second
+                    producer.writeback + 1
+                }
+            })
+            .max()
+            .unwrap_or(0);
+        let execute = structural.max(ready);
+        self.data_stalls += execute - structural;
+
+        let memory = (execute + 1).max(last.writeback);
+        let writeback =
+            (memory + 1 + memory_delay).max(if is_first { 0 } else { last.writeback + 1 });
+        self.memory_stalls += memory_delay;
+        let timing = StageTiming {
+            fetch,
+            decode,
+            execute,
+            memory,
+            writeback,
+        };
+
+        if let Some(dest) = dest {
+            let loads = matches!(
+                inst.code,
+                InstructionCode::Load(_)
+                    | InstructionCode::LoadFloat(_)
+                    | InstructionCode::Atomic(_, _)
+            );
+            let producer = Producer {
+                forwarded: if loads {
+                    memory + 1 + memory_delay
+                } else {
+                    execute + 1
+                },
+                writeback,
+            };
+            match dest {
+                Operand::Int(rd) => self.int_producers[rd as usize] = Some(producer),
+                Operand::Float(rd) => self.float_producers[rd as usize] = Some(producer),
+            }
+        }
+
+        if next_pc != pc.wrapping_add(inst.size()) {
+            self.redirect = Some(execute + 1);
+        }
+
+        if self.records.len() < self.record_limit {
+            self.records.push((pc, inst.assembly(), timing));
+        }
+        self.instructions += 1;
+        self.last = Some(timing);
+        timing
+    }
+
+    fn producer(&self, op: Operand) -> Option<Producer> {
+        match op {
+            Operand::Int(rs) => self.int_producers[rs as usize],
+            Operand::Float(rs) => self.float_producers[rs as usize],
+        }
+    }
+
+    /// Cycles until the last retired instruction leaves WB.
+    pub fn cycles(&self) -> u64 {
+        self.last.map_or(0, |last| last.writeback + 1)
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn cpi(&self) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            self.cycles() as f64 / self.instructions as f64
+        }
+    }
+
+    /// Cycles spent waiting in ID for operands: load-use, or any RAW hazard without forwarding.
+    pub fn data_stalls(&self) -> u64 {
+        self.data_stalls
+    }
+
+    /// Fetch cycles lost to instructions squashed behind taken branches and jumps.
+    pub fn flush_cycles(&self) -> u64 {
+        self.flush_cycles
+    }
+
+    /// Cycles spent waiting in IF and MEM for the caches.
+    pub fn memory_stalls(&self) -> u64 {
+        self.memory_stalls
+    }
+
+    pub fn records(&self) -> &[(u64, String, StageTiming)] {
+        &self.records
+    }
+
+    /// One row per recorded instruction and one column per cycle, naming the stage it is in.
+    pub fn diagram(&self) -> String {
+        let Some(end) = self
+            .records
+            .last()
+            .map(|(_, _, timing)| timing.writeback + 1)
+        else {
+            return String::new();
+        };
+        let width = self
+            .records
+            .iter()
+            .map(|(_, asm, _)| asm.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut diagram = format!("{:>10} {:width$} |", "pc", "cycle", width = width);
+        for cycle in 0..end {
+            write!(diagram, "{:>4}", cycle).ok();
+        }
+        diagram.push('\n');
+
+        for (pc, asm, timing) in self.records.iter() {
+            write!(diagram, "{:#010x} {:width$} |", pc, asm, width = width).ok();
+            for cycle in 0..end {
+                let stage = timing.stage_at(cycle).map_or("", |stage| STAGES[stage]);
+                write!(diagram, "{:>4}", stage).ok();
+            }
+            diagram.push('\n');
+        }
+        diagram
+    }
+}
+
+impl Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "cycles       : {}", self.cycles())?;
+        writeln!(f, "instructions : {}", self.instructions())?;
+        writeln!(f, "cpi          : {:.3}", self.cpi())?;
+        writeln!(f, "data stalls  : {}", self.data_stalls())?;
+        writeln!(f, "flush cycles : {}", self.flush_cycles())?;
+        write!(f, "memory stalls: {}", self.memory_stalls())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::{decoder::decode, xlen::Xlen};
+
+    use super::Pipeline;
+
+    fn retire(pipeline: &mut Pipeline, raw: u32, pc: u64, next_pc: u64) -> [u64; 5] {
+        let inst = decode(raw, &Xlen::X32).ok().unwrap();
+        let t = pipeline.retire(&inst, pc, next_pc);
+        [t.fetch, t.decode, t.execute, t.memory, t.writeback]
+    }
+
+    #[test]
+    fn test_forwarding() {
+        let mut pipeline = Pipeline::new(true);
+        // addi a0, zero, 1; add a1, a0, a0: forwarded from EX, no stall
+        assert_eq!(retire(&mut pipeline, 0x00100513, 0x0, 0x4), [0, 1, 2, 3, 4]);
+        assert_eq!(retire(&mut pipeline, 0x00a505b3, 0x4, 0x8), [1, 2, 3, 4, 5]);
+        // lw a2, 0(a1); add a3, a2, a2: load-use stalls one cycle
+        assert_eq!(retire(&mut pipeline, 0x0005a603, 0x8, 0xC), [2, 3, 4, 5, 6]);
+        assert_eq!(
+            retire(&mut pipeline, 0x00c606b3, 0xC, 0x10),
+            [3, 4, 6, 7, 8]
+        );
+        // the stall holds the next instruction in IF
+        assert_eq!(
+            retire(&mut pipeline, 0x00000013, 0x10, 0x14),
+            [4, 6, 7, 8, 9]
+        );
+        assert_eq!(pipeline.data_stalls(), 1);
+        assert_eq!(pipeline.cycles(), 10);
+        assert_eq!(pipeline.instructions(), 5);
+        assert_eq!(pipeline.cpi(), 2.0);
+    }
+
+    #[test]
+    fn test_without_forwarding() {
+        let mut pipeline = Pipeline::new(false);
+        // add a1, a0, a0 reads a0 in the cycle addi writes it back
+        assert_eq!(retire(&mut pipeline, 0x00100513, 0x0, 0x4), [0, 1, 2, 3, 4]);
+        assert_eq!(retire(&mut pipeline, 0x00a505b3, 0x4, 0x8), [1, 2, 5, 6, 7]);
+        assert_eq!(pipeline.data_stalls(), 2);
+    }
+
+    #[test]
+    fn test_unary_float() {
+        let mut pipeline = Pipeline::new(false);
+        // fadd.s ft0, fa1, fa2; fsqrt.s fa0, fa1: rs2 = 0 of fsqrt is not ft0
+        assert_eq!(retire(&mut pipeline, 0x00C5F053, 0x0, 0x4), [0, 1, 2, 3, 4]);
+        assert_eq!(retire(&mut pipeline, 0x5805F553, 0x4, 0x8), [1, 2, 3, 4, 5]);
+        assert_eq!(pipeline.data_stalls(), 0);
+        // fsqrt.s fa2, ft0 does wait for it
+        assert_eq!(retire(&mut pipeline, 0x58007653, 0x8, 0xC), [2, 3, 5, 6, 7]);
+        assert_eq!(pipeline.data_stalls(), 1);
+    }
+
+    #[test]
+    fn test_branch_flush() {
+        let mut pipeline = Pipeline::new(true);
+        // beq zero, zero, 16 taken: the target is fetched after EX
+        assert_eq!(
+            retire(&mut pipeline, 0x00000863, 0x0, 0x10),
+            [0, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            retire(&mut pipeline, 0x00000013, 0x10, 0x14),
+            [3, 4, 5, 6, 7]
+        );
+        assert_eq!(pipeline.flush_cycles(), 2);
+
+        // not taken costs nothing
+        assert_eq!(
+            retire(&mut pipeline, 0x00001863, 0x14, 0x18),
+            [4, 5, 6, 7, 8]
+        );
+        assert_eq!(
+            retire(&mut pipeline, 0x00000013, 0x18, 0x1C),
+            [5, 6, 7, 8, 9]
+        );
+        assert_eq!(pipeline.flush_cycles(), 2);
+
+        let diagram = pipeline.diagram();
+        let row = diagram.lines().nth(2).unwrap();
+        assert!(row.starts_with("0x00000010 addi zero, zero, 0"));
+        assert!(row.ends_with("|              IF  ID  EX MEM  WB        "));
+    }
+}
@@ -0,0 +1,512 @@
+use std::{collections::HashMap, fmt::Display};
+
+use anyhow::{bail, Result};
+
+use super::decoder::instruction::{Instruction, InstructionCode};
+
+/// Entries of the pattern tables of `Bimodal` and `Gshare` unless told otherwise.
+const DEFAULT_TABLE_BITS: u32 = 12;
+
+/// Entries of the return-address stack unless told otherwise.
+const DEFAULT_RAS_DEPTH: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BranchKind {
+    Conditional,
+    Jump,
+    Call,
+    Return,
+    // jalr that neither calls nor returns
+    Indirect,
+}
+
+/// A control transfer about to be predicted, with what is known at decode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Branch {
+    pub pc: u64,
+    pub kind: BranchKind,
+    // pc + imm for branches and jal; unknown until execute for jalr
+    pub target: Option<u64>,
+    pub fallthrough: u64,
+    // a call on the way out of a return (jalr ra, ra / jalr ra, t0)
+    pub links: bool,
+}
+
+impl Branch {
+    /// Classifies `inst` at `pc`; `None` when it does not transfer control.
+    /// Calls and returns follow the x1/x5 link register hints of the ISA.
+    pub fn new(inst: &Instruction, pc: u64) -> Option<Self> {
+        let is_link = |reg: u8| reg == 1 || reg == 5;
+        let fallthrough = pc.wrapping_add(inst.size());
+        let target = Some(pc.wrapping_add(inst.imm));
+
+        let (kind, target, links) = match &inst.code {
+            InstructionCode::Branch(_) => (BranchKind::Conditional, target, false),
+            InstructionCode::Jal if is_link(inst.rd) => (BranchKind::Call, target, false),
+            InstructionCode::Jal => (BranchKind::Jump, target, false),
+            InstructionCode::Jalr => {
+                let kind = match (is_link(inst.rd), is_link(inst.rs1)) {
+                    (false, true) => BranchKind::Return,
+                    (true, true) if inst.rd != inst.rs1 => BranchKind::Return,
+                    (true, _) => BranchKind::Call,
+                    (false, false) => BranchKind::Indirect,
+                };
+                let links = kind == BranchKind::Return && is_link(inst.rd);
+                (kind, None, links)
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            pc,
+            kind,
+            target,
+            fallthrough,
+            links,
+        })
+    }
+}
+
+/// Guesses the next pc of a control transfer before it executes.
+///
+/// `predict` is called once for each branch and jump, then `update` with where
+/// it really went, so a predictor only ever sees retired control flow.
+pub trait BranchPredictor {
+    fn name(&self) -> String;
+
+    fn predict(&mut self, branch: &Branch) -> u64;
+
+    fn update(&mut self, branch: &Branch, next_pc: u64);
+}
+
+/// Next pc for a conditional branch predicted `taken`, or for a direct jump.
+/// Indirect jumps have no target to guess without a BTB and fall through.
+fn direction(branch: &Branch, taken: bool) -> u64 {
+    match (branch.kind, branch.target) {
+        (BranchKind::Conditional, Some(target)) if taken => target,
+        (BranchKind::Jump | BranchKind::Call, Some(target)) => target,
+        _ => branch.fallthrough,
+    }
+}
+
+/// Every conditional branch falls through.
+pub struct StaticNotTaken;
+
+impl BranchPredictor for StaticNotTaken {
+    fn name(&self) -> String {
+        "static not-taken".to_string()
+    }
+
+    fn predict(&mut self, branch: &Branch) -> u64 {
+        direction(branch, false)
+    }
+
+    fn update(&mut self, _branch: &Branch, _next_pc: u64) {}
+}
+
+/// Backward taken, forward not taken: loops close, skips fall through.
+pub struct Btfn;
+
+impl BranchPredictor for Btfn {
+    fn name(&self) -> String {
+        "btfn".to_string()
+    }
+
+    fn predict(&mut self, branch: &Branch) -> u64 {
+        let backward = branch.target.is_some_and(|target| target < branch.pc);
+        direction(branch, backward)
+    }
+
+    fn update(&mut self, _branch: &Branch, _next_pc: u64) {}
+}
+
+/// Table of 2-bit saturating counters, starting weakly not-taken.
+struct CounterTable {
+    counters: Vec<u8>,
+}
+
+impl CounterTable {
+    fn new(bits: u32) -> Self {
+        Self {
+            counters: vec![1; 1 << bits],
+        }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        key as usize & (self.counters.len() - 1)
+    }
+
+    fn taken(&self, key: u64) -> bool {
+        self.counters[self.index(key)] >= 2
+    }
+
+    fn train(&mut self, key: u64, taken: bool) {
+        let index = self.index(key);
+        let counter = &mut self.counters[index];
+        *counter = if taken {
+            (*counter + 1).min(3)
+        } else {
+            counter.saturating_sub(1)
+        };
+    }
+}
+
+/// 2-bit counters indexed by the branch pc.
+pub struct Bimodal {
+    table: CounterTable,
+}
+
+impl Bimodal {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            table: CounterTable::new(bits),
+        }
+    }
+}
+
+impl Default for Bimodal {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_BITS)
+    }
+}
+
+impl BranchPredictor for Bimodal {
+    fn name(&self) -> String {
+        format!("bimodal ({} entries)", self.table.counters.len())
+    }
+
+    fn predict(&mut self, branch: &Branch) -> u64 {
+        // instructions are at least 2-byte aligned
+        direction(branch, self.table.taken(branch.pc >> 1))
+    }
+
+    fn update(&mut self, branch: &Branch, next_pc: u64) {
+        if branch.kind == BranchKind::Conditional {
+            self.table
+                .train(branch.pc >> 1, next_pc != branch.fallthrough);
+        }
+    }
+}
+
+/// 2-bit counters indexed by the branch pc xor the global history of outcomes.
+pub struct Gshare {
+    table: CounterTable,
+    history: u64,
+    history_bits: u32,
+}
+
+impl Gshare {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            table: CounterTable::new(bits),
+            history: 0,
+            history_bits: bits,
+        }
+    }
+
+    fn key(&self, pc: u64) -> u64 {
+        (pc >> 1) ^ self.history
+    }
+}
+
+impl Default for Gshare {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_BITS)
+    }
+}
+
+impl BranchPredictor for Gshare {
+    fn name(&self) -> String {
+        format!(
+            "gshare ({} entries, {} history bits)",
+            self.table.counters.len(),
+            self.history_bits
+        )
+    }
+
+    fn predict(&mut self, branch: &Branch) -> u64 {
+        direction(branch, self.table.taken(self.key(branch.pc)))
+    }
+
+    fn update(&mut self, branch: &Branch, next_pc: u64) {
+        if branch.kind == BranchKind::Conditional {
+            let taken = next_pc != branch.fallthrough;
+            self.table.train(self.key(branch.pc), taken);
+            let mask = (1 << self.history_bits) - 1;
+            self.history = ((self.history << 1) | taken as u64) & mask;
+        }
+    }
+}
+
+/// Return-address stack in front of another predictor, which keeps the rest.
+/// When full, the oldest return address is forgotten.
+pub struct ReturnAddressStack {
+    stack: Vec<u64>,
+    depth: usize,
+    inner: Box<dyn BranchPredictor>,
+}
+
+impl ReturnAddressStack {
+    pub fn new(depth: usize, inner: Box<dyn BranchPredictor>) -> Self {
+        Self {
+            stack: vec![],
+            depth,
+            inner,
+        }
+    }
+
+    fn push(&mut self, address: u64) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.stack.len() == self.depth {
+            self.stack.remove(0);
+        }
+        self.stack.push(address);
+    }
+}
+
+impl BranchPredictor for ReturnAddressStack {
+    fn name(&self) -> String {
+        format!("{} + ras ({} entries)", self.inner.name(), self.depth)
+    }
+
+    fn predict(&mut self, branch: &Branch) -> u64 {
+        match (branch.kind, self.stack.last()) {
+            (BranchKind::Return, Some(address)) => *address,
+            _ => self.inner.predict(branch),
+        }
+    }
+
+    fn update(&mut self, branch: &Branch, next_pc: u64) {
+        match branch.kind {
+            BranchKind::Call => self.push(branch.fallthrough),
+            BranchKind::Return => {
+                self.stack.pop();
+                if branch.links {
+                    self.push(branch.fallthrough);
+                }
+            }
+            _ => {}
+        }
+        self.inner.update(branch, next_pc);
+    }
+}
+
+/// Builds a predictor from its command line name: `not-taken`, `btfn`, `bimodal`
+/// or `gshare`, optionally followed by `+ras`.
+pub fn from_name(name: &str) -> Result<Box<dyn BranchPredictor>> {
+    let (base, ras) = match name.strip_suffix("+ras") {
+        Some(base) => (base, true),
+        None => (name, false),
+    };
+
+    let predictor: Box<dyn BranchPredictor> = match base {
+        "not-taken" => Box::new(StaticNotTaken),
+        "btfn" => Box::new(Btfn),
+        "bimodal" => Box::new(Bimodal::default()),
+        "gshare" => Box::new(Gshare::default()),
+        _ => bail!("unknown branch predictor: {}", name),
+    };
+
+    Ok(if ras {
+        Box::new(ReturnAddressStack::new(DEFAULT_RAS_DEPTH, predictor))
+    } else {
+        predictor
+    })
+}
+
+/// Outcomes of a predictor over one run.
+#[derive(Default)]
+pub struct BranchStats {
+    pub instructions: u64,
+    pub branches: u64,
+    pub mispredictions: u64,
+    // pc -> (executed, mispredicted)
+    pub per_pc: HashMap<u64, (u64, u64)>,
+}
+
+impl BranchStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, pc: u64, mispredicted: bool) {
+        let entry = self.per_pc.entry(pc).or_default();
+        entry.0 += 1;
+        self.branches += 1;
+        if mispredicted {
+            entry.1 += 1;
+            self.mispredictions += 1;
+        }
+    }
+
+    /// Fraction of control transfers predicted correctly; 1 when there were none.
+    pub fn accuracy(&self) -> f64 {
+        if self.branches == 0 {
+            1.0
+        } else {
+            1.0 - self.mispredictions as f64 / self.branches as f64
+        }
+    }
+
+    /// Mispredictions per thousand retired instructions.
+    pub fn mpki(&self) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            self.mispredictions as f64 * 1000.0 / self.instructions as f64
+        }
+    }
+
+    /// Up to `n` pcs with the most mispredictions, as (pc, executed, mispredicted).
+    pub fn worst(&self, n: usize) -> Vec<(u64, u64, u64)> {
+        let mut worst: Vec<_> = self
+            .per_pc
+            .iter()
+            .filter(|(_, (_, missed))| *missed > 0)
+            .map(|(pc, (executed, missed))| (*pc, *executed, *missed))
+            .collect();
+        worst.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        worst.truncate(n);
+        worst
+    }
+}
+
+/// A predictor attached to a processor, with what it got right so far.
+pub struct BranchProfile {
+    predictor: Box<dyn BranchPredictor>,
+    stats: BranchStats,
+}
+
+impl BranchProfile {
+    pub fn new(predictor: Box<dyn BranchPredictor>) -> Self {
+        Self {
+            predictor,
+            stats: BranchStats::new(),
+        }
+    }
+
+    /// Counts an instruction retired at `pc`, predicting it first if it is a branch or jump.
+    pub fn retire(&mut self, inst: &Instruction, pc: u64, next_pc: u64) {
+        self.stats.instructions += 1;
+
+        if let Some(branch) = Branch::new(inst, pc) {
+            let predicted = self.predictor.predict(&branch);
+            self.predictor.update(&branch, next_pc);
+            self.stats.record(pc, predicted != next_pc);
+        }
+    }
+
+    pub fn predictor(&self) -> &dyn BranchPredictor {
+        self.predictor.as_ref()
+    }
+
+    pub fn stats(&self) -> &BranchStats {
+        &self.stats
+    }
+}
+
+impl Display for BranchProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stats = &self.stats;
+        writeln!(f, "predictor      : {}", self.predictor.name())?;
+        writeln!(f, "branches       : {}", stats.branches)?;
+        writeln!(f, "mispredictions : {}", stats.mispredictions)?;
+        writeln!(f, "accuracy       : {:.2}%", stats.accuracy() * 100.0)?;
+        write!(f, "mpki           : {:.3}", stats.mpki())?;
+        for (pc, executed, missed) in stats.worst(5) {
+            write!(
+                f,
+                "\n  {:#010x} : {} / {} mispredicted",
+                pc, missed, executed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::{decoder::decode, xlen::Xlen};
+
+    use super::{
+        from_name, Bimodal, Branch, BranchKind, BranchProfile, Btfn, Gshare, StaticNotTaken,
+    };
+
+    // bne a0, zero, -8 at 0x108: the back edge of a loop starting at 0x100
+    const LOOP: u32 = 0xfe051ce3;
+
+    fn run_loop(profile: &mut BranchProfile, trips: u64, rounds: u64) {
+        let inst = decode(LOOP, &Xlen::X32).ok().unwrap();
+        for _ in 0..rounds {
+            for trip in 1..=trips {
+                let next_pc = if trip < trips { 0x100 } else { 0x10C };
+                profile.retire(&inst, 0x108, next_pc);
+            }
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let branch = |raw| Branch::new(&decode(raw, &Xlen::X32).ok().unwrap(), 0x100);
+        let loop_edge = branch(LOOP).unwrap();
+        assert_eq!(loop_edge.kind, BranchKind::Conditional);
+        assert_eq!(loop_edge.target, Some(0xF8));
+        assert_eq!(loop_edge.fallthrough, 0x104);
+        // jal ra, 16 / jal zero, 16 / jalr zero, 0(ra) / jalr zero, 0(a0)
+        assert_eq!(branch(0x010000ef).unwrap().kind, BranchKind::Call);
+        assert_eq!(branch(0x0100006f).unwrap().kind, BranchKind::Jump);
+        assert_eq!(branch(0x00008067).unwrap().kind, BranchKind::Return);
+        assert_eq!(branch(0x00050067).unwrap().kind, BranchKind::Indirect);
+        // addi a0, zero, 1
+        assert!(branch(0x00100513).is_none());
+    }
+
+    #[test]
+    fn test_predictors() {
+        let mut not_taken = BranchProfile::new(Box::new(StaticNotTaken));
+        run_loop(&mut not_taken, 10, 10);
+        assert_eq!(not_taken.stats().branches, 100);
+        assert_eq!(not_taken.stats().mispredictions, 90);
+
+        let mut btfn = BranchProfile::new(Box::new(Btfn));
+        run_loop(&mut btfn, 10, 10);
+        assert_eq!(btfn.stats().mispredictions, 10);
+
+        // weakly not-taken, then strongly taken: two to warm up, one per exit
+        let mut bimodal = BranchProfile::new(Box::new(Bimodal::default()));
+        run_loop(&mut bimodal, 10, 10);
+        assert_eq!(bimodal.stats().mispredictions, 11);
+
+        // a short loop is learnt exit and all once the history covers a round
+        let mut gshare = BranchProfile::new(Box::new(Gshare::default()));
+        run_loop(&mut gshare, 4, 200);
+        let mut bimodal = BranchProfile::new(Box::new(Bimodal::default()));
+        run_loop(&mut bimodal, 4, 200);
+        assert!(gshare.stats().mispredictions < bimodal.stats().mispredictions / 4);
+
+        let stats = btfn.stats();
+        assert_eq!(stats.accuracy(), 0.9);
+        assert_eq!(stats.worst(3), [(0x108, 100, 10)]);
+    }
+
+    #[test]
+    fn test_return_address_stack() {
+        // jal ra, 0x100 at 0x0; jalr zero, 0(ra) at 0x100, twice over
+        let call = decode(0x100000ef, &Xlen::X32).ok().unwrap();
+        let ret = decode(0x00008067, &Xlen::X32).ok().unwrap();
+
+        let mut plain = BranchProfile::new(from_name("bimodal").unwrap());
+        let mut ras = BranchProfile::new(from_name("bimodal+ras").unwrap());
+        for profile in [&mut plain, &mut ras] {
+            for _ in 0..2 {
+                profile.retire(&call, 0x0, 0x100);
+                profile.retire(&ret, 0x100, 0x4);
+            }
+        }
+        assert_eq!(plain.stats().mispredictions, 2);
+        assert_eq!(ras.stats().mispredictions, 0);
+        assert!(from_name("perceptron").is_err());
+    }
+}
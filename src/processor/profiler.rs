@@ -0,0 +1,228 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+
+use crate::elf::SymbolTable;
+
+use super::{
+    decoder::instruction::Instruction,
+    predictor::{Branch, BranchKind},
+};
+
+/// What one retired instruction adds to its call path.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ProfileWeight {
+    #[default]
+    Instructions,
+    // cycles of the pipeline model, one per instruction when it is off
+    Cycles,
+}
+
+impl FromStr for ProfileWeight {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "instructions" => Ok(ProfileWeight::Instructions),
+            "cycles" => Ok(ProfileWeight::Cycles),
+            _ => bail!(
+                "profile weight must be instructions or cycles, but get: {}",
+                s
+            ),
+        }
+    }
+}
+
+/// One call path: the function entered last and how it was reached.
+struct Frame {
+    // entry address of the function
+    address: u64,
+    parent: Option<usize>,
+    children: HashMap<u64, usize>,
+    // weight retired in this function itself, not its callees
+    weight: u64,
+}
+
+/// Shadow call stack attributing retired instructions to call paths.
+///
+/// Calls and returns are recognized by their link registers like the return-address
+/// stack of the branch predictors, so code that unwinds without returning (longjmp,
+/// tail calls through a plain jump) is attributed to the path it left behind.
+pub struct CallProfiler {
+    weight: ProfileWeight,
+    // call paths as a tree, the root being where profiling started
+    frames: Vec<Frame>,
+    current: usize,
+    // pcs of the calls leading to the current frame, outermost first
+    call_sites: Vec<u64>,
+}
+
+impl CallProfiler {
+    pub fn new(weight: ProfileWeight) -> Self {
+        Self {
+            weight,
+            frames: vec![],
+            current: 0,
+            call_sites: vec![],
+        }
+    }
+
+    pub fn weight(&self) -> ProfileWeight {
+        self.weight
+    }
+
+    /// Attributes `inst` retired at `pc`, which took `cycles` on the timing model,
+    /// then follows it into a callee or back to the caller.
+    pub fn retire(&mut self, inst: &Instruction, pc: u64, next_pc: u64, cycles: u64) {
+        if self.frames.is_empty() {
+            self.frames.push(Frame {
+                address: pc,
+                parent: None,
+                children: HashMap::new(),
+                weight: 0,
+            });
+        }
+
+        self.frames[self.current].weight += match self.weight {
+            ProfileWeight::Instructions => 1,
+            ProfileWeight::Cycles => cycles,
+        };
+
+        let Some(branch) = Branch::new(inst, pc) else {
+            return;
+        };
+        match branch.kind {
+            BranchKind::Call => {
+                self.call_sites.push(pc);
+                self.enter(next_pc);
+            }
+            BranchKind::Return => {
+                // a return past where profiling started keeps the root
+                if let Some(parent) = self.frames[self.current].parent {
+                    self.current = parent;
+                    self.call_sites.pop();
+                }
+                if branch.links {
+                    self.call_sites.push(pc);
+                    self.enter(next_pc);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn enter(&mut self, address: u64) {
+        if let Some(child) = self.frames[self.current].children.get(&address) {
+            self.current = *child;
+            return;
+        }
+
+        let child = self.frames.len();
+        self.frames.push(Frame {
+            address,
+            parent: Some(self.current),
+            children: HashMap::new(),
+            weight: 0,
+        });
+        self.frames[self.current].children.insert(address, child);
+        self.current = child;
+    }
+
+    /// Entry addresses of the functions on the shadow stack, outermost first.
+    pub fn backtrace(&self) -> Vec<u64> {
+        let mut addresses = vec![];
+        let mut frame = (!self.frames.is_empty()).then_some(self.current);
+        while let Some(index) = frame {
+            addresses.push(self.frames[index].address);
+            frame = self.frames[index].parent;
+        }
+        addresses.reverse();
+        addresses
+    }
+
+    /// Where each function on the shadow stack was called from, outermost first.
+    pub fn call_sites(&self) -> &[u64] {
+        &self.call_sites
+    }
+
+    /// Total weight attributed so far.
+    pub fn total(&self) -> u64 {
+        self.frames.iter().map(|frame| frame.weight).sum()
+    }
+
+    /// Folded stacks, one `caller;callee weight` line per call path, as read by
+    /// `flamegraph.pl` and `inferno-flamegraph`. Functions are named from `symbols`.
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let mut lines = vec![];
+        for (index, frame) in self.frames.iter().enumerate() {
+            if frame.weight == 0 {
+                continue;
+            }
+
+            let mut names = vec![];
+            let mut path = Some(index);
+            while let Some(index) = path {
+                names.push(symbols.describe(self.frames[index].address));
+                path = self.frames[index].parent;
+            }
+            names.reverse();
+            lines.push(format!("{} {}", names.join(";"), frame.weight));
+        }
+
+        lines.sort();
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        elf::{Symbol, SymbolTable},
+        processor::{decoder::decode, xlen::Xlen},
+    };
+
+    use super::{CallProfiler, ProfileWeight};
+
+    #[test]
+    fn test_call_profiler() {
+        let inst = |raw| decode(raw, &Xlen::X32).ok().unwrap();
+        // main: addi; jal ra, f; addi; halt / f: addi; ret
+        let (nop, call, ret) = (inst(0x00000013), inst(0x010000ef), inst(0x00008067));
+        let mut profiler = CallProfiler::new(ProfileWeight::Instructions);
+        profiler.retire(&nop, 0x0, 0x4, 1);
+        profiler.retire(&call, 0x4, 0x14, 1);
+        assert_eq!(profiler.backtrace(), [0x0, 0x14]);
+        assert_eq!(profiler.call_sites(), [0x4]);
+        profiler.retire(&nop, 0x14, 0x18, 1);
+        profiler.retire(&ret, 0x18, 0x8, 1);
+        assert_eq!(profiler.backtrace(), [0x0]);
+        assert!(profiler.call_sites().is_empty());
+        profiler.retire(&nop, 0x8, 0xC, 1);
+        // a return from the root stays there
+        profiler.retire(&ret, 0xC, 0x0, 1);
+        assert_eq!(profiler.total(), 6);
+
+        let symbols = SymbolTable::new(vec![
+            Symbol {
+                name: "main".into(),
+                address: 0x0,
+                size: 0x10,
+            },
+            Symbol {
+                name: "f".into(),
+                address: 0x14,
+                size: 0x8,
+            },
+        ]);
+        assert_eq!(profiler.folded(&symbols), "main 4\nmain;f 2\n");
+        assert_eq!(
+            profiler.folded(&SymbolTable::default()),
+            "0x00000000 4\n0x00000000;0x00000014 2\n"
+        );
+
+        let mut cycles = CallProfiler::new(ProfileWeight::Cycles);
+        cycles.retire(&nop, 0x0, 0x4, 5);
+        cycles.retire(&nop, 0x4, 0x8, 3);
+        assert_eq!(cycles.total(), 8);
+    }
+}
@@ -2,7 +2,9 @@ use std::fmt::Display;
 
 use anyhow::Result;
 
-use super::{decoder::instruction::RiscvInstruction, ProcessorError, ProcessorErrorTrait};
+use crate::snapshot::{SnapshotReader, SnapshotWriter};
+
+use super::{decoder::instruction::RiscvInstruction, ProcessorError};
 
 pub enum RegisterAlias {
     Zero,
@@ -122,19 +124,154 @@ impl TryFrom<u8> for RegisterAlias {
             Ok(alias)
         } else {
             let error_type = RegisterErrorType::AddressOutOfBounds;
-            Err(RegisterError::new(error_type))
+            Err(ProcessorError::from(error_type))
         }
     }
 }
 
+impl From<RegisterAlias> for u8 {
+    fn from(alias: RegisterAlias) -> Self {
+        alias as u8
+    }
+}
+
 impl Display for RegisterAlias {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.assembly())
     }
 }
 
+pub enum FloatRegisterAlias {
+    FT0,
+    FT1,
+    FT2,
+    FT3,
+    FT4,
+    FT5,
+    FT6,
+    FT7,
+    FS0,
+    FS1,
+    FA0,
+    FA1,
+    FA2,
+    FA3,
+    FA4,
+    FA5,
+    FA6,
+    FA7,
+    FS2,
+    FS3,
+    FS4,
+    FS5,
+    FS6,
+    FS7,
+    FS8,
+    FS9,
+    FS10,
+    FS11,
+    FT8,
+    FT9,
+    FT10,
+    FT11,
+}
+
+impl RiscvInstruction for FloatRegisterAlias {
+    fn assembly(&self) -> String {
+        match self {
+            FloatRegisterAlias::FT0 => "ft0",
+            FloatRegisterAlias::FT1 => "ft1",
+            FloatRegisterAlias::FT2 => "ft2",
+            FloatRegisterAlias::FT3 => "ft3",
+            FloatRegisterAlias::FT4 => "ft4",
+            FloatRegisterAlias::FT5 => "ft5",
+            FloatRegisterAlias::FT6 => "ft6",
+            FloatRegisterAlias::FT7 => "ft7",
+            FloatRegisterAlias::FS0 => "fs0",
+            FloatRegisterAlias::FS1 => "fs1",
+            FloatRegisterAlias::FA0 => "fa0",
+            FloatRegisterAlias::FA1 => "fa1",
+            FloatRegisterAlias::FA2 => "fa2",
+            FloatRegisterAlias::FA3 => "fa3",
+            FloatRegisterAlias::FA4 => "fa4",
+            FloatRegisterAlias::FA5 => "fa5",
+            FloatRegisterAlias::FA6 => "fa6",
+            FloatRegisterAlias::FA7 => "fa7",
+            FloatRegisterAlias::FS2 => "fs2",
+            FloatRegisterAlias::FS3 => "fs3",
+            FloatRegisterAlias::FS4 => "fs4",
+            FloatRegisterAlias::FS5 => "fs5",
+            FloatRegisterAlias::FS6 => "fs6",
+            FloatRegisterAlias::FS7 => "fs7",
+            FloatRegisterAlias::FS8 => "fs8",
+            FloatRegisterAlias::FS9 => "fs9",
+            FloatRegisterAlias::FS10 => "fs10",
+            FloatRegisterAlias::FS11 => "fs11",
+            FloatRegisterAlias::FT8 => "ft8",
+            FloatRegisterAlias::FT9 => "ft9",
+            FloatRegisterAlias::FT10 => "ft10",
+            FloatRegisterAlias::FT11 => "ft11",
+        }
+        .into()
+    }
+}
+
+impl TryFrom<u8> for FloatRegisterAlias {
+    type Error = ProcessorError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value < 32 {
+            let alias = match value {
+                0 => FloatRegisterAlias::FT0,
+                1 => FloatRegisterAlias::FT1,
+                2 => FloatRegisterAlias::FT2,
+                3 => FloatRegisterAlias::FT3,
+                4 => FloatRegisterAlias::FT4,
+                5 => FloatRegisterAlias::FT5,
+                6 => FloatRegisterAlias::FT6,
+                7 => FloatRegisterAlias::FT7,
+                8 => FloatRegisterAlias::FS0,
+                9 => FloatRegisterAlias::FS1,
+                10 => FloatRegisterAlias::FA0,
+                11 => FloatRegisterAlias::FA1,
+                12 => FloatRegisterAlias::FA2,
+                13 => FloatRegisterAlias::FA3,
+                14 => FloatRegisterAlias::FA4,
+                15 => FloatRegisterAlias::FA5,
+                16 => FloatRegisterAlias::FA6,
+                17 => FloatRegisterAlias::FA7,
+                18 => FloatRegisterAlias::FS2,
+                19 => FloatRegisterAlias::FS3,
+                20 => FloatRegisterAlias::FS4,
+                21 => FloatRegisterAlias::FS5,
+                22 => FloatRegisterAlias::FS6,
+                23 => FloatRegisterAlias::FS7,
+                24 => FloatRegisterAlias::FS8,
+                25 => FloatRegisterAlias::FS9,
+                26 => FloatRegisterAlias::FS10,
+                27 => FloatRegisterAlias::FS11,
+                28 => FloatRegisterAlias::FT8,
+                29 => FloatRegisterAlias::FT9,
+                30 => FloatRegisterAlias::FT10,
+                31 => FloatRegisterAlias::FT11,
+                _ => unreachable!(),
+            };
+            Ok(alias)
+        } else {
+            let error_type = RegisterErrorType::AddressOutOfBounds;
+            Err(ProcessorError::from(error_type))
+        }
+    }
+}
+
+impl Display for FloatRegisterAlias {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.assembly())
+    }
+}
+
 pub struct Register {
-    mem: [u32; 32],
+    mem: [u64; 32],
 }
 
 impl Register {
@@ -142,16 +279,28 @@ impl Register {
         Self { mem: [0; 32] }
     }
 
-    pub fn read(&self, address: u8) -> Result<u32, ProcessorError> {
+    pub fn save(&self, writer: &mut SnapshotWriter) {
+        self.mem.iter().for_each(|value| writer.put_u64(*value));
+    }
+
+    pub fn restore(reader: &mut SnapshotReader) -> Result<Self> {
+        let mut mem = [0; 32];
+        for value in mem.iter_mut() {
+            *value = reader.get_u64()?;
+        }
+        Ok(Self { mem })
+    }
+
+    pub fn read(&self, address: u8) -> Result<u64, ProcessorError> {
         if address < 32 {
             Ok(self.mem[address as usize])
         } else {
             let error_type = RegisterErrorType::AddressOutOfBounds;
-            Err(RegisterError::new(error_type))
+            Err(ProcessorError::from(error_type))
         }
     }
 
-    pub fn write(&mut self, address: u8, value: u32) -> Result<(), ProcessorError> {
+    pub fn write(&mut self, address: u8, value: u64) -> Result<(), ProcessorError> {
         if address < 32 {
             if 0 < address {
                 self.mem[address as usize] = value;
@@ -159,7 +308,7 @@ impl Register {
             Ok(())
         } else {
             let error_type = RegisterErrorType::AddressOutOfBounds;
-            Err(RegisterError::new(error_type))
+            Err(ProcessorError::from(error_type))
         }
     }
 
@@ -172,39 +321,112 @@ impl Register {
     }
 }
 
-pub enum RegisterErrorType {
-    AddressOutOfBounds,
+impl Default for Register {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl Display for RegisterErrorType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::AddressOutOfBounds => {
-                write!(f, "violate address bounds by memory access")
+/// Puts a single-precision value into the lower half of a 64-bit register
+/// with the upper half all ones.
+pub fn nan_box(value: u32) -> u64 {
+    0xFFFF_FFFF_0000_0000 | value as u64
+}
+
+/// Takes a single-precision value out of a 64-bit register.
+/// An improperly boxed value reads as the canonical NaN.
+pub fn nan_unbox(raw: u64) -> u32 {
+    if raw >> 32 == 0xFFFF_FFFF {
+        raw as u32
+    } else {
+        0x7FC0_0000
+    }
+}
+
+pub struct FloatRegister {
+    mem: [u64; 32],
+}
+
+impl FloatRegister {
+    pub fn new() -> Self {
+        Self { mem: [0; 32] }
+    }
+
+    pub fn save(&self, writer: &mut SnapshotWriter) {
+        self.mem.iter().for_each(|value| writer.put_u64(*value));
+    }
+
+    pub fn restore(reader: &mut SnapshotReader) -> Result<Self> {
+        let mut mem = [0; 32];
+        for value in mem.iter_mut() {
+            *value = reader.get_u64()?;
+        }
+        Ok(Self { mem })
+    }
+
+    pub fn read(&self, address: u8) -> Result<u64, ProcessorError> {
+        if address < 32 {
+            Ok(self.mem[address as usize])
+        } else {
+            let error_type = RegisterErrorType::AddressOutOfBounds;
+            Err(ProcessorError::from(error_type))
+        }
+    }
+
+    pub fn write(&mut self, address: u8, value: u64) -> Result<(), ProcessorError> {
+        if address < 32 {
+            self.mem[address as usize] = value;
+            Ok(())
+        } else {
+            let error_type = RegisterErrorType::AddressOutOfBounds;
+            Err(ProcessorError::from(error_type))
+        }
+    }
+
+    pub fn logging(&self) -> Result<(), ProcessorError> {
+        for i in 0..32 {
+            let reg = FloatRegisterAlias::try_from(i)?.assembly();
+            let raw = self.read(i)?;
+            if raw >> 32 == 0xFFFF_FFFF {
+                println!(" {:8}: {} (single)", reg, f32::from_bits(raw as u32));
+            } else {
+                println!(" {:8}: {}", reg, f64::from_bits(raw));
             }
         }
+        Ok(())
     }
 }
 
-pub struct RegisterError {
-    error_type: RegisterErrorType,
+impl Default for FloatRegister {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl RegisterError {
-    fn new(error_type: RegisterErrorType) -> Box<Self> {
-        let error = Self { error_type };
-        Box::new(error)
-    }
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RegisterErrorType {
+    AddressOutOfBounds,
 }
 
-impl Display for RegisterError {
+impl Display for RegisterErrorType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.form())
+        match self {
+            Self::AddressOutOfBounds => {
+                write!(f, "violate address bounds by memory access")
+            }
+        }
     }
 }
 
-impl ProcessorErrorTrait for RegisterError {
-    fn form(&self) -> String {
-        format!("register access is failed  - {}", self.error_type)
+#[cfg(test)]
+mod tests {
+    use super::{nan_box, nan_unbox};
+
+    #[test]
+    fn test_nan_boxing() {
+        assert_eq!(nan_box(0x3F800000), 0xFFFFFFFF_3F800000);
+        assert_eq!(nan_unbox(0xFFFFFFFF_3F800000), 0x3F800000);
+        assert_eq!(nan_unbox(0x00000000_3F800000), 0x7FC00000);
+        assert_eq!(nan_unbox(0xFFFFFFFE_3F800000), 0x7FC00000);
     }
 }
@@ -0,0 +1,343 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    str::FromStr,
+};
+
+use anyhow::{bail, Error, Result};
+use serde::Serialize;
+
+use crate::elf::SymbolTable;
+
+use super::decoder::instruction::{Instruction, InstructionCode};
+
+/// How the statistics report is printed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum StatsFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+impl FromStr for StatsFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(StatsFormat::Table),
+            "json" => Ok(StatsFormat::Json),
+            _ => bail!("statistics format must be table or json, but get: {}", s),
+        }
+    }
+}
+
+/// Counts gathered from every retired instruction.
+///
+/// Counters are keyed by the raw encoding and the pc, which are cheap to hash
+/// on every step, and only turned into mnemonics and symbols by `report`.
+#[derive(Default)]
+pub struct ExecutionStats {
+    instructions: u64,
+    // raw encoding -> (mnemonic, count)
+    encodings: HashMap<u32, (String, u64)>,
+    // raw encoding of a conditional branch -> (taken, not taken)
+    branches: HashMap<u32, (u64, u64)>,
+    // access width in bytes -> count
+    loads: BTreeMap<u64, u64>,
+    stores: BTreeMap<u64, u64>,
+    pcs: HashMap<u64, u64>,
+}
+
+impl ExecutionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `inst` retired at `pc`, which continued at `next_pc`.
+    pub fn retire(&mut self, inst: &Instruction, pc: u64, next_pc: u64) {
+        self.instructions += 1;
+        *self.pcs.entry(pc).or_default() += 1;
+
+        let raw = inst.raw();
+        self.encodings
+            .entry(raw)
+            .or_insert_with(|| (inst.mnemonic(), 0))
+            .1 += 1;
+
+        match &inst.code {
+            InstructionCode::Branch(_) => {
+                let counts = self.branches.entry(raw).or_default();
+                if next_pc == pc.wrapping_add(inst.size()) {
+                    counts.1 += 1;
+                } else {
+                    counts.0 += 1;
+                }
+            }
+            InstructionCode::Load(opt) => *self.loads.entry(opt.width()).or_default() += 1,
+            InstructionCode::Store(opt) => *self.stores.entry(opt.width()).or_default() += 1,
+            _ => {}
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Aggregates the counters, naming the `top` hottest pcs and, with `symbols`,
+    /// the hottest functions.
+    pub fn report(&self, symbols: Option<&SymbolTable>, top: usize) -> StatsReport {
+        let mut mix: HashMap<&str, u64> = HashMap::new();
+        for (mnemonic, count) in self.encodings.values() {
+            *mix.entry(mnemonic).or_default() += count;
+        }
+
+        let mut branches: HashMap<&str, (u64, u64)> = HashMap::new();
+        for (raw, (taken, not_taken)) in self.branches.iter() {
+            let counts = branches.entry(&self.encodings[raw].0).or_default();
+            counts.0 += taken;
+            counts.1 += not_taken;
+        }
+
+        let mut hot_pcs: Vec<_> = self.pcs.iter().map(|(pc, count)| (*pc, *count)).collect();
+        hot_pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot_pcs.truncate(top);
+
+        let mut functions: HashMap<&str, u64> = HashMap::new();
+        if let Some(symbols) = symbols {
+            for (pc, count) in self.pcs.iter() {
+                let name = symbols.lookup(*pc).map_or("?", |symbol| &symbol.name);
+                *functions.entry(name).or_default() += count;
+            }
+        }
+
+        StatsReport {
+            instructions: self.instructions,
+            mix: sorted(mix)
+                .into_iter()
+                .map(|(mnemonic, count)| MixEntry { mnemonic, count })
+                .collect(),
+            branches: {
+                let mut branches: Vec<_> = branches
+                    .into_iter()
+                    .map(|(mnemonic, (taken, not_taken))| BranchEntry {
+                        mnemonic: mnemonic.into(),
+                        taken,
+                        not_taken,
+                    })
+                    .collect();
+                branches.sort_by(|a, b| a.mnemonic.cmp(&b.mnemonic));
+                branches
+            },
+            loads: self.loads.clone(),
+            stores: self.stores.clone(),
+            hot_pcs: hot_pcs
+                .into_iter()
+                .map(|(pc, count)| HotPc {
+                    pc,
+                    symbol: symbols.map(|symbols| symbols.describe(pc)),
+                    count,
+                })
+                .collect(),
+            hot_functions: sorted(functions)
+                .into_iter()
+                .take(top)
+                .map(|(name, count)| HotFunction { name, count })
+                .collect(),
+        }
+    }
+}
+
+// by count, most frequent first, then by name
+fn sorted(counts: HashMap<&str, u64>) -> Vec<(String, u64)> {
+    let mut counts: Vec<_> = counts
+        .into_iter()
+        .map(|(name, count)| (name.to_string(), count))
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct MixEntry {
+    pub mnemonic: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct BranchEntry {
+    pub mnemonic: String,
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct HotPc {
+    pub pc: u64,
+    pub symbol: Option<String>,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct HotFunction {
+    pub name: String,
+    pub count: u64,
+}
+
+/// Execution statistics of one run, printed as a table or serialized as JSON.
+#[derive(Serialize, Debug)]
+pub struct StatsReport {
+    pub instructions: u64,
+    pub mix: Vec<MixEntry>,
+    pub branches: Vec<BranchEntry>,
+    // access width in bytes -> count
+    pub loads: BTreeMap<u64, u64>,
+    pub stores: BTreeMap<u64, u64>,
+    pub hot_pcs: Vec<HotPc>,
+    // empty without symbols
+    pub hot_functions: Vec<HotFunction>,
+}
+
+impl StatsReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("statistics always serialize")
+    }
+
+    pub fn format(&self, format: StatsFormat) -> String {
+        match format {
+            StatsFormat::Table => self.to_string(),
+            StatsFormat::Json => self.to_json(),
+        }
+    }
+}
+
+impl Display for StatsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = |count: u64| {
+            if self.instructions == 0 {
+                0.0
+            } else {
+                count as f64 * 100.0 / self.instructions as f64
+            }
+        };
+
+        writeln!(f, "instructions retired: {}", self.instructions)?;
+
+        writeln!(f, "\ninstruction mix")?;
+        for entry in self.mix.iter() {
+            writeln!(
+                f,
+                "  {:<12} {:>10} {:>6.2}%",
+                entry.mnemonic,
+                entry.count,
+                percent(entry.count)
+            )?;
+        }
+
+        writeln!(f, "\nbranches           taken  not taken  taken%")?;
+        for entry in self.branches.iter() {
+            let total = entry.taken + entry.not_taken;
+            writeln!(
+                f,
+                "  {:<12} {:>10} {:>10} {:>6.2}%",
+                entry.mnemonic,
+                entry.taken,
+                entry.not_taken,
+                entry.taken as f64 * 100.0 / total as f64
+            )?;
+        }
+
+        writeln!(f, "\nmemory accesses     loads     stores")?;
+        let widths: Vec<_> = self.loads.keys().chain(self.stores.keys()).collect();
+        for width in (1..=8).filter(|width| widths.contains(&width)) {
+            writeln!(
+                f,
+                "  {:<12} {:>10} {:>10}",
+                format!("{} byte{}", width, if width == 1 { "" } else { "s" }),
+                self.loads.get(&width).unwrap_or(&0),
+                self.stores.get(&width).unwrap_or(&0)
+            )?;
+        }
+
+        write!(f, "\nhottest pcs")?;
+        for hot in self.hot_pcs.iter() {
+            write!(f, "\n  {:#010x} {:>10}", hot.pc, hot.count)?;
+            if let Some(symbol) = &hot.symbol {
+                write!(f, "  {}", symbol)?;
+            }
+        }
+
+        if !self.hot_functions.is_empty() {
+            write!(f, "\n\nhottest functions")?;
+            for hot in self.hot_functions.iter() {
+                write!(
+                    f,
+                    "\n  {:<24} {:>10} {:>6.2}%",
+                    hot.name,
+                    hot.count,
+                    percent(hot.count)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        elf::SymbolTable,
+        processor::{decoder::decode, xlen::Xlen},
+    };
+
+    use super::{BranchEntry, ExecutionStats, MixEntry};
+
+    #[test]
+    fn test_execution_stats() {
+        let mut stats = ExecutionStats::new();
+        let mut retire = |raw, pc, next_pc| {
+            let inst = decode(raw, &Xlen::X32).ok().unwrap();
+            stats.retire(&inst, pc, next_pc);
+        };
+        // L: lw a1, 0(a0); addi a0, a0, 4; sb a1, 0(a0); bne a0, a2, L; three trips
+        for trip in 0..3 {
+            retire(0x00052583, 0x0, 0x4);
+            retire(0x00450513, 0x4, 0x8);
+            retire(0x00b50023, 0x8, 0xC);
+            retire(0xfec51ae3, 0xC, if trip < 2 { 0x0 } else { 0x10 });
+        }
+        // c.addi a0, 1 counts as addi
+        retire(0x0505, 0x10, 0x12);
+
+        let report = stats.report(None, 2);
+        assert_eq!(report.instructions, 13);
+        assert_eq!(
+            report.mix[0],
+            MixEntry {
+                mnemonic: "addi".into(),
+                count: 4
+            }
+        );
+        assert_eq!(report.mix.len(), 4);
+        assert_eq!(
+            report.branches,
+            [BranchEntry {
+                mnemonic: "bne".into(),
+                taken: 2,
+                not_taken: 1
+            }]
+        );
+        assert_eq!(report.loads.get(&4), Some(&3));
+        assert_eq!(report.stores.get(&1), Some(&3));
+        assert_eq!(report.hot_pcs.len(), 2);
+        assert_eq!(report.hot_pcs[0].pc, 0x0);
+        assert!(report.hot_functions.is_empty());
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["instructions"], 13);
+        assert_eq!(json["loads"]["4"], 3);
+
+        let report = stats.report(Some(&SymbolTable::default()), 2);
+        assert_eq!(report.hot_functions[0].name, "?");
+        assert_eq!(report.hot_functions[0].count, 13);
+    }
+}
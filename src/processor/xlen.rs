@@ -0,0 +1,41 @@
+use std::fmt::Display;
+
+/// Width of the integer registers, chosen per processor instance.
+/// Registers always hold 64 bits; in RV32 mode the upper half stays zero.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Xlen {
+    #[default]
+    X32,
+    X64,
+}
+
+impl Xlen {
+    pub fn bits(&self) -> u32 {
+        match self {
+            Xlen::X32 => 32,
+            Xlen::X64 => 64,
+        }
+    }
+
+    /// Cuts a value down to the register width.
+    pub fn truncate(&self, value: u64) -> u64 {
+        match self {
+            Xlen::X32 => value % (1 << 32),
+            Xlen::X64 => value,
+        }
+    }
+
+    /// Reads a register value as a signed integer of the register width.
+    pub fn signed(&self, value: u64) -> i64 {
+        match self {
+            Xlen::X32 => value as u32 as i32 as i64,
+            Xlen::X64 => value as i64,
+        }
+    }
+}
+
+impl Display for Xlen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rv{}", self.bits())
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{bail, ensure, Result};
+
+/// Leading bytes of every snapshot file.
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"KRGSNAP\0";
+
+/// Bumped whenever the layout changes; older snapshots are rejected, not guessed at.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Little-endian writer for the snapshot format.
+pub struct SnapshotWriter {
+    buf: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        let mut writer = Self { buf: vec![] };
+        writer.put_bytes(&SNAPSHOT_MAGIC);
+        writer.put_u32(SNAPSHOT_VERSION);
+        writer
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.put_u8(value as u8);
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for SnapshotWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reader matching [`SnapshotWriter`], failing on truncated or foreign data.
+pub struct SnapshotReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Result<Self> {
+        let mut reader = Self { buf };
+        ensure!(
+            reader.get_bytes(SNAPSHOT_MAGIC.len())? == SNAPSHOT_MAGIC,
+            "not a snapshot file"
+        );
+
+        let version = reader.get_u32()?;
+        ensure!(
+            version == SNAPSHOT_VERSION,
+            "snapshot version {} is not supported, expected {}",
+            version,
+            SNAPSHOT_VERSION
+        );
+        Ok(reader)
+    }
+
+    pub fn get_bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < n {
+            bail!("snapshot is truncated");
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8> {
+        Ok(self.get_bytes(1)?[0])
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool> {
+        match self.get_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => bail!("invalid boolean in snapshot: {}", value),
+        }
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32> {
+        let bytes = self.get_bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into()?))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64> {
+        let bytes = self.get_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into()?))
+    }
+
+    /// Checks that the whole snapshot has been consumed.
+    pub fn finish(self) -> Result<()> {
+        ensure!(self.buf.is_empty(), "snapshot has trailing data");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SnapshotReader, SnapshotWriter, SNAPSHOT_MAGIC};
+
+    #[test]
+    fn test_snapshot_format() {
+        let mut writer = SnapshotWriter::new();
+        writer.put_u8(0xAB);
+        writer.put_bool(true);
+        writer.put_u32(0x1234_5678);
+        writer.put_u64(u64::MAX - 1);
+        let buf = writer.finish();
+
+        let mut reader = SnapshotReader::new(&buf).unwrap();
+        assert_eq!(reader.get_u8().unwrap(), 0xAB);
+        assert!(reader.get_bool().unwrap());
+        assert_eq!(reader.get_u32().unwrap(), 0x1234_5678);
+        assert_eq!(reader.get_u64().unwrap(), u64::MAX - 1);
+        reader.finish().unwrap();
+
+        // wrong magic, other version, truncated data
+        assert!(SnapshotReader::new(b"KRGSNAQ\0\x01\0\0\0").is_err());
+        let mut other = SNAPSHOT_MAGIC.to_vec();
+        other.extend_from_slice(&2u32.to_le_bytes());
+        assert!(SnapshotReader::new(&other).is_err());
+        let mut reader = SnapshotReader::new(&buf[..buf.len() - 1]).unwrap();
+        reader.get_bytes(6).unwrap();
+        assert!(reader.get_u64().is_err());
+    }
+}